@@ -1,6 +1,6 @@
 use std::{
     fs::File,
-    io::{self, BufRead, BufReader},
+    io::{self, BufRead, BufReader, Write},
     path::Path,
 };
 
@@ -9,17 +9,12 @@ use md5::{Digest, Md5};
 use crate::serialize::meta::MetaData;
 
 pub fn is_flag_true(data: u8, flag: u8) -> bool {
-    match data & flag {
-        0 => false,
-        _ => true,
-    }
+    !matches!(data & flag, 0)
 }
 pub fn binary_to_u64(binary: &[u8]) -> u64 {
     let mut num: u64 = 0;
-    let mut coef = 1;
-    for i in binary {
-        num += *i as u64 * coef;
-        coef *= 0x100;
+    for (index, i) in binary.iter().enumerate() {
+        num += (*i as u64) << (8 * index);
     }
     num
 }
@@ -41,14 +36,18 @@ pub fn get_checksum(file: File) -> Vec<u8> {
     a.to_vec()
 }
 
-pub fn verify_checksum<T: AsRef<Path>>(metadata: MetaData, file_path: T) -> io::Result<()> {
+pub fn get_bytes_checksum(data: &[u8]) -> Vec<u8> {
+    Md5::digest(data).to_vec()
+}
+
+pub fn verify_checksum<T: AsRef<Path>>(metadata: &MetaData, file_path: T) -> io::Result<()> {
     let file = File::open(&file_path)?;
     let new_checksum = get_checksum(file);
     let old_checksum = metadata.checksum().as_ref().unwrap();
     if new_checksum == *old_checksum {
         Ok(())
     } else {
-        return Err(io::Error::new(
+        Err(io::Error::new(
             io::ErrorKind::InvalidData,
             format!(
                 "Wrong checksum!!!! {}, new checksum: {:x?}, old checksum: {:x?}",
@@ -56,6 +55,123 @@ pub fn verify_checksum<T: AsRef<Path>>(metadata: MetaData, file_path: T) -> io::
                 new_checksum,
                 old_checksum
             ),
-        ));
+        ))
+    }
+}
+
+/// Compare the checksum of restored data with the original checksum in the metadata.
+pub fn compare_checksum(metadata: &MetaData, new_checksum: &[u8]) -> io::Result<()> {
+    let old_checksum = metadata.checksum().as_ref().unwrap();
+    if new_checksum == old_checksum.as_slice() {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Wrong checksum!!!! {}, new checksum: {:x?}, old checksum: {:x?}",
+                metadata.path().to_str().unwrap(),
+                new_checksum,
+                old_checksum
+            ),
+        ))
+    }
+}
+
+/// Writer which calculates the [MD5](md5) checksum of all data written through it.
+pub struct ChecksumWriter<W: Write> {
+    inner: W,
+    hasher: Md5,
+}
+
+impl<W: Write> ChecksumWriter<W> {
+    pub fn new(inner: W) -> Self {
+        ChecksumWriter {
+            inner,
+            hasher: Md5::new(),
+        }
+    }
+
+    /// Returns the checksum of all written data.
+    pub fn finalize(self) -> Vec<u8> {
+        self.hasher.finalize().to_vec()
+    }
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let length = self.inner.write(buf)?;
+        self.hasher.update(&buf[..length]);
+        Ok(length)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Writer which passes only a range of the bytes written through it, and discards the others.
+pub struct RangeWriter<W: Write> {
+    inner: W,
+    skip: u64,
+    remaining: u64,
+}
+
+impl<W: Write> RangeWriter<W> {
+    /// Pass `length` bytes from `start` of the written bytes to the inner writer.
+    pub fn new(inner: W, start: u64, length: u64) -> Self {
+        RangeWriter {
+            inner,
+            skip: start,
+            remaining: length,
+        }
+    }
+}
+
+impl<W: Write> Write for RangeWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let skipped = self.skip.min(buf.len() as u64) as usize;
+        self.skip -= skipped as u64;
+        let passed = self.remaining.min((buf.len() - skipped) as u64) as usize;
+        self.inner.write_all(&buf[skipped..skipped + passed])?;
+        self.remaining -= passed as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Writer which counts the bytes written through it.
+pub struct CountingWriter<W: Write> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: Write> CountingWriter<W> {
+    pub fn new(inner: W) -> Self {
+        CountingWriter { inner, count: 0 }
+    }
+
+    /// Returns the number of bytes written.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Returns the inner writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let length = self.inner.write(buf)?;
+        self.count += length as u64;
+        Ok(length)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
     }
 }
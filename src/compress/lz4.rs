@@ -0,0 +1,169 @@
+//! LZ4 block stream.
+//!
+//! The data is split into blocks of 64 KiB at most, and every block is compressed by itself.
+//! A block is written as its compressed length and its original length, each in 4 bytes of little endian,
+//! followed by the compressed block.
+//! Both sides are writers, so a block is decoded as soon as all of its bytes are written.
+//!
+
+use std::io::{self, Write};
+
+use lz4_flex::block::{compress_into, decompress_into, get_maximum_output_size};
+
+const BLOCK_LENGTH: usize = 64 * 1024;
+const BLOCK_HEADER_LENGTH: usize = 8;
+
+fn invalid_block() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "Invalid LZ4 block.")
+}
+
+/// Compresses everything written to it into LZ4 blocks.
+pub struct Lz4Encoder<W: Write> {
+    writer: W,
+    buffer: Vec<u8>,
+    block: Vec<u8>,
+}
+
+impl<W: Write> Lz4Encoder<W> {
+    pub fn new(writer: W) -> Self {
+        Lz4Encoder {
+            writer,
+            buffer: Vec::with_capacity(BLOCK_LENGTH),
+            block: vec![0u8; get_maximum_output_size(BLOCK_LENGTH)],
+        }
+    }
+
+    /// Write the remaining data as the last block, and returns the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        if !self.buffer.is_empty() {
+            self.write_block()?;
+        }
+        Ok(self.writer)
+    }
+
+    fn write_block(&mut self) -> io::Result<()> {
+        let length = compress_into(&self.buffer, &mut self.block).map_err(|_| invalid_block())?;
+        self.writer.write_all(&(length as u32).to_le_bytes())?;
+        self.writer
+            .write_all(&(self.buffer.len() as u32).to_le_bytes())?;
+        self.writer.write_all(&self.block[..length])?;
+        self.buffer.clear();
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for Lz4Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let length = buf.len().min(BLOCK_LENGTH - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..length]);
+        if self.buffer.len() == BLOCK_LENGTH {
+            self.write_block()?;
+        }
+        Ok(length)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Decompresses LZ4 blocks written to it into the writer.
+pub struct Lz4Decoder<W: Write> {
+    writer: W,
+    buffer: Vec<u8>,
+    block: Vec<u8>,
+}
+
+impl<W: Write> Lz4Decoder<W> {
+    pub fn new(writer: W) -> Self {
+        Lz4Decoder {
+            writer,
+            buffer: Vec::new(),
+            block: vec![0u8; BLOCK_LENGTH],
+        }
+    }
+
+    /// Verify no block is left incomplete, and returns the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        if !self.buffer.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "The LZ4 data ends in the middle of a block.",
+            ));
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    /// Decode every complete block in the buffer.
+    fn write_blocks(&mut self) -> io::Result<()> {
+        let mut start = 0;
+        while self.buffer.len() - start >= BLOCK_HEADER_LENGTH {
+            let header = &self.buffer[start..start + BLOCK_HEADER_LENGTH];
+            let compressed_length = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+            let original_length = u32::from_le_bytes(header[4..].try_into().unwrap()) as usize;
+            if compressed_length > get_maximum_output_size(BLOCK_LENGTH)
+                || original_length > BLOCK_LENGTH
+            {
+                return Err(invalid_block());
+            }
+            let end = start + BLOCK_HEADER_LENGTH + compressed_length;
+            if self.buffer.len() < end {
+                break;
+            }
+            let compressed = &self.buffer[start + BLOCK_HEADER_LENGTH..end];
+            let length = decompress_into(compressed, &mut self.block[..original_length])
+                .map_err(|_| invalid_block())?;
+            if length != original_length {
+                return Err(invalid_block());
+            }
+            self.writer.write_all(&self.block[..length])?;
+            start = end;
+        }
+        self.buffer.drain(..start);
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for Lz4Decoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        self.write_blocks()?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lz4_block_test() {
+        let original: Vec<u8> = (0..BLOCK_LENGTH * 2 + 7).map(|i| (i % 251) as u8).collect();
+        let mut encoder = Lz4Encoder::new(Vec::new());
+        encoder.write_all(&original).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        // Blocks split at any point are decoded as they are completed.
+        let mut decoder = Lz4Decoder::new(Vec::new());
+        for chunk in compressed.chunks(1000) {
+            decoder.write_all(chunk).unwrap();
+        }
+        assert_eq!(decoder.finish().unwrap(), original);
+
+        let mut decoder = Lz4Decoder::new(Vec::new());
+        decoder
+            .write_all(&compressed[..compressed.len() - 1])
+            .unwrap();
+        let error = decoder.finish().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+
+        let mut decoder = Lz4Decoder::new(Vec::new());
+        let error = decoder.write_all(&[0xFF; 16]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
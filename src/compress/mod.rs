@@ -1,87 +1,399 @@
+//! Compression module.
+//!
+//! The data of an entry is compressed and decompressed as a stream between the original file and the serialized file,
+//! so no temporary file is written and the whole data is never held in memory.
+//!
+//! The codec is recorded in the header, so the deserializer picks the right decoder.
+//! The level is not recorded, because it is not needed to decompress.
+//!
+//! LZ4 and XZ are behind the `lz4` and `xz` features.
+//! Their identifiers are always known, so a file compressed with a codec which is not enabled
+//! is rejected with an error naming the feature to enable.
+//!
+
 use std::{
-    ffi::OsStr,
-    fs::{self, File},
-    io::{self, BufReader, BufWriter, Read, Write},
-    path::{Path, PathBuf},
+    fs::File,
+    io::{self, Read, Write},
+    path::Path,
 };
 
 use flate2::{
-    bufread::{ZlibDecoder, ZlibEncoder},
+    write::{ZlibDecoder, ZlibEncoder},
     Compression,
 };
+#[cfg(feature = "xz")]
+use xz2::write::{XzDecoder, XzEncoder};
+
+#[cfg(feature = "lz4")]
+use self::lz4::{Lz4Decoder, Lz4Encoder};
+
+#[cfg(feature = "lz4")]
+mod lz4;
+
+const ZLIB_CODEC: u8 = 0x01;
+const ZSTD_CODEC: u8 = 0x02;
+const LZ4_CODEC: u8 = 0x03;
+const XZ_CODEC: u8 = 0x04;
+const ZLIB_MAX_LEVEL: u32 = 9;
+#[cfg(feature = "xz")]
+const XZ_MAX_LEVEL: u32 = 9;
+#[cfg(feature = "xz")]
+const XZ_DEFAULT_LEVEL: u32 = 6;
+
+/// Extensions of formats which are already compressed, so compressing them again hardly saves anything.
+const INCOMPRESSIBLE_EXTENSIONS: &[&str] = &[
+    "jpg", "jpeg", "png", "gif", "webp", "avif", "heic", "mp3", "m4a", "aac", "ogg", "opus",
+    "flac", "mp4", "m4v", "mkv", "webm", "mov", "zip", "gz", "tgz", "bz2", "xz", "zst", "lz4",
+    "7z", "rar", "jar", "docx", "xlsx", "pptx",
+];
+/// The length of the first block of a file, which is compressed to try.
+const TRIAL_LENGTH: u64 = 64 * 1024;
+/// The compressed first block must be at most this percent of the original to compress the file.
+const MAX_TRIAL_RATIO: u64 = 95;
+
+/// The codec to compress the data of the entries, with its level.
+///
+/// The default is zlib with level 9, which older versions always used.
+///
+/// # Examples
+/// ```
+/// use lusl::{Codec, SerializeOption};
+/// let option = SerializeOption::new().to_compression(Codec::Zstd {
+///     level: -3,
+///     long_distance_matching: true,
+/// });
+/// assert!(option.is_compressed());
+/// assert_eq!(option.compression_codec(), Codec::Zstd { level: -3, long_distance_matching: true });
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Codec {
+    /// zlib with a level from 0 to 9.
+    Zlib { level: u32 },
+    /// Zstandard with a level from the range of [`zstd::compression_level_range`], usually from -131072 to 22.
+    ///
+    /// Negative levels are faster and compress less.
+    /// Long distance matching finds matches far back in the data, which helps with large files.
+    Zstd {
+        level: i32,
+        long_distance_matching: bool,
+    },
+    /// LZ4, which is the fastest and compresses the least. It has no level.
+    #[cfg(feature = "lz4")]
+    Lz4,
+    /// XZ with a level from 0 to 9, which is the slowest and compresses the most.
+    #[cfg(feature = "xz")]
+    Xz { level: u32 },
+}
+
+impl Codec {
+    /// Zstandard with the level and without long distance matching.
+    pub const fn zstd(level: i32) -> Self {
+        Codec::Zstd {
+            level,
+            long_distance_matching: false,
+        }
+    }
+
+    /// Returns the identifier of the codec recorded in the header.
+    pub fn id(self) -> u8 {
+        match self {
+            Codec::Zlib { .. } => ZLIB_CODEC,
+            Codec::Zstd { .. } => ZSTD_CODEC,
+            #[cfg(feature = "lz4")]
+            Codec::Lz4 => LZ4_CODEC,
+            #[cfg(feature = "xz")]
+            Codec::Xz { .. } => XZ_CODEC,
+        }
+    }
+
+    /// Returns the codec of the identifier recorded in the header, with its default level.
+    ///
+    /// # Errors
+    /// - The identifier is unknown.
+    /// - The codec is not enabled by its feature.
+    pub fn from_id(id: u8) -> io::Result<Self> {
+        match id {
+            ZLIB_CODEC => Ok(Codec::default()),
+            ZSTD_CODEC => Ok(Codec::zstd(zstd::DEFAULT_COMPRESSION_LEVEL)),
+            #[cfg(feature = "lz4")]
+            LZ4_CODEC => Ok(Codec::Lz4),
+            #[cfg(not(feature = "lz4"))]
+            LZ4_CODEC => Err(disabled_codec("LZ4", "lz4")),
+            #[cfg(feature = "xz")]
+            XZ_CODEC => Ok(Codec::Xz {
+                level: XZ_DEFAULT_LEVEL,
+            }),
+            #[cfg(not(feature = "xz"))]
+            XZ_CODEC => Err(disabled_codec("XZ", "xz")),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unknown compression codec: {}", id),
+            )),
+        }
+    }
 
-pub const TEMP_COMPRESSED_FILE_PATH: &str = "./.LUSL_temp";
-
-pub fn compress<T: AsRef<Path>, O: AsRef<Path>>(
-    original_file_path: T,
-    destination_path: O,
-) -> io::Result<PathBuf> {
-    let mut compressor = ZlibEncoder::new(
-        BufReader::new(File::open(&original_file_path)?),
-        Compression::new(9),
-    );
-    let dir = destination_path.as_ref().to_path_buf();
-    fs::create_dir_all(&dir)?;
-    let mut t = original_file_path.as_ref().to_path_buf();
-    t.set_extension("zip");
-    let compressed_file_path = dir.join(t.file_name().unwrap());
-    let mut result = BufWriter::new(File::create(&compressed_file_path)?);
-    let mut buf = Vec::new();
-    compressor.read_to_end(&mut buf)?;
-    result.write_all(&buf)?;
-    result.flush()?;
-    Ok(compressed_file_path)
+    /// Verify the level is in the range of the codec.
+    pub fn verify_level(self) -> io::Result<()> {
+        let is_valid = match self {
+            Codec::Zlib { level } => level <= ZLIB_MAX_LEVEL,
+            Codec::Zstd { level, .. } => zstd::compression_level_range().contains(&level),
+            #[cfg(feature = "lz4")]
+            Codec::Lz4 => true,
+            #[cfg(feature = "xz")]
+            Codec::Xz { level } => level <= XZ_MAX_LEVEL,
+        };
+        match is_valid {
+            true => Ok(()),
+            false => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid compression level for {:?}.", self),
+            )),
+        }
+    }
+}
+
+impl Default for Codec {
+    fn default() -> Self {
+        Codec::Zlib { level: 9 }
+    }
 }
 
-pub fn decompress<T: AsRef<Path>, O: AsRef<Path>>(
-    original_file_path: T,
-    destination_path: O,
-) -> io::Result<PathBuf> {
-    let mut decompressor = ZlibDecoder::new(BufReader::new(File::open(&original_file_path)?));
-    let dir = destination_path.as_ref().to_path_buf();
-    fs::create_dir_all(&dir)?;
-    let mut t = original_file_path.as_ref().to_path_buf();
-    let e = t.clone();
-    let original_extension = e.extension();
-    t.set_extension("bin");
-    let decompressed_file_path = dir.join(t.file_name().unwrap());
-    let mut result = BufWriter::new(File::create(&decompressed_file_path)?);
-    let mut buf = Vec::new();
-    decompressor.read_to_end(&mut buf)?;
-    result.write_all(&buf)?;
-    result.flush()?;
-    fs::remove_file(&original_file_path)?;
-    let mut p = decompressed_file_path.clone();
-    p.set_extension(match original_extension {
-        Some(e) => e,
-        None => OsStr::new("s"),
-    });
-    fs::rename(&decompressed_file_path, &p)?;
-    Ok(p)
+#[cfg(any(not(feature = "lz4"), not(feature = "xz")))]
+fn disabled_codec(name: &str, feature: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "The data is compressed with {}, which needs the `{}` feature.",
+            name, feature
+        ),
+    )
+}
+
+/// Returns true if compressing the file with the codec is worth it.
+///
+/// It is not if the extension of the file is of an already compressed format,
+/// or compressing the first 64 KiB of it saves less than 5 percent of them.
+pub fn is_compressible<T: AsRef<Path>>(file_path: T, codec: Codec) -> io::Result<bool> {
+    let file_path = file_path.as_ref();
+    if let Some(extension) = file_path.extension().and_then(|e| e.to_str()) {
+        if INCOMPRESSIBLE_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str()) {
+            return Ok(false);
+        }
+    }
+    let mut trial = Vec::new();
+    File::open(file_path)?
+        .take(TRIAL_LENGTH)
+        .read_to_end(&mut trial)?;
+    let mut encoder = make_encoder(codec, Vec::new())?;
+    encoder.write_all(&trial)?;
+    let compressed = encoder.finish()?;
+    Ok(compressed.len() as u64 * 100 <= trial.len() as u64 * MAX_TRIAL_RATIO)
+}
+
+/// Compresses everything written to it into the writer, with the codec it was made by.
+pub enum Encoder<W: Write> {
+    Zlib(ZlibEncoder<W>),
+    Zstd(zstd::Encoder<'static, W>),
+    #[cfg(feature = "lz4")]
+    Lz4(Lz4Encoder<W>),
+    #[cfg(feature = "xz")]
+    Xz(XzEncoder<W>),
+}
+
+impl<W: Write> Encoder<W> {
+    /// Write the end of the compressed data, and returns the writer.
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            Encoder::Zlib(encoder) => encoder.finish(),
+            Encoder::Zstd(encoder) => encoder.finish(),
+            #[cfg(feature = "lz4")]
+            Encoder::Lz4(encoder) => encoder.finish(),
+            #[cfg(feature = "xz")]
+            Encoder::Xz(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Encoder::Zlib(encoder) => encoder.write(buf),
+            Encoder::Zstd(encoder) => encoder.write(buf),
+            #[cfg(feature = "lz4")]
+            Encoder::Lz4(encoder) => encoder.write(buf),
+            #[cfg(feature = "xz")]
+            Encoder::Xz(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Encoder::Zlib(encoder) => encoder.flush(),
+            Encoder::Zstd(encoder) => encoder.flush(),
+            #[cfg(feature = "lz4")]
+            Encoder::Lz4(encoder) => encoder.flush(),
+            #[cfg(feature = "xz")]
+            Encoder::Xz(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Decompresses everything written to it into the writer, with the codec it was made by.
+pub enum Decoder<W: Write> {
+    Zlib(ZlibDecoder<W>),
+    Zstd(zstd::stream::write::Decoder<'static, W>),
+    #[cfg(feature = "lz4")]
+    Lz4(Lz4Decoder<W>),
+    #[cfg(feature = "xz")]
+    Xz(XzDecoder<W>),
+}
+
+impl<W: Write> Decoder<W> {
+    /// Write the end of the decompressed data, and returns the writer.
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            Decoder::Zlib(decoder) => decoder.finish(),
+            Decoder::Zstd(mut decoder) => {
+                decoder.flush()?;
+                Ok(decoder.into_inner())
+            }
+            #[cfg(feature = "lz4")]
+            Decoder::Lz4(decoder) => decoder.finish(),
+            #[cfg(feature = "xz")]
+            Decoder::Xz(mut decoder) => decoder.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for Decoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Decoder::Zlib(decoder) => decoder.write(buf),
+            Decoder::Zstd(decoder) => decoder.write(buf),
+            #[cfg(feature = "lz4")]
+            Decoder::Lz4(decoder) => decoder.write(buf),
+            #[cfg(feature = "xz")]
+            Decoder::Xz(decoder) => decoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Decoder::Zlib(decoder) => decoder.flush(),
+            Decoder::Zstd(decoder) => decoder.flush(),
+            #[cfg(feature = "lz4")]
+            Decoder::Lz4(decoder) => decoder.flush(),
+            #[cfg(feature = "xz")]
+            Decoder::Xz(decoder) => decoder.flush(),
+        }
+    }
+}
+
+/// Make an encoder which compresses everything written to it into the writer.
+///
+/// [`Encoder::finish`] must be called to write the end of the compressed data.
+pub fn make_encoder<W: Write>(codec: Codec, writer: W) -> io::Result<Encoder<W>> {
+    codec.verify_level()?;
+    Ok(match codec {
+        Codec::Zlib { level } => Encoder::Zlib(ZlibEncoder::new(writer, Compression::new(level))),
+        Codec::Zstd {
+            level,
+            long_distance_matching,
+        } => {
+            let mut encoder = zstd::Encoder::new(writer, level)?;
+            encoder.long_distance_matching(long_distance_matching)?;
+            Encoder::Zstd(encoder)
+        }
+        #[cfg(feature = "lz4")]
+        Codec::Lz4 => Encoder::Lz4(Lz4Encoder::new(writer)),
+        #[cfg(feature = "xz")]
+        Codec::Xz { level } => Encoder::Xz(XzEncoder::new(writer, level)),
+    })
+}
+
+/// Make a decoder which decompresses everything written to it into the writer.
+///
+/// [`Decoder::finish`] must be called to write the end of the decompressed data.
+pub fn make_decoder<W: Write>(codec: Codec, writer: W) -> io::Result<Decoder<W>> {
+    Ok(match codec {
+        Codec::Zlib { .. } => Decoder::Zlib(ZlibDecoder::new(writer)),
+        Codec::Zstd { .. } => Decoder::Zstd(zstd::stream::write::Decoder::new(writer)?),
+        #[cfg(feature = "lz4")]
+        Codec::Lz4 => Decoder::Lz4(Lz4Decoder::new(writer)),
+        #[cfg(feature = "xz")]
+        Codec::Xz { .. } => Decoder::Xz(XzDecoder::new(writer)),
+    })
 }
 
 #[cfg(test)]
 mod tests {
-    use std::{fs, path::PathBuf};
+    use std::{fs, io};
+
+    use super::{is_compressible, make_decoder, make_encoder, Codec};
 
-    use super::{compress, decompress};
+    /// Every codec enabled by the features, with various levels.
+    fn every_codec() -> Vec<Codec> {
+        #[allow(unused_mut)]
+        let mut codecs = vec![
+            Codec::default(),
+            Codec::Zlib { level: 0 },
+            Codec::zstd(3),
+            Codec::zstd(-5),
+            Codec::Zstd {
+                level: 19,
+                long_distance_matching: true,
+            },
+        ];
+        #[cfg(feature = "lz4")]
+        codecs.push(Codec::Lz4);
+        #[cfg(feature = "xz")]
+        codecs.extend([Codec::Xz { level: 0 }, Codec::Xz { level: 9 }]);
+        codecs
+    }
 
     #[test]
     fn compress_test() {
-        let p = PathBuf::from("./decompress_test_temp/board-g43968feec_1920.zip");
-        compress(
+        let original = fs::read("tests/original_images/dir1/board-g43968feec_1920.jpg").unwrap();
+        for codec in every_codec() {
+            let mut encoder = make_encoder(codec, Vec::new()).unwrap();
+            io::copy(&mut original.as_slice(), &mut encoder).unwrap();
+            let compressed = encoder.finish().unwrap();
+
+            // The level is not needed to decompress.
+            let codec = Codec::from_id(codec.id()).unwrap();
+            let mut decoder = make_decoder(codec, Vec::new()).unwrap();
+            io::copy(&mut compressed.as_slice(), &mut decoder).unwrap();
+            let decompressed = decoder.finish().unwrap();
+            assert_eq!(original, decompressed);
+        }
+        assert!(make_encoder(Codec::Zlib { level: 10 }, Vec::new()).is_err());
+        assert!(make_encoder(Codec::zstd(23), Vec::new()).is_err());
+        assert!(Codec::from_id(0).is_err());
+        #[cfg(feature = "xz")]
+        assert!(make_encoder(Codec::Xz { level: 10 }, Vec::new()).is_err());
+        #[cfg(not(feature = "lz4"))]
+        assert_eq!(
+            Codec::from_id(0x03).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn is_compressible_test() {
+        let codec = Codec::default();
+        assert!(!is_compressible(
             "tests/original_images/dir1/board-g43968feec_1920.jpg",
-            p.parent().unwrap(),
+            codec
         )
-        .unwrap();
-        fs::create_dir_all(&p.parent().unwrap()).unwrap();
-        let a = decompress(&p, &p.parent().unwrap().join("test")).unwrap();
-        let original_size = PathBuf::from("tests/original_images/dir1/board-g43968feec_1920.jpg")
-            .metadata()
-            .unwrap()
-            .len();
-        let decompressed_size = a.metadata().unwrap().len();
-        assert_eq!(original_size, decompressed_size);
-        fs::remove_dir_all(&p.parent().unwrap()).unwrap();
+        .unwrap());
+        assert!(is_compressible("src/lib.rs", codec).unwrap());
+
+        // Data already compressed is detected by trying, whatever its extension is.
+        let path = "is_compressible_test.bin";
+        let mut encoder = make_encoder(Codec::zstd(19), Vec::new()).unwrap();
+        io::copy(&mut fs::File::open("src/lib.rs").unwrap(), &mut encoder).unwrap();
+        fs::write(path, encoder.finish().unwrap()).unwrap();
+        assert!(!is_compressible(path, codec).unwrap());
+        fs::remove_file(path).unwrap();
     }
 }
@@ -0,0 +1,586 @@
+//! Key slot module.
+//!
+//! The data of an encrypted file is encrypted with a random data key,
+//! and the data key is wrapped once per credential which can decrypt the file.
+//! Any one of the credentials can unwrap the data key from its slot.
+//!
+//! Every key slot is stored in the following format:
+//! - Slot type: 1 byte
+//! - Password slot: Argon2 parameters of 13 bytes, salt of 32 bytes and the sealed data key
+//! - Legacy password slot: salt of 32 bytes and the sealed data key, with the default Argon2 parameters
+//! - Recipient slot: ephemeral X25519 public key of 32 bytes and the sealed data key
+//! - Raw key slot: the data key sealed with the raw key
+//! - Keyfile slot: salt of 32 bytes and the sealed data key
+//! - Password and keyfile slot: Argon2 parameters of 13 bytes, salt of 32 bytes and the sealed data key
+//!
+//! The slot type records which kind of credential can open the slot.
+//! The data key is sealed with the cipher of the file, so the length of the sealed data key depends on it.
+//!
+
+use std::{
+    fmt,
+    fs::File,
+    io::{self, Read},
+    path::Path,
+};
+
+use chacha20poly1305::aead::{rand_core::RngCore, OsRng};
+use hkdf::Hkdf;
+use sha2::{Digest, Sha256};
+use x25519_dalek::{EphemeralSecret, PublicKey, StaticSecret};
+
+use super::{
+    make_key_from_password_and_salt, make_new_key_from_password, Argon2Params, Cipher, SecretKey,
+    ARGON2_PARAMS_LENGTH, SALT_LENGTH,
+};
+
+pub const KEY_LENGTH: usize = 32;
+pub const X25519_KEY_LENGTH: usize = 32;
+const LEGACY_PASSWORD_SLOT: u8 = 0x01;
+const RECIPIENT_SLOT: u8 = 0x02;
+const PASSWORD_SLOT: u8 = 0x03;
+const KEY_SLOT: u8 = 0x04;
+const KEYFILE_SLOT: u8 = 0x05;
+const PASSWORD_AND_KEYFILE_SLOT: u8 = 0x06;
+const RECIPIENT_KEY_INFO: &[u8] = b"LUSL X25519 key slot";
+const KEYFILE_KEY_INFO: &[u8] = b"LUSL keyfile key slot";
+const PASSWORD_AND_KEYFILE_KEY_INFO: &[u8] = b"LUSL password and keyfile key slot";
+
+/// The kind of credential which can open a key slot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CredentialKind {
+    /// A password, whose key is derived by Argon2.
+    Password,
+    /// A raw key of 32 bytes.
+    Key,
+    /// The contents of a keyfile.
+    Keyfile,
+    /// A password combined with a keyfile.
+    PasswordAndKeyfile,
+    /// The [`Identity`] of a recipient.
+    Identity,
+}
+
+impl fmt::Display for CredentialKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            CredentialKind::Password => "a password",
+            CredentialKind::Key => "a raw key",
+            CredentialKind::Keyfile => "a keyfile",
+            CredentialKind::PasswordAndKeyfile => "a password with a keyfile",
+            CredentialKind::Identity => "an identity",
+        };
+        write!(f, "{}", kind)
+    }
+}
+
+/// The public key of an X25519 key pair, which files can be encrypted for.
+///
+/// # Examples
+/// ```
+/// use lusl::{Identity, Recipient};
+/// let identity = Identity::generate();
+/// let recipient = identity.recipient();
+/// assert_eq!(Recipient::from_bytes(recipient.to_bytes()), recipient);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Recipient(PublicKey);
+
+impl Recipient {
+    /// Make a recipient from the bytes of an X25519 public key.
+    pub fn from_bytes(bytes: [u8; X25519_KEY_LENGTH]) -> Self {
+        Recipient(PublicKey::from(bytes))
+    }
+
+    /// Returns the bytes of the public key.
+    pub fn to_bytes(&self) -> [u8; X25519_KEY_LENGTH] {
+        self.0.to_bytes()
+    }
+}
+
+/// The private key of an X25519 key pair, which can decrypt files encrypted for its [`Recipient`].
+#[derive(Clone)]
+pub struct Identity(StaticSecret);
+
+impl Identity {
+    /// Generate a new random identity.
+    pub fn generate() -> Self {
+        Identity(StaticSecret::random_from_rng(OsRng))
+    }
+
+    /// Make an identity from the bytes of an X25519 private key.
+    pub fn from_bytes(bytes: [u8; X25519_KEY_LENGTH]) -> Self {
+        Identity(StaticSecret::from(bytes))
+    }
+
+    /// Returns the bytes of the private key.
+    pub fn to_bytes(&self) -> [u8; X25519_KEY_LENGTH] {
+        self.0.to_bytes()
+    }
+
+    /// Returns the recipient to encrypt files for this identity.
+    pub fn recipient(&self) -> Recipient {
+        Recipient(PublicKey::from(&self.0))
+    }
+}
+
+/// A data key wrapped by a credential.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum KeySlot {
+    /// The data key is wrapped by the key derived from a password and the salt with the parameters.
+    Password {
+        params: Argon2Params,
+        salt: Vec<u8>,
+        sealed_key: Vec<u8>,
+    },
+    /// The data key is wrapped for a recipient, by the key agreed with the ephemeral public key.
+    Recipient {
+        ephemeral: [u8; X25519_KEY_LENGTH],
+        sealed_key: Vec<u8>,
+    },
+    /// The data key is wrapped by a raw key.
+    Key { sealed_key: Vec<u8> },
+    /// The data key is wrapped by the key derived from the digest of a keyfile and the salt.
+    Keyfile { salt: Vec<u8>, sealed_key: Vec<u8> },
+    /// The data key is wrapped by the key derived from a password with the parameters, the salt and the digest of a keyfile.
+    PasswordAndKeyfile {
+        params: Argon2Params,
+        salt: Vec<u8>,
+        sealed_key: Vec<u8>,
+    },
+}
+
+impl KeySlot {
+    /// Wrap the data key with a password, whose key is derived with the parameters.
+    pub fn for_password(
+        password: &str,
+        params: &Argon2Params,
+        data_key: &[u8],
+        cipher: Cipher,
+    ) -> io::Result<Self> {
+        let (key, salt) = make_new_key_from_password(password, params)?;
+        Ok(KeySlot::Password {
+            params: *params,
+            salt: salt.to_vec(),
+            sealed_key: cipher.seal(&key, data_key),
+        })
+    }
+
+    /// Wrap the data key for a recipient.
+    pub fn for_recipient(recipient: &Recipient, data_key: &[u8], cipher: Cipher) -> Self {
+        let secret = EphemeralSecret::random_from_rng(OsRng);
+        let ephemeral = PublicKey::from(&secret);
+        let shared = secret.diffie_hellman(&recipient.0);
+        let key = make_recipient_key(shared.as_bytes(), &ephemeral, &recipient.0);
+        KeySlot::Recipient {
+            ephemeral: ephemeral.to_bytes(),
+            sealed_key: cipher.seal(&key, data_key),
+        }
+    }
+
+    /// Wrap the data key with a raw key of 32 bytes.
+    pub fn for_key(key: &[u8; KEY_LENGTH], data_key: &[u8], cipher: Cipher) -> Self {
+        KeySlot::Key {
+            sealed_key: cipher.seal(key, data_key),
+        }
+    }
+
+    /// Wrap the data key with the digest of a keyfile, which is read by [`read_keyfile`].
+    pub fn for_keyfile(keyfile: &[u8], data_key: &[u8], cipher: Cipher) -> Self {
+        let salt = make_salt();
+        let key = derive_key(keyfile, &salt, KEYFILE_KEY_INFO);
+        KeySlot::Keyfile {
+            salt,
+            sealed_key: cipher.seal(&key, data_key),
+        }
+    }
+
+    /// Wrap the data key with a password combined with the digest of a keyfile.
+    pub fn for_password_and_keyfile(
+        password: &str,
+        keyfile: &[u8],
+        params: &Argon2Params,
+        data_key: &[u8],
+        cipher: Cipher,
+    ) -> io::Result<Self> {
+        let (password_key, salt) = make_new_key_from_password(password, params)?;
+        let key = derive_key(&password_key, keyfile, PASSWORD_AND_KEYFILE_KEY_INFO);
+        Ok(KeySlot::PasswordAndKeyfile {
+            params: *params,
+            salt: salt.to_vec(),
+            sealed_key: cipher.seal(&key, data_key),
+        })
+    }
+
+    /// Returns the kind of credential which can open the slot.
+    pub fn credential_kind(&self) -> CredentialKind {
+        match self {
+            KeySlot::Password { .. } => CredentialKind::Password,
+            KeySlot::Recipient { .. } => CredentialKind::Identity,
+            KeySlot::Key { .. } => CredentialKind::Key,
+            KeySlot::Keyfile { .. } => CredentialKind::Keyfile,
+            KeySlot::PasswordAndKeyfile { .. } => CredentialKind::PasswordAndKeyfile,
+        }
+    }
+
+    /// Unwrap the data key with a password.
+    ///
+    /// Returns `None` if the password is wrong or the slot is not a password slot.
+    pub fn open_with_password(&self, password: &str, cipher: Cipher) -> Option<SecretKey> {
+        match self {
+            KeySlot::Password {
+                params,
+                salt,
+                sealed_key,
+            } => {
+                let key = make_key_from_password_and_salt(password, salt.clone(), params).ok()?;
+                cipher.unseal(&key, sealed_key).map(SecretKey::new)
+            }
+            _ => None,
+        }
+    }
+
+    /// Unwrap the data key with an identity.
+    ///
+    /// Returns `None` if the slot is not for the recipient of the identity.
+    pub fn open_with_identity(&self, identity: &Identity, cipher: Cipher) -> Option<SecretKey> {
+        match self {
+            KeySlot::Recipient {
+                ephemeral,
+                sealed_key,
+            } => {
+                let ephemeral = PublicKey::from(*ephemeral);
+                let shared = identity.0.diffie_hellman(&ephemeral);
+                let key =
+                    make_recipient_key(shared.as_bytes(), &ephemeral, &identity.recipient().0);
+                cipher.unseal(&key, sealed_key).map(SecretKey::new)
+            }
+            _ => None,
+        }
+    }
+
+    /// Unwrap the data key with a raw key.
+    ///
+    /// Returns `None` if the key is wrong or the slot is not a raw key slot.
+    pub fn open_with_key(&self, key: &[u8; KEY_LENGTH], cipher: Cipher) -> Option<SecretKey> {
+        match self {
+            KeySlot::Key { sealed_key } => cipher.unseal(key, sealed_key).map(SecretKey::new),
+            _ => None,
+        }
+    }
+
+    /// Unwrap the data key with the digest of a keyfile.
+    ///
+    /// Returns `None` if the keyfile is wrong or the slot is not a keyfile slot.
+    pub fn open_with_keyfile(&self, keyfile: &[u8], cipher: Cipher) -> Option<SecretKey> {
+        match self {
+            KeySlot::Keyfile { salt, sealed_key } => cipher
+                .unseal(&derive_key(keyfile, salt, KEYFILE_KEY_INFO), sealed_key)
+                .map(SecretKey::new),
+            _ => None,
+        }
+    }
+
+    /// Unwrap the data key with a password combined with the digest of a keyfile.
+    ///
+    /// Returns `None` if the password or the keyfile is wrong, or the slot is not a password and keyfile slot.
+    pub fn open_with_password_and_keyfile(
+        &self,
+        password: &str,
+        keyfile: &[u8],
+        cipher: Cipher,
+    ) -> Option<SecretKey> {
+        match self {
+            KeySlot::PasswordAndKeyfile {
+                params,
+                salt,
+                sealed_key,
+            } => {
+                let password_key =
+                    make_key_from_password_and_salt(password, salt.clone(), params).ok()?;
+                let key = derive_key(&password_key, keyfile, PASSWORD_AND_KEYFILE_KEY_INFO);
+                cipher.unseal(&key, sealed_key).map(SecretKey::new)
+            }
+            _ => None,
+        }
+    }
+
+    /// Converts the key slot into a binary vector.
+    pub fn to_binary_vec(&self) -> Vec<u8> {
+        let mut binary = Vec::new();
+        match self {
+            KeySlot::Password {
+                params,
+                salt,
+                sealed_key,
+            } => {
+                binary.push(PASSWORD_SLOT);
+                binary.append(&mut params.to_binary_vec());
+                binary.extend_from_slice(salt);
+                binary.extend_from_slice(sealed_key);
+            }
+            KeySlot::Recipient {
+                ephemeral,
+                sealed_key,
+            } => {
+                binary.push(RECIPIENT_SLOT);
+                binary.extend_from_slice(ephemeral);
+                binary.extend_from_slice(sealed_key);
+            }
+            KeySlot::Key { sealed_key } => {
+                binary.push(KEY_SLOT);
+                binary.extend_from_slice(sealed_key);
+            }
+            KeySlot::Keyfile { salt, sealed_key } => {
+                binary.push(KEYFILE_SLOT);
+                binary.extend_from_slice(salt);
+                binary.extend_from_slice(sealed_key);
+            }
+            KeySlot::PasswordAndKeyfile {
+                params,
+                salt,
+                sealed_key,
+            } => {
+                binary.push(PASSWORD_AND_KEYFILE_SLOT);
+                binary.append(&mut params.to_binary_vec());
+                binary.extend_from_slice(salt);
+                binary.extend_from_slice(sealed_key);
+            }
+        }
+        binary
+    }
+
+    /// Read the key slot, whose data key is sealed with the cipher, from the reader.
+    pub fn read_from<R: Read>(reader: &mut R, cipher: Cipher) -> io::Result<Self> {
+        let mut slot_type = [0u8; 1];
+        reader.read_exact(&mut slot_type)?;
+        let mut sealed_key = vec![0u8; cipher.sealed_size(KEY_LENGTH)];
+        match slot_type[0] {
+            LEGACY_PASSWORD_SLOT | PASSWORD_SLOT | PASSWORD_AND_KEYFILE_SLOT => {
+                let params = match slot_type[0] {
+                    LEGACY_PASSWORD_SLOT => Argon2Params::LEGACY,
+                    _ => {
+                        let mut params = [0u8; ARGON2_PARAMS_LENGTH];
+                        reader.read_exact(&mut params)?;
+                        Argon2Params::from_binary(&params)?
+                    }
+                };
+                let mut salt = vec![0u8; SALT_LENGTH];
+                reader.read_exact(&mut salt)?;
+                reader.read_exact(&mut sealed_key)?;
+                match slot_type[0] {
+                    PASSWORD_AND_KEYFILE_SLOT => Ok(KeySlot::PasswordAndKeyfile {
+                        params,
+                        salt,
+                        sealed_key,
+                    }),
+                    _ => Ok(KeySlot::Password {
+                        params,
+                        salt,
+                        sealed_key,
+                    }),
+                }
+            }
+            RECIPIENT_SLOT => {
+                let mut ephemeral = [0u8; X25519_KEY_LENGTH];
+                reader.read_exact(&mut ephemeral)?;
+                reader.read_exact(&mut sealed_key)?;
+                Ok(KeySlot::Recipient {
+                    ephemeral,
+                    sealed_key,
+                })
+            }
+            KEY_SLOT => {
+                reader.read_exact(&mut sealed_key)?;
+                Ok(KeySlot::Key { sealed_key })
+            }
+            KEYFILE_SLOT => {
+                let mut salt = vec![0u8; SALT_LENGTH];
+                reader.read_exact(&mut salt)?;
+                reader.read_exact(&mut sealed_key)?;
+                Ok(KeySlot::Keyfile { salt, sealed_key })
+            }
+            t => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unknown key slot type: {:#04x}", t),
+            )),
+        }
+    }
+}
+
+/// Make a new random data key.
+pub fn make_data_key() -> SecretKey {
+    let mut key = vec![0u8; KEY_LENGTH];
+    OsRng.fill_bytes(&mut key);
+    SecretKey::new(key)
+}
+
+/// Read the keyfile and returns its SHA-256 digest, which is used to wrap the data key.
+///
+/// # Errors
+/// The keyfile cannot be read or is empty.
+pub fn read_keyfile<P: AsRef<Path>>(path: P) -> io::Result<SecretKey> {
+    let mut hasher = Sha256::new();
+    let length = io::copy(&mut File::open(&path)?, &mut hasher)?;
+    if length == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("The keyfile {} is empty.", path.as_ref().display()),
+        ));
+    }
+    Ok(SecretKey::new(hasher.finalize().to_vec()))
+}
+
+fn make_salt() -> Vec<u8> {
+    let mut salt = vec![0u8; SALT_LENGTH];
+    OsRng.fill_bytes(&mut salt);
+    salt
+}
+
+/// Derive a key from the input key material and the salt by HKDF-SHA256.
+fn derive_key(input: &[u8], salt: &[u8], info: &[u8]) -> SecretKey {
+    let mut key = vec![0u8; KEY_LENGTH];
+    Hkdf::<Sha256>::new(Some(salt), input)
+        .expand(info, &mut key)
+        .unwrap();
+    SecretKey::new(key)
+}
+
+/// Derive the key which wraps the data key for a recipient, from the shared secret of X25519.
+fn make_recipient_key(shared: &[u8], ephemeral: &PublicKey, recipient: &PublicKey) -> SecretKey {
+    let mut salt = ephemeral.to_bytes().to_vec();
+    salt.extend_from_slice(recipient.as_bytes());
+    derive_key(shared, &salt, RECIPIENT_KEY_INFO)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encrypt::Argon2Variant;
+
+    #[test]
+    fn key_slot_test() {
+        let cipher = Cipher::default();
+        let data_key = make_data_key();
+        let identity = Identity::generate();
+        let slots = [
+            KeySlot::for_password(
+                "test_password",
+                &Argon2Params::new(Argon2Variant::Argon2id, 8192, 2, 2),
+                &data_key,
+                cipher,
+            )
+            .unwrap(),
+            KeySlot::for_recipient(&identity.recipient(), &data_key, cipher),
+        ];
+        for slot in &slots {
+            let binary = slot.to_binary_vec();
+            assert_eq!(
+                &KeySlot::read_from(&mut binary.as_slice(), cipher).unwrap(),
+                slot
+            );
+        }
+
+        assert_eq!(
+            slots[0]
+                .open_with_password("test_password", cipher)
+                .unwrap()[..],
+            data_key[..]
+        );
+        assert!(slots[0]
+            .open_with_password("wrong_password", cipher)
+            .is_none());
+        assert!(slots[0].open_with_identity(&identity, cipher).is_none());
+        assert_eq!(
+            slots[1].open_with_identity(&identity, cipher).unwrap()[..],
+            data_key[..]
+        );
+        assert!(slots[1]
+            .open_with_identity(&Identity::generate(), cipher)
+            .is_none());
+        assert!(slots[1]
+            .open_with_password("test_password", cipher)
+            .is_none());
+
+        // The other slots are sealed with AES-256-GCM-SIV, whose sealed data key is shorter.
+        let cipher = Cipher::Aes256GcmSiv;
+        let slots = [
+            KeySlot::for_key(&[7u8; KEY_LENGTH], &data_key, cipher),
+            KeySlot::for_keyfile(b"keyfile digest", &data_key, cipher),
+            KeySlot::for_password_and_keyfile(
+                "test_password",
+                b"keyfile digest",
+                &Argon2Params::default(),
+                &data_key,
+                cipher,
+            )
+            .unwrap(),
+        ];
+        for slot in &slots {
+            let binary = slot.to_binary_vec();
+            assert_eq!(
+                &KeySlot::read_from(&mut binary.as_slice(), cipher).unwrap(),
+                slot
+            );
+        }
+        assert_eq!(slots[0].credential_kind(), CredentialKind::Key);
+        assert_eq!(
+            slots[0].open_with_key(&[7u8; KEY_LENGTH], cipher).unwrap()[..],
+            data_key[..]
+        );
+        assert!(slots[0].open_with_key(&[8u8; KEY_LENGTH], cipher).is_none());
+        assert_eq!(
+            slots[1]
+                .open_with_keyfile(b"keyfile digest", cipher)
+                .unwrap()[..],
+            data_key[..]
+        );
+        assert!(slots[1]
+            .open_with_keyfile(b"other digest", cipher)
+            .is_none());
+        assert!(slots[1]
+            .open_with_password("test_password", cipher)
+            .is_none());
+        assert_eq!(
+            slots[2]
+                .open_with_password_and_keyfile("test_password", b"keyfile digest", cipher)
+                .unwrap()[..],
+            data_key[..]
+        );
+        assert!(slots[2]
+            .open_with_password_and_keyfile("test_password", b"other digest", cipher)
+            .is_none());
+        assert!(slots[2]
+            .open_with_password("test_password", cipher)
+            .is_none());
+
+        // A password slot of 2.8.0 has no parameters, and the legacy ones are used.
+        let (key, salt) =
+            make_new_key_from_password("test_password", &Argon2Params::LEGACY).unwrap();
+        let mut binary = vec![LEGACY_PASSWORD_SLOT];
+        binary.extend_from_slice(&salt);
+        binary.append(&mut Cipher::XChaCha20Poly1305.seal(&key, &data_key));
+        let legacy = KeySlot::read_from(&mut binary.as_slice(), Cipher::XChaCha20Poly1305).unwrap();
+        assert_eq!(
+            legacy
+                .open_with_password("test_password", Cipher::XChaCha20Poly1305)
+                .unwrap()[..],
+            data_key[..]
+        );
+
+        // A slot with huge parameters is refused before deriving any key with them.
+        for params in [
+            Argon2Params::new(Argon2Variant::Argon2id, u32::MAX, 2, 1),
+            Argon2Params::new(Argon2Variant::Argon2id, 19456, u32::MAX, 1),
+            Argon2Params::new(Argon2Variant::Argon2id, 19456, 2, u32::MAX),
+        ] {
+            let mut binary = vec![PASSWORD_SLOT];
+            binary.append(&mut params.to_binary_vec());
+            binary.extend_from_slice(&salt);
+            binary.append(&mut Cipher::XChaCha20Poly1305.seal(&key, &data_key));
+            let error =
+                KeySlot::read_from(&mut binary.as_slice(), Cipher::XChaCha20Poly1305).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+}
@@ -1,41 +1,705 @@
+use std::{
+    io::{self, Write},
+    ops::Deref,
+};
+
+use aes_gcm_siv::Aes256GcmSiv;
 use chacha20poly1305::{
-    aead::{rand_core::RngCore, stream, OsRng},
-    KeyInit, XChaCha20Poly1305,
+    aead::{self, rand_core::RngCore, stream, Aead, KeyInit, Nonce, OsRng, Payload},
+    XChaCha20Poly1305,
 };
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+use crate::serialize::BUFFER_LENGTH;
+
+pub mod keyslot;
 
-pub const NONCE_LENGTH: usize = 19;
 pub const SALT_LENGTH: usize = 32;
+pub const TAG_LENGTH: usize = 16;
+/// The STREAM construction takes 5 bytes of the nonce for the chunk counter and the last chunk flag.
+const STREAM_NONCE_OVERHEAD: usize = 5;
+
+pub const ARGON2_PARAMS_LENGTH: usize = 13;
+const ENTRY_AAD_LABEL: &[u8] = b"LUSL entry";
+const END_AAD_LABEL: &[u8] = b"LUSL end";
+
+/// A key which is zeroized when it is dropped.
+///
+/// It doesn't implement `Clone`, so the key is never copied implicitly.
+pub struct SecretKey(Zeroizing<Vec<u8>>);
+
+impl SecretKey {
+    /// Take the key, which is zeroized when the secret key is dropped.
+    pub fn new(key: Vec<u8>) -> Self {
+        SecretKey(Zeroizing::new(key))
+    }
+}
+
+impl Deref for SecretKey {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
 
-pub fn make_nonce() -> [u8; NONCE_LENGTH] {
-    let mut nonce = [0u8; NONCE_LENGTH];
-    OsRng.fill_bytes(&mut nonce);
-    nonce
+/// The variant of Argon2 to derive keys from passwords.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Argon2Variant {
+    /// Data-dependent memory access.
+    Argon2d,
+    /// Data-independent memory access.
+    Argon2i,
+    /// Hybrid of Argon2d and Argon2i.
+    Argon2id,
 }
 
-pub fn make_new_key_from_password(password: &str) -> (Vec<u8>, [u8; SALT_LENGTH]) {
-    let argon2_config = argon2::Config::default();
+/// The cost parameters of Argon2 to derive keys from passwords.
+///
+/// The parameters are recorded in the serialized file, so they are not needed to deserialize it.
+/// The default is Argon2id with 19456 KiB of memory, 2 iterations and 1 lane.
+/// The parameters read from a file must not be over the limits, like [`Argon2Params::MAX_MEMORY_COST`],
+/// so a malicious file cannot make opening it take too much memory or time.
+///
+/// # Examples
+/// ```
+/// use lusl::{Argon2Params, Argon2Variant, SerializeOption};
+/// let params = Argon2Params::new(Argon2Variant::Argon2id, 19456, 2, 1);
+/// let option = SerializeOption::new().to_encrypt("password").to_argon2(params);
+/// assert_eq!(option.argon2_params(), params);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Argon2Params {
+    variant: Argon2Variant,
+    memory_cost: u32,
+    iterations: u32,
+    parallelism: u32,
+}
+
+impl Argon2Params {
+    /// The parameters of the password slots of 2.8.0 and the salt of older files,
+    /// which are Argon2i with 4096 KiB of memory, 3 iterations and 1 lane.
+    pub const LEGACY: Argon2Params = Argon2Params::new(Argon2Variant::Argon2i, 4096, 3, 1);
+    /// The largest memory cost in KiB, which is 1 GiB.
+    pub const MAX_MEMORY_COST: u32 = 1024 * 1024;
+    /// The largest number of iterations.
+    pub const MAX_ITERATIONS: u32 = 16;
+    /// The largest degree of parallelism.
+    pub const MAX_PARALLELISM: u32 = 16;
+
+    /// Make new parameters. The memory cost is in KiB.
+    pub const fn new(
+        variant: Argon2Variant,
+        memory_cost: u32,
+        iterations: u32,
+        parallelism: u32,
+    ) -> Self {
+        Argon2Params {
+            variant,
+            memory_cost,
+            iterations,
+            parallelism,
+        }
+    }
+
+    /// Returns the variant of Argon2.
+    pub fn variant(&self) -> Argon2Variant {
+        self.variant
+    }
+
+    /// Returns the memory cost in KiB.
+    pub fn memory_cost(&self) -> u32 {
+        self.memory_cost
+    }
+
+    /// Returns the number of iterations.
+    pub fn iterations(&self) -> u32 {
+        self.iterations
+    }
+
+    /// Returns the degree of parallelism, the number of lanes.
+    pub fn parallelism(&self) -> u32 {
+        self.parallelism
+    }
+
+    /// Converts the parameters into a binary vector.
+    ///
+    /// The variant in 1 byte is followed by the memory cost, the iterations and the parallelism in 4 bytes (little endian) each.
+    pub fn to_binary_vec(&self) -> Vec<u8> {
+        let mut binary = Vec::with_capacity(ARGON2_PARAMS_LENGTH);
+        binary.push(match self.variant {
+            Argon2Variant::Argon2d => 0,
+            Argon2Variant::Argon2i => 1,
+            Argon2Variant::Argon2id => 2,
+        });
+        binary.extend_from_slice(&self.memory_cost.to_le_bytes());
+        binary.extend_from_slice(&self.iterations.to_le_bytes());
+        binary.extend_from_slice(&self.parallelism.to_le_bytes());
+        binary
+    }
+
+    /// Read the parameters from binary vector.
+    ///
+    /// # Errors
+    /// The variant is unknown, or the parameters are over the limits.
+    pub fn from_binary(binary: &[u8]) -> io::Result<Self> {
+        let variant = match binary[0] {
+            0 => Argon2Variant::Argon2d,
+            1 => Argon2Variant::Argon2i,
+            2 => Argon2Variant::Argon2id,
+            v => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Unknown Argon2 variant: {}", v),
+                ))
+            }
+        };
+        let read_u32 = |i: usize| u32::from_le_bytes(binary[i..i + 4].try_into().unwrap());
+        let params = Argon2Params::new(variant, read_u32(1), read_u32(5), read_u32(9));
+        params.verify_limits(io::ErrorKind::InvalidData)?;
+        Ok(params)
+    }
+
+    /// Verify the parameters are not over the limits, or occur error of the given kind.
+    pub fn verify_limits(&self, kind: io::ErrorKind) -> io::Result<()> {
+        if self.memory_cost > Self::MAX_MEMORY_COST
+            || self.iterations > Self::MAX_ITERATIONS
+            || self.parallelism > Self::MAX_PARALLELISM
+        {
+            return Err(io::Error::new(
+                kind,
+                format!(
+                    "The Argon2 parameters are over the limits of {} KiB, {} iterations and {} lanes.",
+                    Self::MAX_MEMORY_COST,
+                    Self::MAX_ITERATIONS,
+                    Self::MAX_PARALLELISM
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    fn to_config(self) -> argon2::Config<'static> {
+        argon2::Config {
+            variant: match self.variant {
+                Argon2Variant::Argon2d => argon2::Variant::Argon2d,
+                Argon2Variant::Argon2i => argon2::Variant::Argon2i,
+                Argon2Variant::Argon2id => argon2::Variant::Argon2id,
+            },
+            version: argon2::Version::Version13,
+            mem_cost: self.memory_cost,
+            time_cost: self.iterations,
+            lanes: self.parallelism,
+            hash_length: 32,
+            ..argon2::Config::default()
+        }
+    }
+}
+
+impl Default for Argon2Params {
+    fn default() -> Self {
+        Argon2Params::new(Argon2Variant::Argon2id, 19456, 2, 1)
+    }
+}
+
+pub fn make_new_key_from_password(
+    password: &str,
+    params: &Argon2Params,
+) -> io::Result<(SecretKey, [u8; SALT_LENGTH])> {
     let mut salt = [0u8; SALT_LENGTH];
     OsRng.fill_bytes(&mut salt);
-    let key = argon2::hash_raw(password.as_bytes(), &salt, &argon2_config).unwrap();
-    (key, salt)
+    let key = make_key_from_password_and_salt(password, salt.to_vec(), params)?;
+    Ok((key, salt))
+}
+
+pub fn make_key_from_password_and_salt(
+    password: &str,
+    salt: Vec<u8>,
+    params: &Argon2Params,
+) -> io::Result<SecretKey> {
+    params.verify_limits(io::ErrorKind::InvalidInput)?;
+    match argon2::hash_raw(password.as_bytes(), &salt, &params.to_config()) {
+        Ok(key) => Ok(SecretKey::new(key)),
+        Err(e) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid Argon2 parameters: {}", e),
+        )),
+    }
 }
 
-pub fn make_key_from_password_and_salt(password: &str, salt: Vec<u8>) -> Vec<u8> {
-    let argon2_config = argon2::Config::default();
-    let key = argon2::hash_raw(password.as_bytes(), &salt, &argon2_config).unwrap();
-    key
+/// Returns the length of the encrypted data of the given size.
+///
+/// The data is encrypted by chunks of `BUFFER_LENGTH` bytes and every chunk has its own tag,
+/// including the last chunk which may be empty.
+pub fn encrypted_size(size: u64) -> u64 {
+    size + TAG_LENGTH as u64 * (size / BUFFER_LENGTH as u64 + 1)
 }
 
-fn make_aead(key: &[u8]) -> XChaCha20Poly1305 {
-    XChaCha20Poly1305::new_from_slice(&key).unwrap()
+/// Returns the original size of the encrypted data of the given length. The inverse of [`encrypted_size`].
+pub fn decrypted_size(size: u64) -> u64 {
+    let chunk_length = (BUFFER_LENGTH + TAG_LENGTH) as u64;
+    let last_chunk_length = (size % chunk_length).saturating_sub(TAG_LENGTH as u64);
+    size / chunk_length * BUFFER_LENGTH as u64 + last_chunk_length
+}
+
+/// The AEAD cipher which encrypts the data, the metadata and the key slots of a serialized file.
+///
+/// Both ciphers encrypt the data of the entries in the same STREAM construction, by chunks of `BUFFER_LENGTH` bytes.
+/// The cipher is recorded in the header, so it is not needed to deserialize the file.
+///
+/// # Examples
+/// ```
+/// use lusl::{Cipher, SerializeOption};
+/// let option = SerializeOption::new()
+///     .to_encrypt("password")
+///     .to_cipher(Cipher::Aes256GcmSiv);
+/// assert_eq!(option.cipher_suite(), Cipher::Aes256GcmSiv);
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Cipher {
+    /// XChaCha20-Poly1305 with nonces of 24 bytes, which older versions always used.
+    #[default]
+    XChaCha20Poly1305,
+    /// AES-256-GCM-SIV with nonces of 12 bytes.
+    Aes256GcmSiv,
+}
+
+impl Cipher {
+    /// Returns the identifier of the cipher recorded in the header.
+    pub fn id(self) -> u8 {
+        match self {
+            Cipher::XChaCha20Poly1305 => 0x01,
+            Cipher::Aes256GcmSiv => 0x02,
+        }
+    }
+
+    /// Returns the cipher of the identifier recorded in the header.
+    pub fn from_id(id: u8) -> io::Result<Self> {
+        match id {
+            0x01 => Ok(Cipher::XChaCha20Poly1305),
+            0x02 => Ok(Cipher::Aes256GcmSiv),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unknown cipher: {}", id),
+            )),
+        }
+    }
+
+    /// Returns the length of the nonce of the cipher.
+    pub fn nonce_length(self) -> usize {
+        match self {
+            Cipher::XChaCha20Poly1305 => 24,
+            Cipher::Aes256GcmSiv => 12,
+        }
+    }
+
+    /// Returns the length of the nonce in front of the encrypted data of an entry.
+    pub fn stream_nonce_length(self) -> usize {
+        self.nonce_length() - STREAM_NONCE_OVERHEAD
+    }
+
+    /// Make a random nonce for the encrypted data of an entry.
+    pub fn make_stream_nonce(self) -> Vec<u8> {
+        let mut nonce = vec![0u8; self.stream_nonce_length()];
+        OsRng.fill_bytes(&mut nonce);
+        nonce
+    }
+
+    pub fn make_encryptor(self, key: &[u8], nonce: &[u8]) -> Encryptor {
+        match self {
+            Cipher::XChaCha20Poly1305 => Encryptor::XChaCha20Poly1305(
+                stream::EncryptorBE32::from_aead(make_aead(key), nonce.into()),
+            ),
+            Cipher::Aes256GcmSiv => Encryptor::Aes256GcmSiv(Box::new(
+                stream::EncryptorBE32::from_aead(make_aead(key), nonce.into()),
+            )),
+        }
+    }
+
+    pub fn make_decryptor(self, key: &[u8], nonce: &[u8]) -> Decryptor {
+        match self {
+            Cipher::XChaCha20Poly1305 => Decryptor::XChaCha20Poly1305(
+                stream::DecryptorBE32::from_aead(make_aead(key), nonce.into()),
+            ),
+            Cipher::Aes256GcmSiv => Decryptor::Aes256GcmSiv(Box::new(
+                stream::DecryptorBE32::from_aead(make_aead(key), nonce.into()),
+            )),
+        }
+    }
+
+    /// Encrypt the small data at once with a random nonce.
+    ///
+    /// Returns the nonce followed by the encrypted data and its tag.
+    pub fn seal(self, key: &[u8], data: &[u8]) -> Vec<u8> {
+        self.seal_with_aad(key, data, &[])
+    }
+
+    /// Same as [`Cipher::seal`], but the associated data is also authenticated.
+    pub fn seal_with_aad(self, key: &[u8], data: &[u8], aad: &[u8]) -> Vec<u8> {
+        let mut nonce = vec![0u8; self.nonce_length()];
+        OsRng.fill_bytes(&mut nonce);
+        let payload = Payload { msg: data, aad };
+        let mut encrypted = match self {
+            Cipher::XChaCha20Poly1305 => seal_with::<XChaCha20Poly1305>(key, &nonce, payload),
+            Cipher::Aes256GcmSiv => seal_with::<Aes256GcmSiv>(key, &nonce, payload),
+        };
+        nonce.append(&mut encrypted);
+        nonce
+    }
+
+    /// Returns the length of the data sealed from the data of the given length.
+    pub fn sealed_size(self, size: usize) -> usize {
+        self.nonce_length() + size + TAG_LENGTH
+    }
+
+    /// Decrypt the data sealed by [`Cipher::seal`].
+    ///
+    /// Returns `None` if the key is wrong or the data is broken.
+    pub fn unseal(self, key: &[u8], sealed: &[u8]) -> Option<Vec<u8>> {
+        self.unseal_with_aad(key, sealed, &[])
+    }
+
+    /// Decrypt the data sealed by [`Cipher::seal_with_aad`] with the same associated data.
+    pub fn unseal_with_aad(self, key: &[u8], sealed: &[u8], aad: &[u8]) -> Option<Vec<u8>> {
+        if sealed.len() < self.nonce_length() + TAG_LENGTH {
+            return None;
+        }
+        let (nonce, data) = sealed.split_at(self.nonce_length());
+        let payload = Payload { msg: data, aad };
+        match self {
+            Cipher::XChaCha20Poly1305 => unseal_with::<XChaCha20Poly1305>(key, nonce, payload),
+            Cipher::Aes256GcmSiv => unseal_with::<Aes256GcmSiv>(key, nonce, payload),
+        }
+    }
 }
 
-pub fn make_encryptor(key: &[u8], nonce: &[u8]) -> stream::EncryptorBE32<XChaCha20Poly1305> {
-    let aead = make_aead(key);
-    stream::EncryptorBE32::from_aead(aead, nonce.as_ref().into())
+/// Encrypts the data of an entry by chunks, with the cipher it was made by.
+pub enum Encryptor {
+    XChaCha20Poly1305(stream::EncryptorBE32<XChaCha20Poly1305>),
+    Aes256GcmSiv(Box<stream::EncryptorBE32<Aes256GcmSiv>>),
 }
 
-pub fn make_decryptor(key: &[u8], nonce: &[u8]) -> stream::DecryptorBE32<XChaCha20Poly1305> {
-    let aead = make_aead(key);
-    stream::DecryptorBE32::from_aead(aead, nonce.as_ref().into())
+impl Encryptor {
+    pub fn encrypt_next(&mut self, payload: Payload) -> aead::Result<Vec<u8>> {
+        match self {
+            Encryptor::XChaCha20Poly1305(encryptor) => encryptor.encrypt_next(payload),
+            Encryptor::Aes256GcmSiv(encryptor) => encryptor.encrypt_next(payload),
+        }
+    }
+
+    pub fn encrypt_last(self, payload: Payload) -> aead::Result<Vec<u8>> {
+        match self {
+            Encryptor::XChaCha20Poly1305(encryptor) => encryptor.encrypt_last(payload),
+            Encryptor::Aes256GcmSiv(encryptor) => encryptor.encrypt_last(payload),
+        }
+    }
+}
+
+/// Decrypts the data of an entry by chunks, with the cipher it was made by.
+pub enum Decryptor {
+    XChaCha20Poly1305(stream::DecryptorBE32<XChaCha20Poly1305>),
+    Aes256GcmSiv(Box<stream::DecryptorBE32<Aes256GcmSiv>>),
+}
+
+impl Decryptor {
+    pub fn decrypt_next(&mut self, payload: Payload) -> aead::Result<Vec<u8>> {
+        match self {
+            Decryptor::XChaCha20Poly1305(decryptor) => decryptor.decrypt_next(payload),
+            Decryptor::Aes256GcmSiv(decryptor) => decryptor.decrypt_next(payload),
+        }
+    }
+
+    pub fn decrypt_last(self, payload: Payload) -> aead::Result<Vec<u8>> {
+        match self {
+            Decryptor::XChaCha20Poly1305(decryptor) => decryptor.decrypt_last(payload),
+            Decryptor::Aes256GcmSiv(decryptor) => decryptor.decrypt_last(payload),
+        }
+    }
+}
+
+/// Encrypts everything written to it by chunks of `BUFFER_LENGTH` bytes into the writer, with a random nonce in front.
+///
+/// Every chunk is encrypted as soon as it is filled, and the rest is encrypted as the last chunk by [`EncryptWriter::finish`].
+/// The last chunk is authenticated with the length of the whole data as well, see [`last_chunk_aad`].
+pub struct EncryptWriter<'a, W: Write> {
+    writer: W,
+    encryptor: Encryptor,
+    buffer: Vec<u8>,
+    aad: &'a [u8],
+    length: u64,
+}
+
+impl<'a, W: Write> EncryptWriter<'a, W> {
+    /// Write a new random nonce to the writer, and make the encryptor with it.
+    pub fn new(mut writer: W, cipher: Cipher, key: &[u8], aad: &'a [u8]) -> io::Result<Self> {
+        let nonce = cipher.make_stream_nonce();
+        writer.write_all(&nonce)?;
+        Ok(EncryptWriter {
+            writer,
+            encryptor: cipher.make_encryptor(key, &nonce),
+            buffer: Vec::with_capacity(BUFFER_LENGTH),
+            aad,
+            length: 0,
+        })
+    }
+
+    /// Encrypt the last chunk, which may be empty, and returns the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        let aad = last_chunk_aad(self.aad, self.length);
+        let payload = Payload {
+            msg: &self.buffer,
+            aad: &aad,
+        };
+        let encrypted_data = self
+            .encryptor
+            .encrypt_last(payload)
+            .map_err(|_| encrypt_error())?;
+        self.writer.write_all(&encrypted_data)?;
+        Ok(self.writer)
+    }
+}
+
+impl<W: Write> Write for EncryptWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let length = buf.len().min(BUFFER_LENGTH - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..length]);
+        self.length += length as u64;
+        if self.buffer.len() == BUFFER_LENGTH {
+            let payload = Payload {
+                msg: &self.buffer,
+                aad: self.aad,
+            };
+            let encrypted_data = self
+                .encryptor
+                .encrypt_next(payload)
+                .map_err(|_| encrypt_error())?;
+            self.writer.write_all(&encrypted_data)?;
+            self.buffer.clear();
+        }
+        Ok(length)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Returns the associated data of the last chunk of encrypted data, which binds the length of the whole data.
+///
+/// So the length of the data written in front of it, like the size of compressed data, cannot be changed.
+/// Data without associated data, written by versions older than 2.10.0, has none in the last chunk either.
+pub fn last_chunk_aad(aad: &[u8], length: u64) -> Vec<u8> {
+    match aad.is_empty() {
+        true => Vec::new(),
+        false => [aad, &length.to_le_bytes()].concat(),
+    }
+}
+
+fn encrypt_error() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "Cannot encrypt data!")
+}
+
+fn make_aead<A: KeyInit>(key: &[u8]) -> A {
+    A::new_from_slice(key).unwrap()
+}
+
+fn seal_with<A: Aead + KeyInit>(key: &[u8], nonce: &[u8], payload: Payload) -> Vec<u8> {
+    make_aead::<A>(key)
+        .encrypt(Nonce::<A>::from_slice(nonce), payload)
+        .unwrap()
+}
+
+fn unseal_with<A: Aead + KeyInit>(key: &[u8], nonce: &[u8], payload: Payload) -> Option<Vec<u8>> {
+    make_aead::<A>(key)
+        .decrypt(Nonce::<A>::from_slice(nonce), payload)
+        .ok()
+}
+
+/// Binds encrypted data to the header of the file, the order of the entries and their metadata.
+///
+/// The associated data of every entry covers the header, the position of the entry and its metadata,
+/// so the data cannot be moved to another entry or another file.
+/// The associated data of the end marker covers the metadata of all entries and the key slots,
+/// so no entry can be dropped, added or reordered, and no key slot can be replaced.
+/// The key slots are not bound to the entries, so [`crate::rekey`] only has to seal the end marker again.
+#[derive(Clone)]
+pub struct Transcript {
+    header_digest: Vec<u8>,
+    metadata_digests: Sha256,
+    entry_count: u64,
+}
+
+impl Transcript {
+    pub fn new(header: &[u8]) -> Self {
+        Transcript {
+            header_digest: Sha256::digest(header).to_vec(),
+            metadata_digests: Sha256::new(),
+            entry_count: 0,
+        }
+    }
+
+    /// Returns the associated data for the data of the entry at the given position, with the serialized metadata.
+    pub fn entry_aad(&self, position: u64, metadata: &[u8]) -> Vec<u8> {
+        Sha256::new()
+            .chain_update(ENTRY_AAD_LABEL)
+            .chain_update(&self.header_digest)
+            .chain_update(position.to_le_bytes())
+            .chain_update(Sha256::digest(metadata))
+            .finalize()
+            .to_vec()
+    }
+
+    /// Record the serialized metadata of the next entry, and returns the associated data for its data.
+    pub fn next_entry(&mut self, metadata: &[u8]) -> Vec<u8> {
+        let aad = self.entry_aad(self.entry_count, metadata);
+        self.metadata_digests.update(Sha256::digest(metadata));
+        self.entry_count += 1;
+        aad
+    }
+
+    /// Returns the associated data for the end marker after all recorded entries, with the serialized key slots.
+    pub fn end_aad(&self, key_slots: &[u8]) -> Vec<u8> {
+        Sha256::new()
+            .chain_update(END_AAD_LABEL)
+            .chain_update(&self.header_digest)
+            .chain_update(Sha256::digest(key_slots))
+            .chain_update(self.entry_count.to_le_bytes())
+            .chain_update(self.metadata_digests.clone().finalize())
+            .finalize()
+            .to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seal_test() {
+        let (key, _) =
+            make_new_key_from_password("test_password", &Argon2Params::default()).unwrap();
+        let (other_key, _) =
+            make_new_key_from_password("other_password", &Argon2Params::default()).unwrap();
+        for cipher in [Cipher::XChaCha20Poly1305, Cipher::Aes256GcmSiv] {
+            let sealed = cipher.seal(&key, b"lusl metadata");
+            assert_eq!(sealed.len(), cipher.sealed_size(13));
+            assert_eq!(cipher.unseal(&key, &sealed).unwrap(), b"lusl metadata");
+            assert!(cipher.unseal(&other_key, &sealed).is_none());
+            assert!(cipher.unseal(&key, &sealed[..sealed.len() - 1]).is_none());
+            assert_eq!(Cipher::from_id(cipher.id()).unwrap(), cipher);
+        }
+        let sealed = Cipher::XChaCha20Poly1305.seal(&key, b"lusl metadata");
+        assert!(Cipher::Aes256GcmSiv.unseal(&key, &sealed).is_none());
+        assert!(Cipher::from_id(0).is_err());
+    }
+
+    #[test]
+    fn stream_cipher_test() {
+        let key = keyslot::make_data_key();
+        for cipher in [Cipher::XChaCha20Poly1305, Cipher::Aes256GcmSiv] {
+            let nonce = cipher.make_stream_nonce();
+            assert_eq!(nonce.len(), cipher.stream_nonce_length());
+            let mut encryptor = cipher.make_encryptor(&key, &nonce);
+            let first = encryptor
+                .encrypt_next(Payload::from(&b"lusl "[..]))
+                .unwrap();
+            let last = encryptor.encrypt_last(Payload::from(&b"data"[..])).unwrap();
+            assert_eq!(first.len(), 5 + TAG_LENGTH);
+
+            let mut decryptor = cipher.make_decryptor(&key, &nonce);
+            assert_eq!(
+                decryptor.decrypt_next(Payload::from(&first[..])).unwrap(),
+                b"lusl "
+            );
+            assert!(decryptor.decrypt_last(Payload::from(&first[..])).is_err());
+            let mut decryptor = cipher.make_decryptor(&key, &nonce);
+            decryptor.decrypt_next(Payload::from(&first[..])).unwrap();
+            assert_eq!(
+                decryptor.decrypt_last(Payload::from(&last[..])).unwrap(),
+                b"data"
+            );
+        }
+    }
+
+    #[test]
+    fn encrypt_writer_test() {
+        let key = keyslot::make_data_key();
+        // The data of exactly one chunk is followed by an empty last chunk.
+        for size in [0, 13, BUFFER_LENGTH, BUFFER_LENGTH * 2 + 7] {
+            let data: Vec<u8> = (0..size).map(|i| i as u8).collect();
+            let mut encryptor =
+                EncryptWriter::new(Vec::new(), Cipher::Aes256GcmSiv, &key, b"aad").unwrap();
+            encryptor.write_all(&data).unwrap();
+            let encrypted = encryptor.finish().unwrap();
+            let nonce_length = Cipher::Aes256GcmSiv.stream_nonce_length();
+            assert_eq!(
+                encrypted.len() as u64,
+                nonce_length as u64 + encrypted_size(size as u64)
+            );
+
+            let (nonce, mut chunks) = encrypted.split_at(nonce_length);
+            let mut decryptor = Cipher::Aes256GcmSiv.make_decryptor(&key, nonce);
+            let mut decrypted = Vec::new();
+            while chunks.len() > BUFFER_LENGTH + TAG_LENGTH {
+                let (chunk, rest) = chunks.split_at(BUFFER_LENGTH + TAG_LENGTH);
+                let payload = Payload {
+                    msg: chunk,
+                    aad: b"aad",
+                };
+                decrypted.append(&mut decryptor.decrypt_next(payload).unwrap());
+                chunks = rest;
+            }
+            let payload = Payload {
+                msg: chunks,
+                aad: &last_chunk_aad(b"aad", size as u64),
+            };
+            decrypted.append(&mut decryptor.decrypt_last(payload).unwrap());
+            assert_eq!(decrypted, data);
+
+            // The last chunk is not authentic with another length.
+            if size < BUFFER_LENGTH {
+                let payload = Payload {
+                    msg: &encrypted[nonce_length..],
+                    aad: &last_chunk_aad(b"aad", size as u64 + 1),
+                };
+                let decryptor = Cipher::Aes256GcmSiv.make_decryptor(&key, nonce);
+                assert!(decryptor.decrypt_last(payload).is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn argon2_params_test() {
+        let params = Argon2Params::new(Argon2Variant::Argon2id, 8192, 2, 2);
+        let binary = params.to_binary_vec();
+        assert_eq!(binary.len(), ARGON2_PARAMS_LENGTH);
+        assert_eq!(Argon2Params::from_binary(&binary).unwrap(), params);
+
+        let salt = vec![7u8; SALT_LENGTH];
+        let key = make_key_from_password_and_salt("test_password", salt.clone(), &params).unwrap();
+        assert_ne!(
+            key[..],
+            make_key_from_password_and_salt(
+                "test_password",
+                salt.clone(),
+                &Argon2Params::default()
+            )
+            .unwrap()[..]
+        );
+        let invalid = Argon2Params::new(Argon2Variant::Argon2id, 0, 0, 0);
+        assert!(make_key_from_password_and_salt("test_password", salt.clone(), &invalid).is_err());
+
+        // Parameters over the limits are neither read nor used.
+        let huge = Argon2Params::new(
+            Argon2Variant::Argon2id,
+            Argon2Params::MAX_MEMORY_COST + 1,
+            2,
+            1,
+        );
+        let error = Argon2Params::from_binary(&huge.to_binary_vec()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        let error = make_key_from_password_and_salt("test_password", salt, &huge).err();
+        assert_eq!(error.unwrap().kind(), io::ErrorKind::InvalidInput);
+    }
 }
@@ -6,9 +6,15 @@
 //! This library also provides a way to encrypt and compress the serialized file.
 //!
 //! The encryption is done using [XChaCha20-Poly1305](https://en.wikipedia.org/wiki/ChaCha20-Poly1305#XChaCha20-Poly1305_%E2%80%93_extended_nonce_variant)
-//! and the compression is done using [zlib](https://en.wikipedia.org/wiki/Zlib).
+//! or [AES-256-GCM-SIV](https://en.wikipedia.org/wiki/AES-GCM-SIV)
+//! with a password or [X25519](https://en.wikipedia.org/wiki/Curve25519) recipients,
+//! and the compression is done using [zlib](https://en.wikipedia.org/wiki/Zlib)
+//! or [Zstandard](https://en.wikipedia.org/wiki/Zstd) with a selectable level.
+//! [LZ4](https://en.wikipedia.org/wiki/LZ4_(compression_algorithm)) and [XZ](https://en.wikipedia.org/wiki/XZ_Utils)
+//! are available with the `lz4` and `xz` features.
 //!
 //! It also saves [MD5](md5) checksums when serializing files and verify it when deserializing file for data integrity.
+//! The serialized file can be signed with an [Ed25519](https://en.wikipedia.org/wiki/EdDSA#Ed25519) key to prove who produced it.
 //!
 //! ## Usage
 //!
@@ -63,16 +69,25 @@ mod binary;
 mod compress;
 mod encrypt;
 mod serialize;
+mod sign;
 
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::{self, Read};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
+pub use compress::Codec;
+pub use encrypt::keyslot::{CredentialKind, Identity, Recipient};
+pub use encrypt::{Argon2Params, Argon2Variant, Cipher};
 pub use serialize::deserializer::Deserializer;
+pub use serialize::entry::EntryInfo;
+pub use serialize::extended::ExtendedMetaData;
 use serialize::header::{FILE_LABEL, VERSION_START_POINTER};
-pub use serialize::option::SerializeOption;
+pub use serialize::option::{
+    DirectoryEntries, EntryCompression, HiddenFiles, Ownership, SerializeOption, SignaturePlacement,
+};
 pub use serialize::serializer::Serializer;
 pub use serialize::version;
+pub use sign::{SigningKey, VerifyingKey};
 
 /// Reads the version of the serialized file.
 /// # Errors
@@ -99,12 +114,10 @@ pub use serialize::version;
 /// ```
 pub fn read_version<T: AsRef<Path>>(filepath: T) -> io::Result<version::Version> {
     let mut file = File::open(filepath)?;
-    let mut buffer: Vec<u8> = Vec::with_capacity(FILE_LABEL.len());
-    buffer.resize(FILE_LABEL.len(), 0);
-    file.read(&mut buffer)?;
-    let mut version_buffer: Vec<u8> = Vec::with_capacity(4);
-    version_buffer.resize(4, 0);
-    file.read(&mut version_buffer)?;
+    let mut buffer: Vec<u8> = vec![0; FILE_LABEL.len()];
+    file.read_exact(&mut buffer)?;
+    let mut version_buffer: Vec<u8> = vec![0; 4];
+    file.read_exact(&mut version_buffer)?;
     if version_buffer[0] != VERSION_START_POINTER {
         return Err(io::Error::new(
             io::ErrorKind::InvalidData,
@@ -115,6 +128,143 @@ pub fn read_version<T: AsRef<Path>>(filepath: T) -> io::Result<version::Version>
     Ok(version)
 }
 
+/// Lists all entries of the serialized file without restoring them.
+///
+/// This is a shortcut of [`Deserializer::list`].
+/// # Errors
+/// This function will return an error if the file is not a serialized file or its data is invalid.
+/// # Examples
+/// ```rust
+/// use std::path::PathBuf;
+/// use lusl::{list_entries, Serializer};
+///
+/// let original = PathBuf::from("tests");
+/// let result = PathBuf::from("list_entries_example.bin");
+/// let mut serializer = Serializer::new(&original, &result).unwrap();
+/// serializer.serialize().unwrap();
+///
+/// let entries = list_entries(&result).unwrap();
+/// assert_eq!(entries.len(), 10);
+/// # std::fs::remove_file(result).unwrap();
+/// ```
+pub fn list_entries<T: AsRef<Path>>(filepath: T) -> io::Result<Vec<EntryInfo>> {
+    let mut deserializer = Deserializer::new(filepath.as_ref(), Path::new(""))?;
+    deserializer.list()
+}
+
+/// Verifies the Ed25519 signature of the serialized file with the public key.
+///
+/// The signature embedded at the end of the file is verified if there is,
+/// otherwise the detached signature in the file whose path is the path of the serialized file with `.sig` appended.
+/// # Errors
+/// This function will return an error if the file is not signed, or the signature is not valid for the file and the key.
+/// # Examples
+/// ```rust
+/// use std::path::PathBuf;
+/// use lusl::{verify_signature, SerializeOption, Serializer, SigningKey};
+///
+/// let signing_key = SigningKey::generate();
+/// let original = PathBuf::from("tests");
+/// let result = PathBuf::from("verify_signature_example.bin");
+/// let mut serializer = Serializer::new(&original, &result).unwrap();
+/// serializer.set_option(SerializeOption::new().to_sign(&signing_key));
+/// serializer.serialize().unwrap();
+///
+/// verify_signature(&result, &signing_key.verifying_key()).unwrap();
+/// assert!(verify_signature(&result, &SigningKey::generate().verifying_key()).is_err());
+/// # std::fs::remove_file(result).unwrap();
+/// ```
+pub fn verify_signature<T: AsRef<Path>>(filepath: T, public_key: &VerifyingKey) -> io::Result<()> {
+    sign::verify_file(filepath, public_key)
+}
+
+/// Changes the passwords and recipients of an encrypted file, without re-encrypting its data.
+///
+/// The data key is unwrapped by a password or an identity of the old option,
+/// and the key slots are replaced by new ones for every password and recipient of the new option.
+/// Only the key slots, the end marker and the index are rewritten, and the data of the entries is copied as it is.
+/// So the passwords and recipients which are not in the new option cannot decrypt the file anymore.
+/// A signature does not sign the rekeyed file, so a signed file is only rekeyed if the new option has a signing key.
+/// The rekeyed file is signed with the key of the new option where its signature placement is set to,
+/// before it replaces the file. The old detached signature is removed only after that.
+/// # Errors
+/// This function will return an error if the file is not encrypted, was serialized with a version older than 2.8.0,
+/// none of the passwords and identities of the old option can decrypt it,
+/// or it is signed and the new option has no signing key.
+/// # Examples
+/// ```rust
+/// use std::path::PathBuf;
+/// use lusl::{rekey, Deserializer, SerializeOption, Serializer};
+///
+/// let original = PathBuf::from("tests");
+/// let result = PathBuf::from("rekey_example.bin");
+/// let mut serializer = Serializer::new(&original, &result).unwrap();
+/// serializer.set_option(SerializeOption::new().to_encrypt("old_password"));
+/// serializer.serialize().unwrap();
+///
+/// rekey(
+///     &result,
+///     &SerializeOption::new().to_encrypt("old_password"),
+///     &SerializeOption::new().to_encrypt("new_password"),
+/// )
+/// .unwrap();
+///
+/// let mut deserializer = Deserializer::new(&result, &PathBuf::from("rekey_example")).unwrap();
+/// deserializer.set_option(SerializeOption::new().to_encrypt("new_password"));
+/// assert_eq!(deserializer.read_index().unwrap().len(), 10);
+/// # std::fs::remove_file(result).unwrap();
+/// ```
+pub fn rekey<T: AsRef<Path>>(
+    filepath: T,
+    old_option: &SerializeOption,
+    new_option: &SerializeOption,
+) -> io::Result<()> {
+    let filepath = filepath.as_ref();
+    if new_option.signing_key().is_none() && sign::is_signed(filepath)? {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "The file is signed, and its signature would not sign the rekeyed file. Set a signing key to the new option.",
+        ));
+    }
+    let mut rekeyed = filepath.as_os_str().to_owned();
+    rekeyed.push(".rekey");
+    let rekeyed = PathBuf::from(rekeyed);
+    let rekeyed_signature = sign::detached_signature_path(&rekeyed);
+    if rekeyed_signature.is_file() {
+        fs::remove_file(&rekeyed_signature)?;
+    }
+    // Sign the rekeyed file before it replaces the original, so the original is kept if signing fails.
+    let result = {
+        let mut deserializer = Deserializer::new(filepath, Path::new(""))?;
+        deserializer.set_option(old_option.clone());
+        deserializer.rekey(new_option, &rekeyed)
+    }
+    .and_then(|_| match new_option.signing_key() {
+        Some(key) => sign::sign_file(&rekeyed, key, new_option.signature_placement_mode()),
+        None => Ok(()),
+    });
+    match result {
+        Ok(_) => {
+            fs::rename(&rekeyed, filepath)?;
+            let detached = sign::detached_signature_path(filepath);
+            if rekeyed_signature.is_file() {
+                fs::rename(rekeyed_signature, detached)?;
+            } else if detached.is_file() {
+                fs::remove_file(detached)?;
+            }
+            Ok(())
+        }
+        Err(e) => {
+            for path in [rekeyed, rekeyed_signature] {
+                if path.is_file() {
+                    fs::remove_file(path)?;
+                }
+            }
+            Err(e)
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::serialize::version::{get_major_version, get_minor_version, get_patch_version};
@@ -144,4 +294,120 @@ mod tests {
         // delete the file.
         fs::remove_file(&result).unwrap();
     }
+
+    #[test]
+    fn rekey_test() {
+        let original = PathBuf::from("tests");
+        let result = PathBuf::from("rekey_test.bin");
+        let identity = Identity::generate();
+        for encrypt_metadata in [false, true] {
+            let old_option = SerializeOption::new()
+                .to_encrypt("team_a_password")
+                .to_encrypt("team_b_password")
+                .to_compress(true)
+                .to_encrypt_metadata(encrypt_metadata);
+            let mut serializer = Serializer::new(&original, &result).unwrap();
+            serializer.set_option(old_option.clone());
+            serializer.serialize().unwrap();
+
+            // Revoke team b and add a recipient.
+            let new_option = SerializeOption::new()
+                .to_encrypt("team_a_password")
+                .to_recipient(&identity.recipient());
+            rekey(&result, &old_option, &new_option).unwrap();
+
+            let restored = PathBuf::from("rekey_test_dir");
+            let mut deserializer = Deserializer::new(&result, &restored).unwrap();
+            deserializer.set_option(
+                SerializeOption::new()
+                    .to_encrypt("team_b_password")
+                    .to_compress(true),
+            );
+            assert_eq!(
+                deserializer.deserialize().unwrap_err().kind(),
+                io::ErrorKind::InvalidData
+            );
+            deserializer.set_option(SerializeOption::new().to_identity(&identity));
+            assert_eq!(deserializer.read_index().unwrap().len(), 10);
+            deserializer.set_option(
+                SerializeOption::new()
+                    .to_encrypt("team_a_password")
+                    .to_compress(true),
+            );
+            deserializer.deserialize().unwrap();
+            assert_eq!(
+                fs::read(restored.join("tests/original_images/dir1/폭발.jpg")).unwrap(),
+                fs::read("tests/original_images/dir1/폭발.jpg").unwrap()
+            );
+            fs::remove_dir_all(restored).unwrap();
+        }
+
+        let mut serializer = Serializer::new(&original, &result).unwrap();
+        serializer.serialize().unwrap();
+        let error = rekey(
+            &result,
+            &SerializeOption::default(),
+            &SerializeOption::default(),
+        );
+        assert_eq!(error.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        fs::remove_file(&result).unwrap();
+    }
+
+    #[test]
+    fn rekey_signed_test() {
+        let original = PathBuf::from("tests");
+        let result = PathBuf::from("rekey_signed_test.bin");
+        let detached = sign::detached_signature_path(&result);
+        let old_key = SigningKey::generate();
+        let new_key = SigningKey::generate();
+        let old_option = SerializeOption::new().to_encrypt("old_password");
+        for placement in [SignaturePlacement::Embedded, SignaturePlacement::Detached] {
+            let mut serializer = Serializer::new(&original, &result).unwrap();
+            serializer.set_option(
+                old_option
+                    .clone()
+                    .to_sign(&old_key)
+                    .to_signature_placement(placement),
+            );
+            serializer.serialize().unwrap();
+            let signed = fs::read(&result).unwrap();
+
+            // The signature would not sign the rekeyed file, so the file is left as it is.
+            let new_option = SerializeOption::new().to_encrypt("new_password");
+            let error = rekey(&result, &old_option, &new_option).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+            assert_eq!(fs::read(&result).unwrap(), signed);
+            verify_signature(&result, &old_key.verifying_key()).unwrap();
+
+            // Both placements are re-signed with the new key in the other placement.
+            let new_placement = match placement {
+                SignaturePlacement::Embedded => SignaturePlacement::Detached,
+                SignaturePlacement::Detached => SignaturePlacement::Embedded,
+            };
+            let new_option = new_option
+                .to_sign(&new_key)
+                .to_signature_placement(new_placement);
+            rekey(&result, &old_option, &new_option).unwrap();
+            assert_eq!(
+                detached.is_file(),
+                new_placement == SignaturePlacement::Detached
+            );
+            assert!(!PathBuf::from("rekey_signed_test.bin.rekey").exists());
+            assert!(!PathBuf::from("rekey_signed_test.bin.rekey.sig").exists());
+            verify_signature(&result, &new_key.verifying_key()).unwrap();
+            assert!(verify_signature(&result, &old_key.verifying_key()).is_err());
+
+            let mut deserializer = Deserializer::new(&result, &PathBuf::new()).unwrap();
+            deserializer.set_option(
+                SerializeOption::new()
+                    .to_encrypt("new_password")
+                    .to_verify(&new_key.verifying_key()),
+            );
+            assert_eq!(deserializer.read_index().unwrap().len(), 10);
+            if detached.is_file() {
+                fs::remove_file(&detached).unwrap();
+            }
+        }
+        fs::remove_file(&result).unwrap();
+    }
 }
@@ -1,23 +1,46 @@
 use std::{
-    collections::VecDeque,
+    collections::{HashSet, VecDeque},
     fs::{self, File, OpenOptions},
-    io::{self, BufRead, BufReader, BufWriter, Write},
-    path::{Path, PathBuf},
+    io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Component, Path, PathBuf},
     sync::mpsc::Sender,
 };
 
+use chacha20poly1305::aead::Payload;
+
 use crate::{
-    binary::verify_checksum,
-    compress::{decompress, TEMP_COMPRESSED_FILE_PATH},
-    encrypt::{make_decryptor, make_key_from_password_and_salt, NONCE_LENGTH, SALT_LENGTH},
+    binary::{
+        binary_to_u64, compare_checksum, get_bytes_checksum, verify_checksum, ChecksumWriter,
+        RangeWriter,
+    },
+    compress::make_decoder,
+    encrypt::{
+        decrypted_size, encrypted_size,
+        keyslot::{read_keyfile, CredentialKind, KeySlot},
+        last_chunk_aad, make_key_from_password_and_salt, Argon2Params, Cipher, SecretKey,
+        Transcript, SALT_LENGTH, TAG_LENGTH,
+    },
+    sign::{signed_length, verify_file},
 };
 
 use super::{
-    header::Header,
-    option::SerializeOption,
+    entry::EntryInfo,
+    extended::is_root,
+    header::{
+        Header, AUTHENTICATION_MIN_VERSION, EXTENDED_METADATA_MIN_VERSION,
+        HIDDEN_FILES_MIN_VERSION, INDEX_MIN_VERSION, KEY_SLOTS_MIN_VERSION,
+        METADATA_ENCRYPTION_MIN_VERSION,
+    },
+    index::{Trailer, OFFSET_LENGTH, TRAILER_LENGTH},
+    make_key_slots,
+    option::{HiddenFiles, Ownership, SerializeOption},
     version::{get_major_version, get_minor_version},
 };
-use super::{header::FILE_LABEL, meta::MetaData, BUFFER_LENGTH};
+use super::{
+    header::FILE_LABEL,
+    meta::{Compression, MetaData},
+    BUFFER_LENGTH,
+};
 
 /// # Deserializer
 ///
@@ -44,6 +67,7 @@ use super::{header::FILE_LABEL, meta::MetaData, BUFFER_LENGTH};
 /// ```
 pub struct Deserializer {
     serialized_file: BufReader<File>,
+    serialized_path: PathBuf,
     buffer: VecDeque<u8>,
     restore_path: PathBuf,
     option: SerializeOption,
@@ -54,7 +78,7 @@ impl Deserializer {
     /// Set serialized data file path and restored file path.
     pub fn new<T: AsRef<Path>>(serialized_file: T, restore_path: T) -> io::Result<Self> {
         let serialized_file_path = serialized_file.as_ref().to_path_buf();
-        if let false = serialized_file_path.is_file() {
+        if !serialized_file_path.is_file() {
             return Err(io::Error::new(
                 io::ErrorKind::AlreadyExists,
                 "File doesn't exists!",
@@ -63,8 +87,9 @@ impl Deserializer {
         Ok(Deserializer {
             serialized_file: BufReader::with_capacity(
                 BUFFER_LENGTH,
-                File::open(serialized_file_path)?,
+                File::open(&serialized_file_path)?,
             ),
+            serialized_path: serialized_file_path,
             buffer: VecDeque::with_capacity(BUFFER_LENGTH + 16),
             restore_path: restore_path.as_ref().to_path_buf(),
             option: SerializeOption::default(),
@@ -84,10 +109,12 @@ impl Deserializer {
     }
 
     fn fill_buf(&mut self) -> io::Result<usize> {
-        self.buffer.append(&mut VecDeque::from_iter(
-            self.serialized_file.fill_buf()?.to_vec(),
-        ));
-        self.serialized_file.consume(self.buffer.len());
+        let length = {
+            let buffer = self.serialized_file.fill_buf()?;
+            self.buffer.extend(buffer);
+            buffer.len()
+        };
+        self.serialized_file.consume(length);
         Ok(self.buffer.len())
     }
 
@@ -101,6 +128,232 @@ impl Deserializer {
         }
         Ok(self.buffer.drain(..length).collect())
     }
+
+    /// Same as `fill_buf_with_len`, but occur error if the file ends before the given length.
+    fn fill_buf_with_exact_len(&mut self, length: usize) -> io::Result<Vec<u8>> {
+        let binary = self.fill_buf_with_len(length)?;
+        if binary.len() < length {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "The serialized file ends unexpectedly.",
+            ));
+        }
+        Ok(binary)
+    }
+
+    /// Returns the current reading position from the start of the serialized file.
+    fn position(&mut self) -> io::Result<u64> {
+        Ok(self.serialized_file.stream_position()? - self.buffer.len() as u64)
+    }
+
+    /// Move the reading position and discard buffered data.
+    fn seek_to(&mut self, position: u64) -> io::Result<()> {
+        self.serialized_file.seek(SeekFrom::Start(position))?;
+        self.buffer.clear();
+        Ok(())
+    }
+
+    /// Move the reading position forward, without reading the skipped data.
+    fn skip(&mut self, length: u64) -> io::Result<()> {
+        if length <= self.buffer.len() as u64 {
+            self.buffer.drain(..length as usize);
+            Ok(())
+        } else {
+            let position = self.position()?;
+            self.seek_to(position + length)
+        }
+    }
+
+    /// Skip the data of an entry whose metadata was just read.
+    ///
+    /// If the entry is the last entry of a solid block, the block is skipped.
+    /// Returns the size of the compressed data if the entry is compressed by itself.
+    fn skip_entry_data(&mut self, header: &Header, metadata: &MetaData) -> io::Result<Option<u64>> {
+        if !metadata.has_data() {
+            return Ok(None);
+        }
+        let compression = entry_compression(header, metadata);
+        let compressed_size = match compression {
+            Compression::Stored => None,
+            Compression::Solid { is_last: false, .. } => return Ok(None),
+            _ => Some(binary_to_u64(&self.fill_buf_with_exact_len(8)?)),
+        };
+        let stored_size = compressed_size.unwrap_or(metadata.size());
+        match header.is_encrypted() {
+            true => self
+                .skip(header.cipher().stream_nonce_length() as u64 + encrypted_size(stored_size))?,
+            false => self.skip(stored_size)?,
+        }
+        match compression {
+            Compression::Compressed => Ok(compressed_size),
+            _ => Ok(None),
+        }
+    }
+
+    /// List all entries of the serialized file without restoring them.
+    ///
+    /// If the file has an entry index, only the index is read.
+    /// Otherwise, only the metadata of the entries are read, and the data of them are skipped.
+    /// Nothing is written to the restore path.
+    /// No password is required even if the file is encrypted, unless its metadata is encrypted as well.
+    ///
+    /// # Errors
+    /// - Wrong file format or data.
+    ///
+    /// # Examples
+    /// ```
+    /// use lusl::{Serializer, Deserializer, SerializeOption};
+    /// use std::path::PathBuf;
+    /// let original = PathBuf::from("tests");
+    /// let result = PathBuf::from("list_example.bin");
+    /// let mut serializer = Serializer::new(&original, &result).unwrap();
+    /// serializer.set_option(SerializeOption::new().to_compress(true));
+    /// serializer.serialize().unwrap();
+    ///
+    /// let restored = PathBuf::from("list_example_dir");
+    /// let mut deserializer = Deserializer::new(&result, &restored).unwrap();
+    /// let entries = deserializer.list().unwrap();
+    /// assert_eq!(entries.len(), 10);
+    /// assert!(entries.iter().all(|e| e.is_file() && e.compressed_size().is_some()));
+    /// assert!(!restored.exists());
+    /// # std::fs::remove_file(result).unwrap();
+    /// ```
+    pub fn list(&mut self) -> io::Result<Vec<EntryInfo>> {
+        self.seek_to(0)?;
+        let mut header = self.read_header()?;
+        let key = match header.is_metadata_encrypted() {
+            true => Some(self.read_key(&mut header)?),
+            false => None,
+        };
+        if header.is_indexed() {
+            return self.read_index_entries(&header, key.as_deref());
+        }
+        if key.is_none() {
+            if header.has_key_slots() {
+                self.read_key_slots(header.cipher())?;
+            } else if header.is_encrypted() {
+                self.skip(SALT_LENGTH as u64)?;
+            }
+        }
+        let mut entries = Vec::new();
+        for _ in 0..header.file_count() {
+            let offset = self.position()?;
+            let metadata = self.read_metadata(&header, key.as_deref())?;
+            let compressed_size = self.skip_entry_data(&header, &metadata)?;
+            entries.push(EntryInfo::new(offset, metadata, compressed_size));
+        }
+        Ok(entries)
+    }
+
+    /// Read the entry index at the end of the serialized file.
+    ///
+    /// Only the header, the trailer and the index are read, so it doesn't need to walk the whole file.
+    ///
+    /// # Errors
+    /// - Wrong file format or data.
+    /// - The file was serialized without an index, by a library version older than 2.2.0.
+    /// - The metadata is encrypted and the password is wrong or not given.
+    ///
+    /// # Examples
+    /// ```
+    /// use lusl::{Serializer, Deserializer};
+    /// use std::path::PathBuf;
+    /// let original = PathBuf::from("tests");
+    /// let result = PathBuf::from("read_index_example.bin");
+    /// let mut serializer = Serializer::new(&original, &result).unwrap();
+    /// serializer.serialize().unwrap();
+    ///
+    /// let mut deserializer = Deserializer::new(&result, &PathBuf::from("read_index_example")).unwrap();
+    /// let index = deserializer.read_index().unwrap();
+    /// assert!(index.iter().any(|e| e.path().ends_with("폭발.jpg")));
+    /// # std::fs::remove_file(result).unwrap();
+    /// ```
+    pub fn read_index(&mut self) -> io::Result<Vec<EntryInfo>> {
+        self.seek_to(0)?;
+        let mut header = self.read_header()?;
+        let key = match header.is_metadata_encrypted() {
+            true => Some(self.read_key(&mut header)?),
+            false => None,
+        };
+        self.read_index_entries(&header, key.as_deref())
+    }
+
+    /// Read the entry index, with the header and the key which are already read.
+    ///
+    /// The sizes of the compressed entries are worked out from the offsets of the entries after them.
+    fn read_index_entries(
+        &mut self,
+        header: &Header,
+        key: Option<&[u8]>,
+    ) -> io::Result<Vec<EntryInfo>> {
+        if !header.is_indexed() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "The file has no entry index. It was serialized with a library version older than {}.",
+                    INDEX_MIN_VERSION
+                ),
+            ));
+        }
+        let trailer = self.read_trailer()?;
+        self.seek_to(trailer.index_offset())?;
+        // If the metadata is encrypted, the entry count is hidden from the trailer.
+        let entry_count = match header.is_metadata_encrypted() {
+            true => header.file_count(),
+            false => trailer.entry_count(),
+        };
+        let mut index = Vec::new();
+        for _ in 0..entry_count {
+            let offset = binary_to_u64(&self.fill_buf_with_exact_len(OFFSET_LENGTH)?);
+            let metadata = self.read_metadata(header, key)?;
+            index.push(EntryInfo::new(offset, metadata, None));
+        }
+        // The end marker of an authenticated file lies between the last entry and the index.
+        let end_of_entries = match make_transcript(header) {
+            Some(_) => trailer
+                .index_offset()
+                .saturating_sub(header.cipher().sealed_size(8) as u64),
+            None => trailer.index_offset(),
+        };
+        set_compressed_sizes(header, &mut index, end_of_entries);
+        Ok(index)
+    }
+
+    /// Read the trailer at the end of an indexed file, in front of the embedded signature if there is.
+    fn read_trailer(&mut self) -> io::Result<Trailer> {
+        let file_length = signed_length(&mut self.serialized_file)?;
+        if file_length < TRAILER_LENGTH as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "The serialized file is too short to have an index trailer.",
+            ));
+        }
+        self.seek_to(file_length - TRAILER_LENGTH as u64)?;
+        Trailer::from_binary(&self.fill_buf_with_exact_len(TRAILER_LENGTH)?)
+    }
+
+    /// Check there is nothing left after the last entry except the index.
+    fn verify_end(&mut self, header: &Header) -> io::Result<()> {
+        let is_valid = match header.is_indexed() {
+            true => {
+                let end_of_entries = self.position()?;
+                let trailer = self.read_trailer()?;
+                let entry_count = match header.is_metadata_encrypted() {
+                    true => 0,
+                    false => header.file_count(),
+                };
+                trailer.index_offset() == end_of_entries && trailer.entry_count() == entry_count
+            }
+            false => self.buffer.is_empty() && self.fill_buf()? == 0,
+        };
+        if !is_valid {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Number of files is different with the original directory!",
+            ));
+        }
+        Ok(())
+    }
     /// Deserialize data file to directory.
     ///
     /// If the file encrypted, deserializing with given password which is in the option.
@@ -111,498 +364,2782 @@ impl Deserializer {
     /// - Wrong file format or data.
     /// - MD5 checksum of deserialized file is different from original checksum.
     /// - Wrong password.
+    /// - The file is encrypted and has been tampered with. Everything restored from it is removed.
+    /// - The verifying key of the option is set, and the file is not signed or its signature is not valid.
     pub fn deserialize(&mut self) -> io::Result<()> {
-        let header = self.verify_header()?;
-        let original_file_count = header.file_count();
+        self.verify_signature()?;
+        self.seek_to(0)?;
+        let mut header = self.verify_header()?;
+        self.report_hidden_files(header.hidden_files());
         match header.is_encrypted() {
-            true => self.deserialize_with_decrypt(
-                &match self.option.password() {
-                    Some(p) => p,
-                    None => {
-                        return Err(io::Error::new(
-                            io::ErrorKind::NotFound,
-                            "This file is encrypted but there is no password input.",
-                        ))
-                    }
-                },
-                original_file_count,
-            )?,
-            false => self.deserialize_raw(original_file_count)?,
+            true => {
+                let key = self.read_key(&mut header)?;
+                self.deserialize_with_decrypt(&header, &key)?
+            }
+            false => self.deserialize_raw(&header)?,
         }
+        self.verify_end(&header)?;
         Ok(())
     }
 
-    fn send_progress(&self, message: &str) {
-        if let Some(ref tx) = self.sender {
-            tx.send(message.to_string()).unwrap();
-        }
+    /// Read how hidden files and directories were handled when serializing.
+    ///
+    /// Hidden files and directories excluded by the mode are not in the serialized file.
+    ///
+    /// # Errors
+    /// - Wrong file format or data.
+    pub fn hidden_files(&mut self) -> io::Result<HiddenFiles> {
+        self.seek_to(0)?;
+        Ok(self.read_header()?.hidden_files())
     }
 
-    fn deserialize_raw(&mut self, original_file_count: u64) -> io::Result<()> {
-        let mut current_file_count: u64 = 0;
-        loop {
-            let metadata = self.read_metadata()?;
-
-            // Write file
-            let file_path = self.restore_path.join(&metadata.path());
-            fs::create_dir_all(self.restore_path.join(&metadata.path()).parent().unwrap()).unwrap();
-            File::create(self.restore_path.join(&metadata.path()))?;
-            match self.option.is_compressed() {
-                true => {
-                    let mut compressed_size = 0u64;
-                    let t = self.fill_buf_with_len(8)?;
-                    compressed_size += t[0] as u64 * 0x1;
-                    compressed_size += t[1] as u64 * 0x100;
-                    compressed_size += t[2] as u64 * 0x10000;
-                    compressed_size += t[3] as u64 * 0x1000000;
-                    let temp_file = PathBuf::from(TEMP_COMPRESSED_FILE_PATH)
-                        .join(metadata.path().file_name().unwrap());
-                    self.write_raw_file(&temp_file, compressed_size as usize)?;
-                    let a = decompress(&temp_file, TEMP_COMPRESSED_FILE_PATH)?;
-                    fs::rename(a, &file_path)?;
-                }
-                false => {
-                    self.write_raw_file(&file_path, metadata.size() as usize)?;
-                }
+    fn report_hidden_files(&self, hidden_files: HiddenFiles) {
+        match hidden_files {
+            HiddenFiles::Include => (),
+            HiddenFiles::SkipFiles => {
+                self.send_progress("Hidden files were excluded when serializing.")
+            }
+            HiddenFiles::SkipAll => {
+                self.send_progress("Hidden files and directories were excluded when serializing.")
             }
+        }
+    }
 
-            // Verify checksum
-            verify_checksum(metadata, &file_path)?;
+    /// Verify the signature of the serialized file, if the verifying key of the option is set.
+    fn verify_signature(&self) -> io::Result<()> {
+        match self.option.verifying_key() {
+            Some(key) => verify_file(&self.serialized_path, key),
+            None => Ok(()),
+        }
+    }
 
-            // Count file.
-            current_file_count += 1;
+    /// Read which kinds of credentials can decrypt the serialized file, which are recorded in its key slots.
+    ///
+    /// Returns an empty list if the file is not encrypted.
+    /// A file serialized with a version older than 2.8.0 can be decrypted only with a password.
+    ///
+    /// # Errors
+    /// - Wrong file format or data.
+    ///
+    /// # Examples
+    /// ```
+    /// use lusl::{CredentialKind, Deserializer, SerializeOption, Serializer};
+    /// use std::path::PathBuf;
+    /// let original = PathBuf::from("tests");
+    /// let result = PathBuf::from("credential_kinds_example.bin");
+    /// let mut serializer = Serializer::new(&original, &result).unwrap();
+    /// serializer.set_option(SerializeOption::new().to_encrypt_with_key(&[7u8; 32]));
+    /// serializer.serialize().unwrap();
+    ///
+    /// let mut deserializer = Deserializer::new(&result, &PathBuf::from("credential_kinds_example")).unwrap();
+    /// assert_eq!(deserializer.credential_kinds().unwrap(), vec![CredentialKind::Key]);
+    /// # std::fs::remove_file(result).unwrap();
+    /// ```
+    pub fn credential_kinds(&mut self) -> io::Result<Vec<CredentialKind>> {
+        self.seek_to(0)?;
+        let header = self.read_header()?;
+        if !header.is_encrypted() {
+            return Ok(Vec::new());
+        }
+        if !header.has_key_slots() {
+            return Ok(vec![CredentialKind::Password]);
+        }
+        let slots = self.read_key_slots(header.cipher())?;
+        Ok(slot_credential_kinds(&slots))
+    }
 
-            // Send progress.
-            self.send_progress(&format!(
-                "Deserializing... {} / {}    {}",
-                current_file_count,
-                original_file_count,
-                &file_path.to_str().unwrap()
+    /// Verify the option has a credential of one of the required kinds.
+    fn check_credential_kinds(&self, required: &[CredentialKind]) -> io::Result<()> {
+        let given = self.option.credential_kinds();
+        if given.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "This file is encrypted with {}, but there is no credential input.",
+                    join_kinds(required, " or ")
+                ),
             ));
-
-            // EOF.
-            if self.buffer.len() == 0 {
-                if self.fill_buf()? == 0 {
-                    break;
-                } else {
-                    continue;
-                }
-            }
         }
-        if original_file_count != current_file_count {
+        if !required.iter().any(|kind| given.contains(kind)) {
             return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "Number of files is different with the original directory!",
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "This file is encrypted with {}, but only {} is given.",
+                    join_kinds(required, " or "),
+                    join_kinds(&given, " and ")
+                ),
             ));
         }
-        if PathBuf::from(TEMP_COMPRESSED_FILE_PATH).is_dir() {
-            fs::remove_dir_all(TEMP_COMPRESSED_FILE_PATH)?;
-        }
         Ok(())
     }
 
-    fn deserialize_with_decrypt(
-        &mut self,
-        password: &str,
-        original_file_count: u64,
-    ) -> io::Result<()> {
-        let mut current_file_count: u64 = 0;
-        // Read salt and key.
-        let salt = self.fill_buf_with_len(SALT_LENGTH)?;
-        let key = make_key_from_password_and_salt(password, salt);
-
-        loop {
-            let metadata = self.read_metadata()?;
+    /// Returns the password in the option, or occur error if there is no password.
+    fn password(&self) -> io::Result<&str> {
+        match self.option.password() {
+            Some(p) => Ok(p),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "This file is encrypted but there is no password input.",
+            )),
+        }
+    }
 
-            // Write file
-            let file_path = self.restore_path.join(&metadata.path());
-            fs::create_dir_all(self.restore_path.join(&metadata.path()).parent().unwrap()).unwrap();
-            File::create(self.restore_path.join(&metadata.path()))?;
-            match self.option.is_compressed() {
-                true => {
-                    let mut compressed_size = 0u64;
-                    let t = self.fill_buf_with_len(8)?;
-                    compressed_size += t[0] as u64 * 0x1;
-                    compressed_size += t[1] as u64 * 0x100;
-                    compressed_size += t[2] as u64 * 0x10000;
-                    compressed_size += t[3] as u64 * 0x1000000;
-                    let temp_file = PathBuf::from(TEMP_COMPRESSED_FILE_PATH)
-                        .join(metadata.path().file_name().unwrap());
-                    self.write_decrypt_file(&temp_file, compressed_size as usize, &key)?;
-                    let a = decompress(&temp_file, TEMP_COMPRESSED_FILE_PATH)?;
-                    fs::rename(a, &file_path)?;
-                }
-                false => {
-                    self.write_decrypt_file(&file_path, metadata.size() as usize, &key)?;
-                }
+    /// Read the key slots right after the header and unwrap the data key with the passwords or identities in the option.
+    /// If the file has no key slot, the key is made from the first password in the option and the salt after the header.
+    ///
+    /// If the metadata is encrypted, the hidden file count is also read and set to the header.
+    fn read_key(&mut self, header: &mut Header) -> io::Result<SecretKey> {
+        let key = match header.has_key_slots() {
+            true => {
+                let slots = self.read_key_slots(header.cipher())?;
+                self.open_key_slots(&slots, header.cipher())?
+            }
+            false => {
+                self.check_credential_kinds(&[CredentialKind::Password])?;
+                let salt = self.fill_buf_with_exact_len(SALT_LENGTH)?;
+                make_key_from_password_and_salt(self.password()?, salt, &Argon2Params::LEGACY)?
             }
+        };
+        self.read_file_count(header, &key)?;
+        Ok(key)
+    }
 
-            // Verify checksum
-            verify_checksum(metadata, &file_path)?;
+    /// Read the hidden file count after the key slots and set it to the header, if the metadata is encrypted.
+    fn read_file_count(&mut self, header: &mut Header, key: &[u8]) -> io::Result<()> {
+        if header.is_metadata_encrypted() {
+            let cipher = header.cipher();
+            let sealed_count = self.fill_buf_with_exact_len(cipher.sealed_size(8))?;
+            let file_count = cipher
+                .unseal(key, &sealed_count)
+                .ok_or_else(decrypt_error)?;
+            header.set_file_count(binary_to_u64(&file_count));
+        }
+        Ok(())
+    }
 
-            // Count file.
-            current_file_count += 1;
+    /// Read the key slots sealed with the cipher, with the number of them in front.
+    fn read_key_slots(&mut self, cipher: Cipher) -> io::Result<Vec<KeySlot>> {
+        let slot_count = self.fill_buf_with_exact_len(1)?[0];
+        let mut slots = Vec::with_capacity(slot_count as usize);
+        for _ in 0..slot_count {
+            slots.push(KeySlot::read_from(&mut EntryReader(self), cipher)?);
+        }
+        Ok(slots)
+    }
 
-            // Send progress.
-            self.send_progress(&format!(
-                "Deserializing... {} / {}    {}",
-                current_file_count,
-                original_file_count,
-                &file_path.to_str().unwrap()
-            ));
+    /// Read the key slots right after the header as they are serialized.
+    ///
+    /// The reading position is moved.
+    fn read_raw_key_slots(&mut self, cipher: Cipher) -> io::Result<Vec<u8>> {
+        self.seek_to(0)?;
+        self.read_header()?;
+        let start = self.position()?;
+        self.read_key_slots(cipher)?;
+        let length = self.position()? - start;
+        self.seek_to(start)?;
+        self.fill_buf_with_exact_len(length as usize)
+    }
 
-            // EOF.
-            if self.buffer.len() == 0 {
-                if self.fill_buf()? == 0 {
-                    break;
-                } else {
-                    continue;
-                }
+    /// Unwrap the data key from the first key slot which one of the credentials in the option can open.
+    ///
+    /// If none of the credentials is of the kinds recorded in the key slots, occur error which tells the kinds.
+    fn open_key_slots(&self, slots: &[KeySlot], cipher: Cipher) -> io::Result<SecretKey> {
+        self.check_credential_kinds(&slot_credential_kinds(slots))?;
+        let keyfiles = self
+            .option
+            .keyfiles()
+            .iter()
+            .map(read_keyfile)
+            .collect::<io::Result<Vec<_>>>()?;
+        let password_keyfiles = self
+            .option
+            .password_keyfiles()
+            .map(|(password, keyfile)| Ok((password, read_keyfile(keyfile)?)))
+            .collect::<io::Result<Vec<_>>>()?;
+        for slot in slots {
+            let key = match slot.credential_kind() {
+                CredentialKind::Password => self
+                    .option
+                    .passwords()
+                    .find_map(|p| slot.open_with_password(p, cipher)),
+                CredentialKind::Key => self
+                    .option
+                    .keys()
+                    .find_map(|k| slot.open_with_key(k, cipher)),
+                CredentialKind::Keyfile => keyfiles
+                    .iter()
+                    .find_map(|k| slot.open_with_keyfile(k, cipher)),
+                CredentialKind::PasswordAndKeyfile => password_keyfiles
+                    .iter()
+                    .find_map(|(p, k)| slot.open_with_password_and_keyfile(p, k, cipher)),
+                CredentialKind::Identity => self
+                    .option
+                    .identities()
+                    .iter()
+                    .find_map(|i| slot.open_with_identity(i, cipher)),
+            };
+            if let Some(key) = key {
+                return Ok(key);
             }
         }
-        if original_file_count != current_file_count {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "Number of files is different with the original directory!",
-            ));
-        }
-        if PathBuf::from(TEMP_COMPRESSED_FILE_PATH).is_dir() {
-            fs::remove_dir_all(TEMP_COMPRESSED_FILE_PATH)?;
-        }
-        Ok(())
+        Err(decrypt_error())
     }
 
-    fn verify_header(&mut self) -> io::Result<Header> {
-        // Verify label.
-        let mut header = Header::new();
-        header.deserialize_label(&self.fill_buf_with_len(FILE_LABEL.as_bytes().len())?)?;
-
-        // Verify version.
-        header.deserialize_version(&self.fill_buf_with_len(4)?)?;
-        if header.version().major() < get_major_version() {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                format!("The major version of the file is too low. It is a serialized file with an older version of the library. \
-                To deserialize this file, library version {}.x.x is required. \
-                If you want to deserialize this file, Use an older version of the library.", header.version().major()),
-            ));
-        } else if header.version().major() > get_major_version() {
+    /// Write the serialized file to the result with new key slots for the passwords and recipients of the new option.
+    ///
+    /// The data key is unwrapped by the passwords or identities of the option of the deserializer.
+    /// Everything but the key slots, the end marker and the index is copied as it is, without decrypting.
+    /// An embedded signature is dropped, so the result is not signed.
+    pub(crate) fn rekey<T: AsRef<Path>>(
+        &mut self,
+        new_option: &SerializeOption,
+        result: T,
+    ) -> io::Result<()> {
+        self.seek_to(0)?;
+        let mut header = self.read_header()?;
+        if !header.is_encrypted() {
             return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                format!("The major version of the file is too high. It is a serialized file with a newer version of the library. \
-                To deserialize this file, library version {}.{}.x is required. \
-                If you want to deserialize this file, Use a newer version of the library.", header.version().major(), header.version().minor()),
+                io::ErrorKind::InvalidInput,
+                "The file is not encrypted.",
             ));
-        } else if header.version().minor() > get_minor_version() {
+        }
+        if !header.has_key_slots() {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidData,
-                format!("The minor version of the file is too high. \
-                It is a serialized file with a newer version of the library. \
-                To deserialize this file, library version {}.{}.x is required. If you want to deserialize this file, Use a newer version of the library.", header.version().major(), header.version().minor()),
+                format!(
+                    "The file has no key slot. It was serialized with a library version older than {}.",
+                    KEY_SLOTS_MIN_VERSION
+                ),
             ));
         }
+        let slots_offset = self.position()?;
+        let slots = self.read_key_slots(header.cipher())?;
+        let key = self.open_key_slots(&slots, header.cipher())?;
+        let data_offset = self.position()?;
+        self.read_file_count(&mut header, &key)?;
+        // The new key slots are sealed with the cipher of the file, not the one of the new option.
+        let new_slots = make_key_slots(new_option, &key, header.cipher())?;
+        let distance = new_slots.len() as i64 - (data_offset - slots_offset) as i64;
 
-        // Read header flags.
-        header.deserialize_flag(&self.fill_buf_with_len(1)?);
-
-        // Verify header flags.
-        match header.is_compressed() {
-            true => {
-                if !self.option.is_compressed() {
-                    return Err(io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        "The archive is compressed, but the option is not set.",
-                    ));
-                }
-            }
-            false => {
-                if self.option.is_compressed() {
-                    return Err(io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        "The archive is not compressed, but the option is set.",
-                    ));
-                }
-            }
-        }
-        match header.is_encrypted() {
+        let mut writer = BufWriter::new(File::create(result)?);
+        self.seek_to(0)?;
+        self.write_raw_data(&mut writer, slots_offset as usize)?;
+        writer.write_all(&new_slots)?;
+        match header.is_indexed() {
             true => {
-                if !self.option.is_encrypted() {
-                    return Err(io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        "The archive is encrypted, but the option is not set.",
-                    ));
+                let (index, end_marker) = match make_transcript(&header) {
+                    Some(transcript) => {
+                        // The end marker is authenticated with the key slots, so it is sealed again with the new ones.
+                        let key_slots = self.read_raw_key_slots(header.cipher())?;
+                        let (index, transcript) =
+                            self.read_verified_index(&header, &key, transcript, &key_slots)?;
+                        let end_marker = header.cipher().seal_with_aad(
+                            &key,
+                            &header.file_count().to_le_bytes(),
+                            &transcript.end_aad(&new_slots),
+                        );
+                        (index, end_marker)
+                    }
+                    None => (self.read_index_entries(&header, Some(&key))?, Vec::new()),
+                };
+                // The offsets in the index are moved as much as the key slots are resized.
+                let trailer = self.read_trailer()?;
+                let end_of_entries = trailer.index_offset() - end_marker.len() as u64;
+                self.seek_to(data_offset)?;
+                self.write_raw_data(&mut writer, (end_of_entries - data_offset) as usize)?;
+                writer.write_all(&end_marker)?;
+                let metadata_key = header.is_metadata_encrypted().then_some(&*key);
+                for entry in index {
+                    writer.write_all(
+                        &entry
+                            .moved_by(distance)
+                            .serialize(metadata_key, header.cipher()),
+                    )?;
                 }
+                let index_offset = trailer.index_offset().wrapping_add_signed(distance);
+                writer.write_all(
+                    &Trailer::new(index_offset, trailer.entry_count()).to_binary_vec(),
+                )?;
             }
             false => {
-                if self.option.is_encrypted() {
+                if make_transcript(&header).is_some() {
                     return Err(io::Error::new(
                         io::ErrorKind::InvalidData,
-                        "The archive is not encrypted, but the option is set.",
+                        "The end marker of the file cannot be found without the entry index.",
                     ));
                 }
+                // An embedded signature is not copied, because it does not sign the rekeyed file.
+                let signed_length = signed_length(&mut self.serialized_file)?;
+                self.seek_to(data_offset)?;
+                self.write_raw_data(&mut writer, (signed_length - data_offset) as usize)?;
             }
         }
-
-        // Read the number of original files.
-        let original_file_count_bytes = self.fill_buf_with_len(1)?[0];
-        header.deserialize_file_count(&self.fill_buf_with_len(original_file_count_bytes as usize)?);
-
-        Ok(header)
-    }
-
-    fn read_metadata(&mut self) -> io::Result<MetaData> {
-        let mut metadata = MetaData::new();
-
-        // Restore file path
-        let path_size_bin = self.fill_buf_with_len(2)?;
-        let path_size = path_size_bin[0] as usize * 0x100 + path_size_bin[1] as usize;
-        metadata.deserialize_path(&self.fill_buf_with_len(path_size)?);
-
-        // Restore file type
-        let flag_and_byte_count = self.fill_buf_with_len(1)?[0];
-        metadata.deserialize_type(flag_and_byte_count);
-
-        // Restore file size
-        let size_count = (flag_and_byte_count & 0xF) as usize;
-        metadata.deserialize_size(&self.fill_buf_with_len(size_count)?);
-
-        // Restore checksum
-        metadata.deserialize_checksum(&self.fill_buf_with_len(16)?);
-
-        Ok(metadata)
+        writer.flush()
     }
 
-    fn write_raw_file<T: AsRef<Path>>(
+    /// Restore only one entry whose path is matched with the given path to the destination file.
+    ///
+    /// The path of an entry is relative to the parent of the serialized root,
+    /// as [`EntryInfo::path`] returns.
+    /// See [`Deserializer::extract_entry_to_writer`] for more information.
+    ///
+    /// # Errors
+    /// - There is no entry with the given path.
+    /// - Wrong file format or data.
+    /// - MD5 checksum of restored data is different from original checksum.
+    /// - Wrong password.
+    /// - The verifying key of the option is set, and the file is not signed or its signature is not valid.
+    ///
+    /// # Examples
+    /// ```
+    /// use lusl::{Serializer, Deserializer};
+    /// use std::path::PathBuf;
+    /// let original = PathBuf::from("tests");
+    /// let result = PathBuf::from("extract_entry_example.bin");
+    /// let mut serializer = Serializer::new(&original, &result).unwrap();
+    /// serializer.serialize().unwrap();
+    ///
+    /// let mut deserializer = Deserializer::new(&result, &PathBuf::from("extract_entry_example")).unwrap();
+    /// let destination = PathBuf::from("extract_entry_example.jpg");
+    /// deserializer
+    ///     .extract_entry("tests/original_images/dir1/폭발.jpg", &destination)
+    ///     .unwrap();
+    /// assert_eq!(destination.metadata().unwrap().len(), 562560);
+    /// # std::fs::remove_file(result).unwrap();
+    /// # std::fs::remove_file(destination).unwrap();
+    /// ```
+    pub fn extract_entry<P: AsRef<Path>, D: AsRef<Path>>(
         &mut self,
-        restored_file_path: T,
-        size: usize,
+        path: P,
+        destination: D,
     ) -> io::Result<()> {
-        match restored_file_path.as_ref().parent() {
-            Some(p) => fs::create_dir_all(p)?,
-            None => (),
-        }
-        let mut file = BufWriter::new(
-            OpenOptions::new()
-                .create(true)
-                .write(true)
-                .open(&restored_file_path)?,
-        );
-        let mut counter = 0;
-        loop {
-            counter += self.fill_buf()?;
-            if counter > size {
-                if size > self.buffer.len() {
-                    file.write(
-                        &Vec::from(self.buffer.clone())[..self.buffer.len() - (counter - size)],
-                    )?;
-                    self.buffer.drain(..self.buffer.len() - (counter - size));
-                } else {
-                    file.write(&Vec::from(self.buffer.clone())[..size])?;
-                    self.buffer.drain(..size);
-                }
-                file.flush()?;
-                break;
+        let path = path.as_ref();
+        let (header, key, metadata, position) = self.find_entry(path)?;
+        match (metadata.is_dir(), metadata.is_symlink()) {
+            (true, _) => {
+                fs::create_dir_all(&destination)?;
+                self.restore_extended(&metadata, &destination)?;
             }
-
-            file.write(&Vec::from(self.buffer.clone()))?;
-            self.buffer.clear();
-            if counter == size {
+            (_, true) => self.restore_symlink(&metadata, &destination)?,
+            _ => {
+                let mut file = create_restored_file(&destination)?;
+                self.write_entry_data(&header, &metadata, key.as_deref(), position, &mut file)?;
                 file.flush()?;
-                break;
+                drop(file);
+                self.restore_extended(&metadata, &destination)?;
             }
         }
+        self.send_progress(&format!("Extracting complete: {}", path.to_str().unwrap()));
         Ok(())
     }
 
-    fn write_decrypt_file<T: AsRef<Path>>(
+    /// Restore only one entry whose path is matched with the given path into the writer.
+    ///
+    /// If the file has an entry index, the entry is found by the index.
+    /// Otherwise, the metadata of entries are read in order and the data of other entries are skipped.
+    /// The [MD5](md5) checksum of the restored data is verified.
+    ///
+    /// # Errors
+    /// - There is no entry with the given path.
+    /// - Wrong file format or data.
+    /// - MD5 checksum of restored data is different from original checksum.
+    /// - Wrong password.
+    /// - The verifying key of the option is set, and the file is not signed or its signature is not valid.
+    /// - The entry is a symbolic link or a directory, which has no data.
+    pub fn extract_entry_to_writer<P: AsRef<Path>, W: Write>(
         &mut self,
-        restored_file_path: T,
-        mut size: usize,
-        key: &[u8],
+        path: P,
+        mut writer: W,
     ) -> io::Result<()> {
-        match restored_file_path.as_ref().parent() {
-            Some(p) => fs::create_dir_all(p)?,
-            None => (),
-        }
-        let mut file = BufWriter::with_capacity(
-            BUFFER_LENGTH + 16,
-            OpenOptions::new()
-                .create(true)
-                .write(true)
-                .open(&restored_file_path)?,
-        );
-        let nonce = self.fill_buf_with_len(NONCE_LENGTH)?;
-        let mut decryptor = make_decryptor(key, &nonce);
-        let mut counter = 0;
-        loop {
-            let mut temp = self.fill_buf_with_len(BUFFER_LENGTH + 16)?;
-            size += 16;
-            counter += temp.len();
-            if counter > size {
-                let decrypted_data = decryptor
-                    .decrypt_last(&temp[..BUFFER_LENGTH + 16 - (counter - size)])
-                    .expect("decrypt failed");
-                file.write(&decrypted_data)?;
-                let mut new_buf = VecDeque::new();
-                new_buf.extend(&temp[BUFFER_LENGTH + 16 - (counter - size)..]);
-                new_buf.append(&mut self.buffer);
-                self.buffer = new_buf;
-                file.flush()?;
-                break;
-            }
+        let path = path.as_ref();
+        let (header, key, metadata, position) = self.find_entry(path)?;
+        if !metadata.has_data() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("The entry {} has no data to write.", path.to_str().unwrap()),
+            ));
+        }
+        self.write_entry_data(&header, &metadata, key.as_deref(), position, &mut writer)?;
+        writer.flush()?;
+        self.send_progress(&format!("Extracting complete: {}", path.to_str().unwrap()));
+        Ok(())
+    }
 
-            if counter == size {
-                if temp.len() == BUFFER_LENGTH + 16 {
-                    let decrypted_data = decryptor
-                        .decrypt_next(temp.as_slice())
-                        .expect("decrypt failed");
-                    file.write(&decrypted_data)?;
-                    let temp = self.fill_buf_with_len(16)?;
-                    let decrypted_data = decryptor
-                        .decrypt_last(temp.as_slice())
-                        .expect("decrypt failed");
-                    file.write(&decrypted_data)?;
-                } else {
-                    let decrypted_data = decryptor
-                        .decrypt_last(temp.as_slice())
-                        .expect("decrypt failed");
-                    file.write(&decrypted_data)?;
-                }
-                file.flush()?;
+    /// Find the entry whose path is matched with the given path, and read its metadata.
+    ///
+    /// Returns the header, the key if the file is encrypted, the metadata of the entry and its position in the file.
+    /// If the entry is a hard link, the file it is linked to is found instead.
+    /// The reading position is left at the data of the entry.
+    fn find_entry(&mut self, path: &Path) -> io::Result<FoundEntry> {
+        let found = self.locate_entry(path)?;
+        if !found.2.is_hardlink() {
+            return Ok(found);
+        }
+        let found = self.locate_entry(found.2.link_target().unwrap())?;
+        if found.2.is_hardlink() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "The hard link {} is linked to another hard link.",
+                    path.to_str().unwrap()
+                ),
+            ));
+        }
+        Ok(found)
+    }
+
+    /// Find the entry whose path is matched with the given path, and read its metadata.
+    fn locate_entry(&mut self, path: &Path) -> io::Result<FoundEntry> {
+        let not_found = || {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "There is no entry {} in the serialized file.",
+                    path.to_str().unwrap()
+                ),
+            )
+        };
+
+        self.verify_signature()?;
+        self.seek_to(0)?;
+        let mut header = self.verify_header()?;
+        let key = match header.is_encrypted() {
+            true => Some(self.read_key(&mut header)?),
+            false => None,
+        };
+
+        // Find the offset of the entry by the index.
+        let mut offset = None;
+        if header.is_indexed() {
+            let index = self.read_index_entries(&header, key.as_deref())?;
+            match index
+                .into_iter()
+                .enumerate()
+                .find(|(_, e)| e.path() == path)
+            {
+                Some((position, e)) => offset = Some((position as u64, e.offset())),
+                None => return Err(not_found()),
+            }
+        }
+
+        let (metadata, position) = match offset {
+            Some((position, offset)) => {
+                self.seek_to(offset)?;
+                (self.read_metadata(&header, key.as_deref())?, position)
+            }
+            None => {
+                let mut found = None;
+                for position in 0..header.file_count() {
+                    let metadata = self.read_metadata(&header, key.as_deref())?;
+                    if metadata.path() == path {
+                        found = Some((metadata, position));
+                        break;
+                    }
+                    self.skip_entry_data(&header, &metadata)?;
+                }
+                match found {
+                    Some(f) => f,
+                    None => return Err(not_found()),
+                }
+            }
+        };
+        Ok((header, key, metadata, position))
+    }
+
+    /// Write the original data of an entry whose metadata was just read, and verify its checksum.
+    ///
+    /// The position of the entry is needed to authenticate the encrypted data.
+    /// If the entry is in a solid block, only the block is decompressed.
+    fn write_entry_data<W: Write>(
+        &mut self,
+        header: &Header,
+        metadata: &MetaData,
+        key: Option<&[u8]>,
+        position: u64,
+        writer: W,
+    ) -> io::Result<()> {
+        let aad = match make_transcript(header) {
+            Some(transcript) => transcript.entry_aad(position, &metadata.serialize()),
+            None => Vec::new(),
+        };
+        let mut writer = ChecksumWriter::new(writer);
+        match entry_compression(header, metadata) {
+            Compression::Compressed => {
+                self.write_decompressed_data(header, &mut writer, key, &aad)?;
+            }
+            Compression::Solid { offset, is_last } => {
+                let aad = match is_last {
+                    true => aad,
+                    false => self.seek_solid_block(header, key, position)?,
+                };
+                let range = RangeWriter::new(&mut writer, offset, metadata.size());
+                self.write_decompressed_data(header, range, key, &aad)?;
+            }
+            Compression::Stored => match key {
+                Some(key) => self.write_decrypt_data(
+                    &mut writer,
+                    metadata.size() as usize,
+                    key,
+                    &aad,
+                    header.cipher(),
+                )?,
+                None => self.write_raw_data(&mut writer, metadata.size() as usize)?,
+            },
+        }
+        compare_checksum(metadata, &writer.finalize())
+    }
+
+    /// Move to the solid block of the entry whose metadata was just read, by skipping the entries after it.
+    ///
+    /// The block follows the metadata of the last entry in it.
+    /// Returns the associated data to decrypt the block, which is of the last entry.
+    fn seek_solid_block(
+        &mut self,
+        header: &Header,
+        key: Option<&[u8]>,
+        mut position: u64,
+    ) -> io::Result<Vec<u8>> {
+        loop {
+            position += 1;
+            let metadata = self.read_metadata(header, key)?;
+            if let Compression::Solid { is_last: true, .. } = entry_compression(header, &metadata) {
+                return Ok(match make_transcript(header) {
+                    Some(transcript) => transcript.entry_aad(position, &metadata.serialize()),
+                    None => Vec::new(),
+                });
+            }
+            self.skip_entry_data(header, &metadata)?;
+        }
+    }
+
+    /// Decompress the solid block after the metadata of its last entry into the restored files of its entries.
+    ///
+    /// The checksums of the entries are verified and their extended metadata are restored after the block.
+    fn restore_solid_block(
+        &mut self,
+        header: &Header,
+        entries: &mut Vec<(PathBuf, MetaData)>,
+        key: Option<&[u8]>,
+        aad: &[u8],
+    ) -> io::Result<()> {
+        let writer = SolidWriter::new(entries.iter().map(|(p, m)| (p.clone(), m.size())));
+        self.write_decompressed_data(header, writer, key, aad)?
+            .finish()?;
+        for (file_path, metadata) in entries.drain(..) {
+            verify_checksum(&metadata, &file_path)?;
+            self.restore_extended(&metadata, &file_path)?;
+        }
+        Ok(())
+    }
+
+    fn send_progress(&self, message: &str) {
+        if let Some(ref tx) = self.sender {
+            tx.send(message.to_string()).unwrap();
+        }
+    }
+
+    fn deserialize_raw(&mut self, header: &Header) -> io::Result<()> {
+        let original_file_count = header.file_count();
+        let mut current_file_count: u64 = 0;
+        let mut directories = Vec::new();
+        let mut solid_block = Vec::new();
+        let mut restored_files = HashSet::new();
+        while current_file_count < original_file_count {
+            let metadata = self.read_metadata(header, None)?;
+
+            // Write file
+            let file_path = self.restore_path.join(metadata.path());
+            verify_relative_path(metadata.path())?;
+            verify_no_symlink(&self.restore_path, &metadata)?;
+            if !metadata.has_data() {
+                self.restore_entry_without_data(
+                    metadata,
+                    &file_path,
+                    &mut directories,
+                    &restored_files,
+                )?;
+            } else {
+                fs::create_dir_all(file_path.parent().unwrap())?;
+                File::create(&file_path)?;
+                restored_files.insert(metadata.path().clone());
+                match entry_compression(header, &metadata) {
+                    Compression::Solid { is_last, .. } => {
+                        // The file is written when the block after the metadata of its last entry is read.
+                        solid_block.push((file_path.clone(), metadata));
+                        if is_last {
+                            self.restore_solid_block(header, &mut solid_block, None, &[])?;
+                        }
+                    }
+                    compression => {
+                        match compression {
+                            Compression::Compressed => {
+                                let file = create_restored_file(&file_path)?;
+                                self.write_decompressed_data(header, file, None, &[])?
+                                    .flush()?;
+                            }
+                            _ => {
+                                self.write_raw_file(&file_path, metadata.size() as usize)?;
+                            }
+                        }
+
+                        // Verify checksum
+                        verify_checksum(&metadata, &file_path)?;
+                        self.restore_extended(&metadata, &file_path)?;
+                    }
+                }
+            }
+
+            // Count file.
+            current_file_count += 1;
+
+            // Send progress.
+            self.send_progress(&format!(
+                "Deserializing... {} / {}    {}",
+                current_file_count,
+                original_file_count,
+                &file_path.to_str().unwrap()
+            ));
+        }
+        if !solid_block.is_empty() {
+            return Err(missing_solid_block_error());
+        }
+        self.restore_directories(directories)?;
+        Ok(())
+    }
+
+    /// Restore the encrypted entries.
+    ///
+    /// If the file is authenticated, the index is verified with the end marker before anything is restored,
+    /// and every entry is compared with it before it is restored.
+    /// So dropped, added, reordered or changed entries in the index are found before any file is written.
+    /// Other tampering, like changed data, is found while restoring, and then everything restored is removed.
+    /// Files which already existed in the restore path are overwritten and not recovered.
+    fn deserialize_with_decrypt(&mut self, header: &Header, key: &[u8]) -> io::Result<()> {
+        let mut created_paths = Vec::new();
+        let result = self.restore_decrypted_entries(header, key, &mut created_paths);
+        if matches!(&result, Err(e) if e.kind() == io::ErrorKind::InvalidData) {
+            for path in created_paths.into_iter().rev() {
+                match path.symlink_metadata() {
+                    Ok(m) if m.is_dir() => fs::remove_dir_all(path)?,
+                    Ok(_) => fs::remove_file(path)?,
+                    Err(_) => (),
+                }
+            }
+        }
+        result
+    }
+
+    /// Restore the encrypted entries, and record the paths created for them in the given list.
+    ///
+    /// Only the topmost one of the paths created for an entry is recorded, because everything under it is restored.
+    fn restore_decrypted_entries(
+        &mut self,
+        header: &Header,
+        key: &[u8],
+        created_paths: &mut Vec<PathBuf>,
+    ) -> io::Result<()> {
+        let original_file_count = header.file_count();
+        let mut current_file_count: u64 = 0;
+        let mut directories = Vec::new();
+        let mut solid_block = Vec::new();
+        let mut restored_files = HashSet::new();
+        let mut transcript = make_transcript(header);
+        let mut key_slots = Vec::new();
+        let mut index = None;
+        if let Some(transcript) = &transcript {
+            let position = self.position()?;
+            key_slots = self.read_raw_key_slots(header.cipher())?;
+            if header.is_indexed() {
+                let (entries, _) =
+                    self.read_verified_index(header, key, transcript.clone(), &key_slots)?;
+                index = Some(entries);
+            }
+            self.seek_to(position)?;
+        }
+        while current_file_count < original_file_count {
+            let metadata = self.read_metadata(header, Some(key))?;
+            if let Some(index) = &index {
+                match index.get(current_file_count as usize) {
+                    Some(entry) if entry.metadata().serialize() == metadata.serialize() => (),
+                    _ => return Err(tamper_error()),
+                }
+            }
+            let aad = match transcript.as_mut() {
+                Some(t) => t.next_entry(&metadata.serialize()),
+                None => Vec::new(),
+            };
+
+            // Write file
+            let file_path = self.restore_path.join(metadata.path());
+            verify_relative_path(metadata.path())?;
+            verify_no_symlink(&self.restore_path, &metadata)?;
+            created_paths.extend(first_missing_path(&self.restore_path, metadata.path()));
+            if !metadata.has_data() {
+                self.restore_entry_without_data(
+                    metadata,
+                    &file_path,
+                    &mut directories,
+                    &restored_files,
+                )?;
+            } else {
+                fs::create_dir_all(file_path.parent().unwrap())?;
+                File::create(&file_path)?;
+                restored_files.insert(metadata.path().clone());
+                match entry_compression(header, &metadata) {
+                    Compression::Solid { is_last, .. } => {
+                        // The file is written when the block after the metadata of its last entry is read.
+                        solid_block.push((file_path.clone(), metadata));
+                        if is_last {
+                            self.restore_solid_block(header, &mut solid_block, Some(key), &aad)?;
+                        }
+                    }
+                    compression => {
+                        match compression {
+                            Compression::Compressed => {
+                                let file = create_restored_file(&file_path)?;
+                                self.write_decompressed_data(header, file, Some(key), &aad)?
+                                    .flush()?;
+                            }
+                            _ => {
+                                self.write_decrypt_file(
+                                    &file_path,
+                                    metadata.size() as usize,
+                                    key,
+                                    &aad,
+                                    header.cipher(),
+                                )?;
+                            }
+                        }
+
+                        // Verify checksum
+                        verify_checksum(&metadata, &file_path)?;
+                        self.restore_extended(&metadata, &file_path)?;
+                    }
+                }
+            }
+
+            // Count file.
+            current_file_count += 1;
+
+            // Send progress.
+            self.send_progress(&format!(
+                "Deserializing... {} / {}    {}",
+                current_file_count,
+                original_file_count,
+                &file_path.to_str().unwrap()
+            ));
+        }
+        if !solid_block.is_empty() {
+            return Err(missing_solid_block_error());
+        }
+        if let Some(transcript) = transcript {
+            self.verify_end_marker(header, key, &transcript, &key_slots)?;
+        }
+        self.restore_directories(directories)?;
+        Ok(())
+    }
+
+    /// Read the end marker after the last entry, and verify it is authenticated with all entries read before
+    /// and the serialized key slots.
+    ///
+    /// If an entry was dropped, added or reordered, or the header or the key slots were changed,
+    /// the marker cannot be decrypted.
+    fn verify_end_marker(
+        &mut self,
+        header: &Header,
+        key: &[u8],
+        transcript: &Transcript,
+        key_slots: &[u8],
+    ) -> io::Result<()> {
+        let cipher = header.cipher();
+        let marker = self.fill_buf_with_exact_len(cipher.sealed_size(8))?;
+        let file_count = cipher
+            .unseal_with_aad(key, &marker, &transcript.end_aad(key_slots))
+            .ok_or_else(tamper_error)?;
+        if binary_to_u64(&file_count) != header.file_count() {
+            return Err(tamper_error());
+        }
+        Ok(())
+    }
+
+    /// Read the index, and verify the end marker in front of it is authenticated with the metadata in the index.
+    ///
+    /// Returns the index and the transcript with all entries in it recorded.
+    /// The reading position is moved.
+    fn read_verified_index(
+        &mut self,
+        header: &Header,
+        key: &[u8],
+        mut transcript: Transcript,
+        key_slots: &[u8],
+    ) -> io::Result<(Vec<EntryInfo>, Transcript)> {
+        let index = self.read_index_entries(header, Some(key))?;
+        for entry in &index {
+            transcript.next_entry(&entry.metadata().serialize());
+        }
+        let trailer = self.read_trailer()?;
+        let end_marker_offset = trailer
+            .index_offset()
+            .checked_sub(header.cipher().sealed_size(8) as u64)
+            .ok_or_else(tamper_error)?;
+        self.seek_to(end_marker_offset)?;
+        self.verify_end_marker(header, key, &transcript, key_slots)?;
+        Ok((index, transcript))
+    }
+
+    /// Read the header and verify it is matched with the option of the deserializer.
+    fn verify_header(&mut self) -> io::Result<Header> {
+        let header = self.read_header()?;
+        self.verify_option(&header)?;
+        Ok(header)
+    }
+
+    /// Read the header of the serialized file and verify its label and version.
+    fn read_header(&mut self) -> io::Result<Header> {
+        // Verify label.
+        let mut header = Header::new();
+        header.deserialize_label(&self.fill_buf_with_exact_len(FILE_LABEL.len())?)?;
+
+        // Verify version.
+        header.deserialize_version(&self.fill_buf_with_exact_len(4)?)?;
+        if header.version().major() < get_major_version() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("The major version of the file is too low. It is a serialized file with an older version of the library. \
+                To deserialize this file, library version {}.x.x is required. \
+                If you want to deserialize this file, Use an older version of the library.", header.version().major()),
+            ));
+        } else if header.version().major() > get_major_version() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("The major version of the file is too high. It is a serialized file with a newer version of the library. \
+                To deserialize this file, library version {}.{}.x is required. \
+                If you want to deserialize this file, Use a newer version of the library.", header.version().major(), header.version().minor()),
+            ));
+        } else if header.version().minor() > get_minor_version() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("The minor version of the file is too high. \
+                It is a serialized file with a newer version of the library. \
+                To deserialize this file, library version {}.{}.x is required. If you want to deserialize this file, Use a newer version of the library.", header.version().major(), header.version().minor()),
+            ));
+        }
+
+        // Read header flags.
+        header.deserialize_flag(&self.fill_buf_with_exact_len(1)?);
+        if header.is_indexed() && header.version() < INDEX_MIN_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "The file has an index flag, but the version of the file is older than {}.",
+                    INDEX_MIN_VERSION
+                ),
+            ));
+        }
+        if header.hidden_files() != HiddenFiles::SkipFiles
+            && header.version() < HIDDEN_FILES_MIN_VERSION
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "The file has a hidden files flag, but the version of the file is older than {}.",
+                    HIDDEN_FILES_MIN_VERSION
+                ),
+            ));
+        }
+        if header.has_extended_metadata() && header.version() < EXTENDED_METADATA_MIN_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "The file has an extended metadata flag, but the version of the file is older than {}.",
+                    EXTENDED_METADATA_MIN_VERSION
+                ),
+            ));
+        }
+        if header.has_key_slots() {
+            if header.version() < KEY_SLOTS_MIN_VERSION {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "The file has a key slots flag, but the version of the file is older than {}.",
+                        KEY_SLOTS_MIN_VERSION
+                    ),
+                ));
+            }
+            if !header.is_encrypted() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "The file has a key slots flag, but it is not encrypted.",
+                ));
+            }
+        }
+        if header.is_metadata_encrypted() {
+            if header.version() < METADATA_ENCRYPTION_MIN_VERSION {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "The file has a metadata encryption flag, but the version of the file is older than {}.",
+                        METADATA_ENCRYPTION_MIN_VERSION
+                    ),
+                ));
+            }
+            if !header.is_encrypted() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "The file has a metadata encryption flag, but it is not encrypted.",
+                ));
+            }
+        }
+
+        // Read the cipher of encrypted files.
+        if header.has_cipher_id() {
+            header.deserialize_cipher(&self.fill_buf_with_exact_len(1)?)?;
+        }
+
+        // Read the codec of compressed files.
+        if header.has_codec_id() {
+            header.deserialize_codec(&self.fill_buf_with_exact_len(1)?)?;
+        }
+
+        // Read the number of original files.
+        let original_file_count_bytes = self.fill_buf_with_exact_len(1)?[0];
+        header.deserialize_file_count(
+            &self.fill_buf_with_exact_len(original_file_count_bytes as usize)?,
+        );
+
+        Ok(header)
+    }
+
+    /// Verify the encryption of the header is matched with the option of the deserializer.
+    ///
+    /// The compression is not verified, because the codec in the header
+    /// and the compression recorded in every entry tell how to decompress it.
+    fn verify_option(&self, header: &Header) -> io::Result<()> {
+        match header.is_encrypted() {
+            true => {
+                if !self.option.is_encrypted() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "The archive is encrypted, but the option is not set.",
+                    ));
+                }
+            }
+            false => {
+                if self.option.is_encrypted() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "The archive is not encrypted, but the option is set.",
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Read the metadata of an entry.
+    ///
+    /// If the metadata is encrypted, it is decrypted with the given key.
+    fn read_metadata(&mut self, header: &Header, key: Option<&[u8]>) -> io::Result<MetaData> {
+        if !header.is_metadata_encrypted() {
+            return MetaData::read_from(&mut EntryReader(self), header);
+        }
+        let key = match key {
+            Some(k) => k,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "The metadata of this file is encrypted but there is no password input.",
+                ))
+            }
+        };
+        let sealed_size = binary_to_u64(&self.fill_buf_with_exact_len(4)?) as usize;
+        let sealed = self.fill_buf_with_exact_len(sealed_size)?;
+        let binary = header
+            .cipher()
+            .unseal(key, &sealed)
+            .ok_or_else(decrypt_error)?;
+        MetaData::read_from(&mut binary.as_slice(), header)
+    }
+
+    /// Restore an entry which has no data, like a symbolic link or a directory.
+    ///
+    /// The extended metadata of a directory are not restored here but kept in the given list,
+    /// because restoring its contents changes them.
+    /// A hard link can only be linked to one of the given files, which are restored from this file before.
+    fn restore_entry_without_data(
+        &self,
+        metadata: MetaData,
+        path: &Path,
+        directories: &mut Vec<(PathBuf, MetaData)>,
+        restored_files: &HashSet<PathBuf>,
+    ) -> io::Result<()> {
+        if metadata.is_dir() {
+            fs::create_dir_all(path)?;
+            directories.push((path.to_path_buf(), metadata));
+        } else if metadata.is_hardlink() {
+            self.restore_hardlink(&metadata, path, restored_files)?;
+        } else {
+            self.restore_symlink(&metadata, path)?;
+        }
+        Ok(())
+    }
+
+    /// Restore the extended metadata of the directories, after all of their contents are restored.
+    fn restore_directories(&self, directories: Vec<(PathBuf, MetaData)>) -> io::Result<()> {
+        // Children first, because a restored parent may not be accessible anymore.
+        for (path, metadata) in directories.iter().rev() {
+            self.restore_extended(metadata, path)?;
+        }
+        Ok(())
+    }
+
+    /// Link the hard link entry to the file restored before.
+    ///
+    /// The target must be one of the restored files, so a malicious file cannot link to a file outside of the restore path.
+    fn restore_hardlink(
+        &self,
+        metadata: &MetaData,
+        link_path: &Path,
+        restored_files: &HashSet<PathBuf>,
+    ) -> io::Result<()> {
+        let target = match metadata.link_target() {
+            Some(t) if restored_files.contains(t) => self.restore_path.join(t),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "The hard link {} is not linked to a file restored before. The file may be malicious.",
+                        metadata.path().to_str().unwrap()
+                    ),
+                ))
+            }
+        };
+        remove_restored_file(link_path)?;
+        fs::hard_link(target, link_path)?;
+        self.restore_extended(metadata, link_path)
+    }
+
+    /// Recreate the symbolic link entry, and verify the checksum of its target.
+    fn restore_symlink<T: AsRef<Path>>(&self, metadata: &MetaData, link_path: T) -> io::Result<()> {
+        let link_path = link_path.as_ref();
+        let link_target = metadata.link_target().unwrap();
+        compare_checksum(
+            metadata,
+            &get_bytes_checksum(link_target.to_string_lossy().as_bytes()),
+        )?;
+        remove_restored_file(link_path)?;
+        create_symlink(link_target, link_path)?;
+        self.restore_extended(metadata, link_path)
+    }
+
+    /// Restore the extended metadata of the entry to the restored file, if there are.
+    fn restore_extended<T: AsRef<Path>>(
+        &self,
+        metadata: &MetaData,
+        file_path: T,
+    ) -> io::Result<()> {
+        if let Some(extended) = metadata.extended() {
+            let restore_owner = match self.option.ownership_mode() {
+                Ownership::Restore => true,
+                Ownership::RestoreIfRoot => is_root(),
+                Ownership::Skip => false,
+            };
+            match metadata.is_symlink() {
+                true => extended.restore_symlink(file_path, restore_owner)?,
+                false => extended.restore(file_path, restore_owner)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn write_raw_file<T: AsRef<Path>>(
+        &mut self,
+        restored_file_path: T,
+        size: usize,
+    ) -> io::Result<()> {
+        let mut file = create_restored_file(restored_file_path)?;
+        self.write_raw_data(&mut file, size)?;
+        file.flush()?;
+        Ok(())
+    }
+
+    fn write_raw_data<W: Write>(&mut self, writer: &mut W, size: usize) -> io::Result<()> {
+        let mut remaining = size;
+        while remaining > 0 {
+            let chunk = self.fill_buf_with_exact_len(remaining.min(BUFFER_LENGTH))?;
+            writer.write_all(&chunk)?;
+            remaining -= chunk.len();
+        }
+        Ok(())
+    }
+
+    /// Decompress the compressed data of an entry into the writer, with the length of the compressed data in front.
+    ///
+    /// If the key is given, the compressed data is decrypted with it and the associated data.
+    fn write_decompressed_data<W: Write>(
+        &mut self,
+        header: &Header,
+        writer: W,
+        key: Option<&[u8]>,
+        aad: &[u8],
+    ) -> io::Result<W> {
+        let compressed_size = binary_to_u64(&self.fill_buf_with_exact_len(8)?) as usize;
+        let mut decoder = make_decoder(header.codec(), writer)?;
+        match key {
+            Some(key) => {
+                self.write_decrypt_data(&mut decoder, compressed_size, key, aad, header.cipher())?
+            }
+            None => self.write_raw_data(&mut decoder, compressed_size)?,
+        }
+        decoder.finish()
+    }
+
+    fn write_decrypt_file<T: AsRef<Path>>(
+        &mut self,
+        restored_file_path: T,
+        size: usize,
+        key: &[u8],
+        aad: &[u8],
+        cipher: Cipher,
+    ) -> io::Result<()> {
+        let mut file = create_restored_file(restored_file_path)?;
+        self.write_decrypt_data(&mut file, size, key, aad, cipher)?;
+        file.flush()?;
+        Ok(())
+    }
+
+    /// Decrypt the data of an entry with the cipher and the associated data, which is empty for files older than 2.10.0.
+    fn write_decrypt_data<W: Write>(
+        &mut self,
+        writer: &mut W,
+        size: usize,
+        key: &[u8],
+        aad: &[u8],
+        cipher: Cipher,
+    ) -> io::Result<()> {
+        // If the data is authenticated, failing to decrypt it means it was tampered with.
+        let error = match aad.is_empty() {
+            true => decrypt_error,
+            false => tamper_error,
+        };
+        let nonce = self.fill_buf_with_exact_len(cipher.stream_nonce_length())?;
+        let mut decryptor = cipher.make_decryptor(key, &nonce);
+        let mut remaining = encrypted_size(size as u64) as usize;
+        loop {
+            // Every chunk but the last one has the same length.
+            if remaining > BUFFER_LENGTH + TAG_LENGTH {
+                let chunk = self.fill_buf_with_exact_len(BUFFER_LENGTH + TAG_LENGTH)?;
+                let payload = Payload {
+                    msg: chunk.as_slice(),
+                    aad,
+                };
+                let decrypted_data = match decryptor.decrypt_next(payload) {
+                    Ok(d) => d,
+                    Err(_) => return Err(error()),
+                };
+                writer.write_all(&decrypted_data)?;
+                remaining -= chunk.len();
+            } else {
+                let chunk = self.fill_buf_with_exact_len(remaining)?;
+                let payload = Payload {
+                    msg: chunk.as_slice(),
+                    aad: &last_chunk_aad(aad, size as u64),
+                };
+                let decrypted_data = match decryptor.decrypt_last(payload) {
+                    Ok(d) => d,
+                    Err(_) => return Err(error()),
+                };
+                writer.write_all(&decrypted_data)?;
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The header, the key if the file is encrypted, the metadata of an entry and its position in the file.
+type FoundEntry = (Header, Option<SecretKey>, MetaData, u64);
+
+/// Reads the serialized file through the buffer of the deserializer.
+struct EntryReader<'a>(&'a mut Deserializer);
+
+impl Read for EntryReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let binary = self.0.fill_buf_with_len(buf.len())?;
+        buf[..binary.len()].copy_from_slice(&binary);
+        Ok(binary.len())
+    }
+}
+
+/// Writes the decompressed data of a solid block into the restored files of its entries, split by their sizes.
+///
+/// Every file is opened when its data begins, so the files of a large block are not opened at once.
+struct SolidWriter {
+    files: VecDeque<(PathBuf, u64)>,
+    current: Option<(BufWriter<File>, u64)>,
+}
+
+impl SolidWriter {
+    fn new<I: IntoIterator<Item = (PathBuf, u64)>>(files: I) -> Self {
+        SolidWriter {
+            files: files.into_iter().collect(),
+            current: None,
+        }
+    }
+
+    /// Verify the data of every entry is written, and flush the last file.
+    fn finish(mut self) -> io::Result<()> {
+        if let Some((mut file, remaining)) = self.current.take() {
+            file.flush()?;
+            if remaining > 0 {
+                return Err(short_solid_block_error());
+            }
+        }
+        // Empty files are created already.
+        match self.files.iter().all(|(_, size)| *size == 0) {
+            true => Ok(()),
+            false => Err(short_solid_block_error()),
+        }
+    }
+}
+
+impl Write for SolidWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut written = 0;
+        while written < buf.len() {
+            match &mut self.current {
+                Some((file, remaining)) if *remaining > 0 => {
+                    let length = (*remaining).min((buf.len() - written) as u64) as usize;
+                    file.write_all(&buf[written..written + length])?;
+                    *remaining -= length as u64;
+                    written += length;
+                }
+                _ => {
+                    if let Some((mut file, _)) = self.current.take() {
+                        file.flush()?;
+                    }
+                    let (path, size) = self.files.pop_front().ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "The solid block has more data than its entries.",
+                        )
+                    })?;
+                    self.current = Some((create_restored_file(path)?, size));
+                }
+            }
+        }
+        Ok(written)
+    }
 
-                break;
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.current {
+            Some((file, _)) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+fn create_restored_file<T: AsRef<Path>>(restored_file_path: T) -> io::Result<BufWriter<File>> {
+    if let Some(p) = restored_file_path.as_ref().parent() {
+        fs::create_dir_all(p)?;
+    }
+    Ok(BufWriter::with_capacity(
+        BUFFER_LENGTH + TAG_LENGTH,
+        OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&restored_file_path)?,
+    ))
+}
+
+/// Make sure the path of an entry is relative and stays under the restore path.
+///
+/// A path with `..`, a root or a prefix component can be made only by a malicious file.
+fn verify_relative_path(path: &Path) -> io::Result<()> {
+    if path
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+    {
+        return Ok(());
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "The entry {} is not under the restore path. The file may be malicious.",
+            path.to_str().unwrap()
+        ),
+    ))
+}
+
+/// Make sure restoring the entry doesn't write through a symbolic link, which may point outside of the restore path.
+///
+/// None of the directories between the restore path and the entry can be a symbolic link.
+/// Neither can the entry itself, unless it is a link which replaces the old file.
+fn verify_no_symlink(restore_path: &Path, metadata: &MetaData) -> io::Result<()> {
+    let path = metadata.path();
+    let mut checked = restore_path.to_path_buf();
+    let mut components = path.components().peekable();
+    while let Some(component) = components.next() {
+        checked.push(component);
+        let is_entry = components.peek().is_none();
+        if is_entry && (metadata.is_symlink() || metadata.is_hardlink()) {
+            break;
+        }
+        if fs::symlink_metadata(&checked).is_ok_and(|m| m.file_type().is_symlink()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "The entry {} is restored through a symbolic link. The file may be malicious.",
+                    path.to_str().unwrap()
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Make the place to restore a link, by creating its parent directories and removing the old file.
+fn remove_restored_file(path: &Path) -> io::Result<()> {
+    if let Some(p) = path.parent() {
+        fs::create_dir_all(p)?;
+    }
+    if let Ok(m) = fs::symlink_metadata(path) {
+        if !m.is_dir() {
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Create a symbolic link which points at the target.
+fn create_symlink(target: &Path, link_path: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink(target, link_path)
+    }
+    #[cfg(windows)]
+    {
+        // Windows distinguishes links to directories from links to files.
+        match link_path
+            .parent()
+            .unwrap_or(Path::new(""))
+            .join(target)
+            .is_dir()
+        {
+            true => std::os::windows::fs::symlink_dir(target, link_path),
+            false => std::os::windows::fs::symlink_file(target, link_path),
+        }
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "Symbolic links are not supported on this platform: {}",
+                link_path.to_str().unwrap()
+            ),
+        ))
+    }
+}
+
+/// Returns the kinds of credentials which can open the key slots, without duplicates.
+fn slot_credential_kinds(slots: &[KeySlot]) -> Vec<CredentialKind> {
+    let mut kinds = Vec::new();
+    for kind in slots.iter().map(KeySlot::credential_kind) {
+        if !kinds.contains(&kind) {
+            kinds.push(kind);
+        }
+    }
+    kinds
+}
+
+/// Join the kinds of credentials with the separator, like "a password or a keyfile".
+fn join_kinds(kinds: &[CredentialKind], separator: &str) -> String {
+    kinds
+        .iter()
+        .map(|kind| kind.to_string())
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
+/// Returns how the data of the entry is written.
+///
+/// Files older than 2.16.0 don't record it in the metadata, and compress every entry if the file is compressed.
+fn entry_compression(header: &Header, metadata: &MetaData) -> Compression {
+    match metadata.compression() {
+        Some(compression) => compression,
+        None if header.is_compressed() => Compression::Compressed,
+        None => Compression::Stored,
+    }
+}
+
+/// Set the sizes of the compressed data of the entries read from the index.
+///
+/// The data of an entry lies between its metadata and the next entry,
+/// so the size is worked out from the offsets without reading the data.
+fn set_compressed_sizes(header: &Header, index: &mut [EntryInfo], end_of_entries: u64) {
+    let mut next_offset = end_of_entries;
+    for entry in index.iter_mut().rev() {
+        let metadata = entry.metadata();
+        if metadata.has_data() && entry_compression(header, metadata) == Compression::Compressed {
+            let metadata_length = match header.is_metadata_encrypted() {
+                true => 4 + header.cipher().sealed_size(metadata.serialize().len()),
+                false => metadata.serialize().len(),
+            } as u64;
+            // The data is the size of the compressed data followed by the compressed data.
+            let stored_size = next_offset.saturating_sub(entry.offset() + metadata_length + 8);
+            let compressed_size = match header.is_encrypted() {
+                true => decrypted_size(
+                    stored_size.saturating_sub(header.cipher().stream_nonce_length() as u64),
+                ),
+                false => stored_size,
+            };
+            entry.set_compressed_size(Some(compressed_size));
+        }
+        next_offset = entry.offset();
+    }
+}
+
+/// Make the transcript to authenticate the entries of an encrypted file, if the version of the file supports it.
+///
+/// The file count in the header was written as 0 if the metadata is encrypted.
+fn make_transcript(header: &Header) -> Option<Transcript> {
+    if !header.is_encrypted() || header.version() < AUTHENTICATION_MIN_VERSION {
+        return None;
+    }
+    let mut written = header.clone();
+    if written.is_metadata_encrypted() {
+        written.set_file_count(0);
+    }
+    Some(Transcript::new(&written.to_binary_vec()))
+}
+
+/// Returns the restore path or the first of the ancestors of the entry path under it which doesn't exist yet.
+fn first_missing_path(restore_path: &Path, entry_path: &Path) -> Option<PathBuf> {
+    let mut path = restore_path.to_path_buf();
+    if !path.as_os_str().is_empty() && path.symlink_metadata().is_err() {
+        return Some(path);
+    }
+    for component in entry_path.components() {
+        path.push(component);
+        if path.symlink_metadata().is_err() {
+            return Some(path);
+        }
+    }
+    None
+}
+
+fn short_solid_block_error() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "The solid block ends before the data of its entries.",
+    )
+}
+
+fn missing_solid_block_error() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "The solid block of the last entries is missing.",
+    )
+}
+
+fn decrypt_error() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "Cannot decrypt data! The password may be wrong or the file may be broken.",
+    )
+}
+
+fn tamper_error() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "The serialized file has been tampered with! The header, the entries or their order are not authentic.",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        compress::{make_encoder, Codec},
+        encrypt::{keyslot::Identity, Argon2Variant},
+        serialize::{
+            option::{DirectoryEntries, EntryCompression, SignaturePlacement},
+            serializer::Serializer,
+        },
+        sign::{detached_signature_path, SigningKey},
+    };
+
+    use super::*;
+    use std::{path::PathBuf, sync::mpsc, thread};
+
+    #[test]
+    fn deserialize_file_test() {
+        let original = PathBuf::from("tests/original_images/dir1/board-g43968feec_1920.jpg");
+        let result = PathBuf::from("deserialize_file_test.bin");
+        let mut serializer = Serializer::new(original, result.clone()).unwrap();
+        serializer.set_option(SerializeOption::default());
+        serializer.serialize().unwrap();
+
+        let restored = PathBuf::from("deserialize_file_test_restored_dir");
+        let mut deserializer = Deserializer::new(result.clone(), restored.clone()).unwrap();
+        deserializer.set_option(SerializeOption::default());
+        deserializer.deserialize().unwrap();
+        assert!(&result.is_file());
+        assert!(&restored.is_dir());
+        if result.is_file() {
+            fs::remove_file(result).unwrap();
+        }
+        if restored.is_dir() {
+            fs::remove_dir_all(restored).unwrap();
+        }
+    }
+
+    #[test]
+    fn deserialize_dir_test() {
+        let original = PathBuf::from("tests");
+        let result = PathBuf::from("deserialize_dir_test.bin");
+        let mut serializer = Serializer::new(original, result.clone()).unwrap();
+        serializer.set_option(SerializeOption::default());
+        serializer.serialize().unwrap();
+
+        let restored = PathBuf::from("deserialize_test_dir");
+        let mut deserializer = Deserializer::new(result.clone(), restored.clone()).unwrap();
+        deserializer.set_option(SerializeOption::default());
+        deserializer.deserialize().unwrap();
+        assert!(&result.is_file());
+        assert!(&restored.is_dir());
+        if result.is_file() {
+            fs::remove_file(result).unwrap();
+        }
+        if restored.is_dir() {
+            fs::remove_dir_all(restored).unwrap();
+        }
+    }
+
+    #[test]
+    fn deserialize_with_decrypt_test() {
+        let original = PathBuf::from("tests");
+        let result = PathBuf::from("deserialize_with_decrypt_test.bin");
+        let mut serializer = Serializer::new(original, result.clone()).unwrap();
+        serializer.set_option(SerializeOption::new().to_encrypt("test_password"));
+        serializer.serialize().unwrap();
+
+        let serialized_file = PathBuf::from("deserialize_with_decrypt_test.bin");
+        let restored = PathBuf::from("deserialize_with_decrypt_test_dir");
+        let mut deserializer = Deserializer::new(serialized_file, restored.clone()).unwrap();
+        deserializer.set_option(SerializeOption::new().to_encrypt("test_password"));
+        deserializer.deserialize().unwrap();
+        assert!(&result.is_file());
+        assert!(&restored.is_dir());
+        if result.is_file() {
+            fs::remove_file(result).unwrap();
+        }
+        if restored.is_dir() {
+            fs::remove_dir_all(restored).unwrap();
+        }
+    }
+
+    #[test]
+    fn deserialize_with_compress_test() {
+        const ENTRY: &str = "tests/original_images/dir1/board-g43968feec_1920.jpg";
+        #[allow(unused_mut)]
+        let mut codecs = vec![Codec::default(), Codec::zstd(3)];
+        #[cfg(feature = "lz4")]
+        codecs.push(Codec::Lz4);
+        #[cfg(feature = "xz")]
+        codecs.push(Codec::Xz { level: 6 });
+
+        for codec in codecs {
+            let original = PathBuf::from("tests");
+            let result = PathBuf::from("deserialize_compress_test.bin");
+            let mut serializer = Serializer::new(original, result.clone()).unwrap();
+            serializer.set_option(SerializeOption::new().to_compression(codec));
+            serializer.serialize().unwrap();
+
+            let serialized_file = PathBuf::from("deserialize_compress_test.bin");
+            let restored = PathBuf::from("deserialize_compress_test_dir");
+            let mut deserializer = Deserializer::new(serialized_file, restored.clone()).unwrap();
+            deserializer.set_option(SerializeOption::new().to_compress(true));
+            deserializer.deserialize().unwrap();
+            assert!(&result.is_file());
+            assert!(&restored.is_dir());
+            assert_eq!(
+                fs::read(restored.join(ENTRY)).unwrap(),
+                fs::read(ENTRY).unwrap()
+            );
+            if result.is_file() {
+                fs::remove_file(result).unwrap();
+            }
+            if restored.is_dir() {
+                fs::remove_dir_all(restored).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn deserialize_with_decrypt_compress_test() {
+        let original = PathBuf::from("tests");
+        let result = PathBuf::from("deserialize_decrypt_compress_test.bin");
+        let option = SerializeOption::new()
+            .to_compress(true)
+            .to_encrypt("test_password");
+        let mut serializer = Serializer::new(original, result.clone()).unwrap();
+        serializer.set_option(option.clone());
+        serializer.serialize().unwrap();
+
+        let serialized_file = PathBuf::from("deserialize_decrypt_compress_test.bin");
+        let restored = PathBuf::from("deserialize_decrypt_compress_test_dir");
+        let mut deserializer = Deserializer::new(serialized_file, restored.clone()).unwrap();
+        deserializer.set_option(option.clone());
+        deserializer.deserialize().unwrap();
+        assert!(&result.is_file());
+        assert!(&restored.is_dir());
+        if result.is_file() {
+            fs::remove_file(result).unwrap();
+        }
+        if restored.is_dir() {
+            fs::remove_dir_all(restored).unwrap();
+        }
+    }
+
+    #[test]
+    fn deserialize_with_zstd_test() {
+        const ENTRY: &str = "tests/original_images/dir1/board-g43968feec_1920.jpg";
+        let original = PathBuf::from("tests");
+        let result = PathBuf::from("deserialize_with_zstd_test.bin");
+        let restored = PathBuf::from("deserialize_with_zstd_test_dir");
+        let mut serializer = Serializer::new(&original, &result).unwrap();
+        serializer.set_option(SerializeOption::new().to_compression(Codec::zstd(23)));
+        assert_eq!(
+            serializer.serialize().unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+
+        for codec in [
+            Codec::zstd(-5),
+            Codec::Zstd {
+                level: 19,
+                long_distance_matching: true,
+            },
+        ] {
+            let mut serializer = Serializer::new(&original, &result).unwrap();
+            serializer.set_option(
+                SerializeOption::new()
+                    .to_encrypt("test_password")
+                    .to_compression(codec),
+            );
+            serializer.serialize().unwrap();
+
+            // The codec is read from the file, not from the option.
+            let mut deserializer = Deserializer::new(result.clone(), restored.clone()).unwrap();
+            deserializer.set_option(
+                SerializeOption::new()
+                    .to_encrypt("test_password")
+                    .to_compress(true),
+            );
+            assert_eq!(deserializer.read_header().unwrap().codec().id(), codec.id());
+            deserializer.deserialize().unwrap();
+            assert!(restored.join(ENTRY).is_file());
+            let mut data = Vec::new();
+            deserializer
+                .extract_entry_to_writer(ENTRY, &mut data)
+                .unwrap();
+            assert_eq!(data, fs::read(ENTRY).unwrap());
+            fs::remove_dir_all(&restored).unwrap();
+        }
+        fs::remove_file(result).unwrap();
+    }
+
+    #[test]
+    fn deserialize_with_entry_compression_test() {
+        let original = PathBuf::from("entry_compression_test_original");
+        let result = PathBuf::from("entry_compression_test.bin");
+        let restored = PathBuf::from("entry_compression_test_dir");
+        fs::create_dir_all(&original).unwrap();
+        fs::copy(
+            "tests/original_images/dir1/board-g43968feec_1920.jpg",
+            original.join("image.jpg"),
+        )
+        .unwrap();
+        let text = fs::read("src/lib.rs").unwrap();
+        fs::write(original.join("text.txt"), &text).unwrap();
+        // Compressed data without a known extension is detected by trying to compress it.
+        let mut encoder = make_encoder(Codec::zstd(19), Vec::new()).unwrap();
+        encoder.write_all(&text).unwrap();
+        fs::write(original.join("data.bin"), encoder.finish().unwrap()).unwrap();
+
+        // Every entry is compressed by default.
+        assert_eq!(
+            SerializeOption::new().entry_compression_mode(),
+            EntryCompression::All
+        );
+        for (entry_compression, compressed_files) in [
+            (EntryCompression::SkipIncompressible, vec!["text.txt"]),
+            (
+                EntryCompression::All,
+                vec!["data.bin", "image.jpg", "text.txt"],
+            ),
+        ] {
+            let option = SerializeOption::new()
+                .to_encrypt("test_password")
+                .to_encrypt_metadata(true)
+                .to_compress(true)
+                .to_entry_compression(entry_compression);
+            let mut serializer = Serializer::new(&original, &result).unwrap();
+            serializer.set_option(option.clone());
+            serializer.serialize().unwrap();
+
+            let mut deserializer = Deserializer::new(&result, &restored).unwrap();
+            // The compression is read from the file, so the option doesn't need to set it.
+            deserializer.set_option(SerializeOption::new().to_encrypt("test_password"));
+            let mut compressed: Vec<_> = deserializer
+                .list()
+                .unwrap()
+                .iter()
+                .filter(|e| e.compressed_size().is_some())
+                .map(|e| e.path().file_name().unwrap().to_str().unwrap().to_string())
+                .collect();
+            compressed.sort();
+            assert_eq!(compressed, compressed_files);
+
+            deserializer.deserialize().unwrap();
+            for name in ["data.bin", "image.jpg", "text.txt"] {
+                let path = PathBuf::from("entry_compression_test_original").join(name);
+                assert_eq!(
+                    fs::read(restored.join(&path)).unwrap(),
+                    fs::read(&path).unwrap()
+                );
+                let mut data = Vec::new();
+                deserializer
+                    .extract_entry_to_writer(&path, &mut data)
+                    .unwrap();
+                assert_eq!(data, fs::read(&path).unwrap());
+            }
+            fs::remove_dir_all(&restored).unwrap();
+        }
+        fs::remove_file(result).unwrap();
+        fs::remove_dir_all(original).unwrap();
+    }
+
+    #[test]
+    fn deserialize_with_solid_test() {
+        let original = PathBuf::from("solid_test_original");
+        let result = PathBuf::from("solid_test.bin");
+        let restored = PathBuf::from("solid_test_dir");
+        fs::create_dir_all(original.join("sub")).unwrap();
+        let source = fs::read_to_string("src/serialize/deserializer.rs").unwrap();
+        let mut names = vec![String::from("empty.txt"), String::from("sub/image.jpg")];
+        // Only full chunks, because a short last chunk may be too small to compress.
+        for (i, lines) in source
+            .lines()
+            .collect::<Vec<_>>()
+            .chunks_exact(40)
+            .enumerate()
+        {
+            let name = format!("sub/{:03}.rs", i);
+            fs::write(original.join(&name), lines.join("\n")).unwrap();
+            names.push(name);
+        }
+        fs::write(original.join("empty.txt"), "").unwrap();
+        fs::copy(
+            "tests/original_images/dir1/board-g43968feec_1920.jpg",
+            original.join("sub/image.jpg"),
+        )
+        .unwrap();
+        // A hard link may be restored before the block of the file it is linked to.
+        #[cfg(unix)]
+        {
+            fs::hard_link(original.join("sub/000.rs"), original.join("link.rs")).unwrap();
+            names.push(String::from("link.rs"));
+        }
+
+        let mut serializer = Serializer::new(&original, &result).unwrap();
+        serializer.set_option(SerializeOption::new().to_compress(true));
+        serializer.serialize().unwrap();
+        let compressed_length = fs::metadata(&result).unwrap().len();
+
+        // The JPEG image is stored by itself, out of the solid blocks.
+        for option in [
+            SerializeOption::new()
+                .to_solid(16 * 1024)
+                .to_entry_compression(EntryCompression::SkipIncompressible),
+            SerializeOption::new()
+                .to_solid(u64::MAX)
+                .to_entry_compression(EntryCompression::SkipIncompressible)
+                .to_encrypt("test_password")
+                .to_encrypt_metadata(true),
+        ] {
+            let mut serializer = Serializer::new(&original, &result).unwrap();
+            serializer.set_option(option.clone());
+            serializer.serialize().unwrap();
+            if !option.is_encrypted() {
+                assert!(fs::metadata(&result).unwrap().len() < compressed_length);
+            }
+
+            let mut deserializer = Deserializer::new(&result, &restored).unwrap();
+            deserializer.set_option(option);
+            let entries = deserializer.list().unwrap();
+            for entry in &entries {
+                let is_text = entry.path().extension().unwrap() == "rs";
+                assert_eq!(entry.is_solid(), is_text && entry.is_file());
             }
-            let decrypted_data = decryptor
-                .decrypt_next(temp.as_slice())
-                .expect("decrypt failed");
-            file.write(&decrypted_data)?;
-            temp.clear();
+
+            deserializer.deserialize().unwrap();
+            for name in &names {
+                let path = original.join(name);
+                let data = fs::read(&path).unwrap();
+                assert_eq!(fs::read(restored.join(&path)).unwrap(), data);
+                let mut extracted = Vec::new();
+                deserializer
+                    .extract_entry_to_writer(&path, &mut extracted)
+                    .unwrap();
+                assert_eq!(extracted, data);
+            }
+            fs::remove_dir_all(&restored).unwrap();
+        }
+        fs::remove_file(result).unwrap();
+        fs::remove_dir_all(original).unwrap();
+    }
+
+    #[test]
+    fn read_index_test() {
+        let original = PathBuf::from("tests");
+        let result = PathBuf::from("read_index_test.bin");
+        let mut serializer = Serializer::new(original, result.clone()).unwrap();
+        serializer.set_option(SerializeOption::new().to_compress(true));
+        serializer.serialize().unwrap();
+
+        let restored = PathBuf::from("read_index_test_dir");
+        let mut deserializer = Deserializer::new(result.clone(), restored.clone()).unwrap();
+        deserializer.set_option(SerializeOption::new().to_compress(true));
+        let index = deserializer.read_index().unwrap();
+        assert_eq!(index.len(), 10);
+        deserializer.seek_to(0).unwrap();
+        let header = deserializer.read_header().unwrap();
+        for entry in &index {
+            deserializer.seek_to(entry.offset()).unwrap();
+            let metadata = deserializer.read_metadata(&header, None).unwrap();
+            assert_eq!(metadata.path(), entry.path());
+            assert_eq!(metadata.checksum().as_deref(), entry.checksum());
+        }
+
+        // The index must not disturb deserializing.
+        deserializer.deserialize().unwrap();
+        assert!(&restored.is_dir());
+        assert!(!PathBuf::from("read_index_test_dir/tests/read_index_test.bin").exists());
+        if result.is_file() {
+            fs::remove_file(result).unwrap();
+        }
+        if restored.is_dir() {
+            fs::remove_dir_all(restored).unwrap();
+        }
+    }
+
+    #[test]
+    fn list_test() {
+        let options = [
+            SerializeOption::default(),
+            SerializeOption::new().to_compress(true),
+            SerializeOption::new().to_encrypt("test_password"),
+            SerializeOption::new()
+                .to_encrypt("test_password")
+                .to_compress(true),
+        ];
+        for (i, option) in options.into_iter().enumerate() {
+            let original = PathBuf::from("tests");
+            let result = PathBuf::from(format!("list_test_{}.bin", i));
+            let mut serializer = Serializer::new(original, result.clone()).unwrap();
+            serializer.set_option(option.clone());
+            serializer.serialize().unwrap();
+
+            let restored = PathBuf::from(format!("list_test_dir_{}", i));
+            let mut deserializer = Deserializer::new(result.clone(), restored.clone()).unwrap();
+            let entries = deserializer.list().unwrap();
+            assert!(!restored.exists());
+            assert_eq!(entries.len(), 10);
+            let board = entries
+                .iter()
+                .find(|e| e.path().ends_with("board-g43968feec_1920.jpg"))
+                .unwrap();
+            assert!(board.is_file());
+            assert!(!board.is_dir());
+            assert_eq!(board.size(), 914433);
+            assert_eq!(
+                board.checksum().unwrap(),
+                [55, 202, 20, 134, 104, 18, 50, 126, 23, 118, 216, 203, 178, 80, 80, 28]
+            );
+            assert_eq!(board.compressed_size().is_some(), option.is_compressed());
+
+            // Listing must not disturb deserializing.
+            deserializer.set_option(option);
+            deserializer.deserialize().unwrap();
+            assert!(restored.is_dir());
+
+            // Walking the entries of a file without index must list the same entries.
+            let without_index = PathBuf::from(format!("list_test_without_index_{}.bin", i));
+            let mut binary = fs::read(&result).unwrap();
+            binary[FILE_LABEL.len() + 4] &= !0x20;
+            fs::write(&without_index, binary).unwrap();
+            let mut deserializer =
+                Deserializer::new(without_index.clone(), restored.clone()).unwrap();
+            assert_eq!(deserializer.list().unwrap(), entries);
+
+            fs::remove_file(result).unwrap();
+            fs::remove_file(without_index).unwrap();
+            fs::remove_dir_all(restored).unwrap();
         }
-        Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::serialize::serializer::Serializer;
+    #[test]
+    fn extract_entry_test() {
+        const ENTRY: &str = "tests/original_images/dir2/dir3/books-g6617d4d97_1920.jpg";
+        let options = [
+            SerializeOption::default(),
+            SerializeOption::new().to_encrypt("test_password"),
+            SerializeOption::new()
+                .to_encrypt("test_password")
+                .to_compress(true),
+        ];
+        for (i, option) in options.into_iter().enumerate() {
+            let original = PathBuf::from("tests");
+            let result = PathBuf::from(format!("extract_entry_test_{}.bin", i));
+            let mut serializer = Serializer::new(original, result.clone()).unwrap();
+            serializer.set_option(option.clone());
+            serializer.serialize().unwrap();
 
-    use super::*;
-    use std::{path::PathBuf, sync::mpsc, thread};
+            let restored = PathBuf::from(format!("extract_entry_test_dir_{}", i));
+            let mut deserializer = Deserializer::new(result.clone(), restored.clone()).unwrap();
+            deserializer.set_option(option);
+            let mut data = Vec::new();
+            deserializer
+                .extract_entry_to_writer(ENTRY, &mut data)
+                .unwrap();
+            assert_eq!(data, fs::read(ENTRY).unwrap());
+
+            let destination = restored.join("books.jpg");
+            deserializer.extract_entry(ENTRY, &destination).unwrap();
+            assert_eq!(fs::read(&destination).unwrap(), data);
+            assert_eq!(fs::read_dir(&restored).unwrap().count(), 1);
+
+            let error = deserializer
+                .extract_entry_to_writer("tests/no_such_file.jpg", io::sink())
+                .unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::NotFound);
+
+            fs::remove_file(result).unwrap();
+            fs::remove_dir_all(restored).unwrap();
+        }
+    }
 
     #[test]
-    fn deserialize_file_test() {
-        let original = PathBuf::from("tests/original_images/dir1/board-g43968feec_1920.jpg");
-        let result = PathBuf::from("deserialize_file_test.bin");
+    fn extract_entry_without_index_test() {
+        const ENTRY: &str = "tests/original_images/dir4/colorful-2174045.png";
+        let original = PathBuf::from("tests");
+        let result = PathBuf::from("extract_entry_without_index_test.bin");
         let mut serializer = Serializer::new(original, result.clone()).unwrap();
-        serializer.set_option(SerializeOption::default());
         serializer.serialize().unwrap();
 
-        let restored = PathBuf::from("deserialize_file_test_restored_dir");
+        // Make a file like the older versions, which has no index.
+        let mut deserializer = Deserializer::new(result.clone(), PathBuf::new()).unwrap();
+        let index_offset = deserializer.read_trailer().unwrap().index_offset();
+        let mut binary = fs::read(&result).unwrap();
+        binary.truncate(index_offset as usize);
+        binary[FILE_LABEL.len() + 4] = 0;
+        fs::write(&result, binary).unwrap();
+
+        let mut deserializer = Deserializer::new(result.clone(), PathBuf::new()).unwrap();
+        assert!(deserializer.read_index().is_err());
+        let mut data = Vec::new();
+        deserializer
+            .extract_entry_to_writer(ENTRY, &mut data)
+            .unwrap();
+        assert_eq!(data, fs::read(ENTRY).unwrap());
+        fs::remove_file(result).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn deserialize_with_encrypted_metadata_test() {
+        const ENTRY: &str = "tests/original_images/dir2/dir3/books-g6617d4d97_1920.jpg";
+        let original = PathBuf::from("tests");
+        let result = PathBuf::from("deserialize_with_encrypted_metadata_test.bin");
+        let option = SerializeOption::new()
+            .to_encrypt("test_password")
+            .to_compress(true)
+            .to_encrypt_metadata(true);
+        let mut serializer = Serializer::new(original.clone(), result.clone()).unwrap();
+        serializer.set_option(option.clone());
+        serializer.serialize().unwrap();
+
+        // Neither the paths nor the number of entries are visible.
+        let binary = fs::read(&result).unwrap();
+        assert!(!binary.windows(ENTRY.len()).any(|w| w == ENTRY.as_bytes()));
+        // The length of the file count follows the version, the flag, the cipher and the codec.
+        assert_eq!(binary[FILE_LABEL.len() + 7], 0);
+
+        let restored = PathBuf::from("deserialize_with_encrypted_metadata_test_dir");
         let mut deserializer = Deserializer::new(result.clone(), restored.clone()).unwrap();
-        deserializer.set_option(SerializeOption::default());
+        assert_eq!(
+            deserializer.list().unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+        deserializer.set_option(SerializeOption::new().to_encrypt("wrong_password"));
+        assert_eq!(
+            deserializer.read_index().unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        deserializer.set_option(option);
+        assert_eq!(deserializer.list().unwrap().len(), 10);
+        assert_eq!(deserializer.read_index().unwrap().len(), 10);
+        let mut data = Vec::new();
+        deserializer
+            .extract_entry_to_writer(ENTRY, &mut data)
+            .unwrap();
+        assert_eq!(data, fs::read(ENTRY).unwrap());
         deserializer.deserialize().unwrap();
-        assert!(&result.is_file());
-        assert!(&restored.is_dir());
-        if result.is_file() {
-            fs::remove_file(result).unwrap();
-        }
-        if restored.is_dir() {
-            fs::remove_dir_all(restored).unwrap();
-        }
+        assert_eq!(
+            fs::read(restored.join(ENTRY)).unwrap(),
+            fs::read(ENTRY).unwrap()
+        );
+
+        // Metadata cannot be encrypted without encryption.
+        let mut serializer = Serializer::new(original, result.clone()).unwrap();
+        serializer.set_option(SerializeOption::new().to_encrypt_metadata(true));
+        assert_eq!(
+            serializer.serialize().unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+
+        fs::remove_file(result).unwrap();
+        fs::remove_dir_all(restored).unwrap();
     }
 
     #[test]
-    fn deserialize_dir_test() {
+    fn deserialize_with_multiple_passwords_test() {
+        const ENTRY: &str = "tests/original_images/dir1/board-g43968feec_1920.jpg";
         let original = PathBuf::from("tests");
-        let result = PathBuf::from("deserialize_dir_test.bin");
+        let result = PathBuf::from("deserialize_with_multiple_passwords_test.bin");
         let mut serializer = Serializer::new(original, result.clone()).unwrap();
-        serializer.set_option(SerializeOption::default());
+        serializer.set_option(
+            SerializeOption::new()
+                .to_encrypt("team_a_password")
+                .to_encrypt("team_b_password")
+                .to_argon2(Argon2Params::new(Argon2Variant::Argon2id, 8192, 2, 2)),
+        );
+        serializer.serialize().unwrap();
+
+        let mut deserializer = Deserializer::new(result.clone(), PathBuf::new()).unwrap();
+        for password in ["team_a_password", "team_b_password"] {
+            deserializer.set_option(SerializeOption::new().to_encrypt(password));
+            let mut data = Vec::new();
+            deserializer
+                .extract_entry_to_writer(ENTRY, &mut data)
+                .unwrap();
+            assert_eq!(data, fs::read(ENTRY).unwrap());
+        }
+        deserializer.set_option(
+            SerializeOption::new()
+                .to_encrypt("wrong_password")
+                .to_encrypt("team_b_password"),
+        );
+        deserializer
+            .extract_entry_to_writer(ENTRY, io::sink())
+            .unwrap();
+        deserializer.set_option(SerializeOption::new().to_encrypt("wrong_password"));
+        assert_eq!(
+            deserializer
+                .extract_entry_to_writer(ENTRY, io::sink())
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        fs::remove_file(result).unwrap();
+    }
+
+    #[test]
+    fn deserialize_with_key_and_keyfile_test() {
+        const KEYFILE: &str = "tests/original_images/dir1/laboratory-g8f9267f5f_1920.jpg";
+        const OTHER_KEYFILE: &str = "tests/original_images/dir1/폭발.jpg";
+        let original = PathBuf::from("tests/original_images/dir1");
+        let result = PathBuf::from("deserialize_with_key_and_keyfile_test.bin");
+        let restored = PathBuf::from("deserialize_with_key_and_keyfile_test_dir");
+        let key = [7u8; 32];
+        let mut serializer = Serializer::new(&original, &result).unwrap();
+        serializer.set_option(
+            SerializeOption::new()
+                .to_encrypt_with_key(&key)
+                .to_encrypt_with_keyfile(KEYFILE)
+                .to_encrypt_with_password_and_keyfile("test_password", KEYFILE),
+        );
         serializer.serialize().unwrap();
 
-        let restored = PathBuf::from("deserialize_test_dir");
         let mut deserializer = Deserializer::new(result.clone(), restored.clone()).unwrap();
-        deserializer.set_option(SerializeOption::default());
-        deserializer.deserialize().unwrap();
-        assert!(&result.is_file());
-        assert!(&restored.is_dir());
-        if result.is_file() {
-            fs::remove_file(result).unwrap();
+        assert_eq!(
+            deserializer.credential_kinds().unwrap(),
+            vec![
+                CredentialKind::Key,
+                CredentialKind::Keyfile,
+                CredentialKind::PasswordAndKeyfile
+            ]
+        );
+        for option in [
+            SerializeOption::new().to_encrypt_with_key(&key),
+            SerializeOption::new().to_encrypt_with_keyfile(KEYFILE),
+            SerializeOption::new().to_encrypt_with_password_and_keyfile("test_password", KEYFILE),
+        ] {
+            deserializer.set_option(option);
+            deserializer.deserialize().unwrap();
+            fs::remove_dir_all(&restored).unwrap();
         }
-        if restored.is_dir() {
-            fs::remove_dir_all(restored).unwrap();
+
+        // Wrong credentials of the right kinds.
+        for option in [
+            SerializeOption::new().to_encrypt_with_key(&[8u8; 32]),
+            SerializeOption::new().to_encrypt_with_keyfile(OTHER_KEYFILE),
+            SerializeOption::new().to_encrypt_with_password_and_keyfile("wrong_password", KEYFILE),
+        ] {
+            deserializer.set_option(option);
+            let error = deserializer.deserialize().unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
         }
+
+        // A credential of a wrong kind.
+        deserializer.set_option(SerializeOption::new().to_encrypt("test_password"));
+        let error = deserializer.deserialize().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(
+            error.to_string(),
+            "This file is encrypted with a raw key or a keyfile or a password with a keyfile, \
+            but only a password is given."
+        );
+        assert!(!restored.exists());
+
+        fs::remove_file(result).unwrap();
     }
 
     #[test]
-    fn deserialize_with_decrypt_test() {
+    fn deserialize_with_aes_test() {
+        let original = PathBuf::from("tests/original_images/dir1");
+        let result = PathBuf::from("deserialize_with_aes_test.bin");
+        let restored = PathBuf::from("deserialize_with_aes_test_dir");
+        let option = SerializeOption::new()
+            .to_encrypt("test_password")
+            .to_compress(true)
+            .to_encrypt_metadata(true)
+            .to_cipher(Cipher::Aes256GcmSiv);
+        let mut serializer = Serializer::new(&original, &result).unwrap();
+        serializer.set_option(option.clone());
+        serializer.serialize().unwrap();
+
+        let mut deserializer = Deserializer::new(result.clone(), restored.clone()).unwrap();
+        assert_eq!(
+            deserializer.read_header().unwrap().cipher(),
+            Cipher::Aes256GcmSiv
+        );
+        // The cipher is read from the file, not from the option.
+        let option = SerializeOption::new()
+            .to_encrypt("test_password")
+            .to_compress(true);
+        deserializer.set_option(option.clone());
+        assert_eq!(deserializer.list().unwrap().len(), 3);
+        deserializer.deserialize().unwrap();
+        assert!(restored.join("dir1").is_dir());
+        fs::remove_dir_all(&restored).unwrap();
+
+        // The new key slots are sealed with the cipher of the file.
+        let new_option = SerializeOption::new().to_encrypt("new_password");
+        crate::rekey(&result, &option, &new_option).unwrap();
+        let mut deserializer = Deserializer::new(result.clone(), restored.clone()).unwrap();
+        deserializer.set_option(new_option.to_compress(true));
+        deserializer.deserialize().unwrap();
+        fs::remove_dir_all(&restored).unwrap();
+
+        fs::remove_file(result).unwrap();
+    }
+
+    #[test]
+    fn deserialize_with_recipient_test() {
+        const ENTRY: &str = "tests/original_images/dir1/board-g43968feec_1920.jpg";
         let original = PathBuf::from("tests");
-        let result = PathBuf::from("deserialize_with_decrypt_test.bin");
+        let result = PathBuf::from("deserialize_with_recipient_test.bin");
+        let identity = Identity::generate();
         let mut serializer = Serializer::new(original, result.clone()).unwrap();
+        serializer.set_option(
+            SerializeOption::new()
+                .to_encrypt("test_password")
+                .to_recipient(&identity.recipient()),
+        );
+        serializer.serialize().unwrap();
+
+        let restored = PathBuf::from("deserialize_with_recipient_test_dir");
+        let mut deserializer = Deserializer::new(result.clone(), restored.clone()).unwrap();
+        for option in [
+            SerializeOption::new().to_identity(&identity),
+            SerializeOption::new().to_encrypt("test_password"),
+        ] {
+            deserializer.set_option(option);
+            let mut data = Vec::new();
+            deserializer
+                .extract_entry_to_writer(ENTRY, &mut data)
+                .unwrap();
+            assert_eq!(data, fs::read(ENTRY).unwrap());
+        }
+        deserializer.set_option(SerializeOption::new().to_identity(&Identity::generate()));
+        assert_eq!(
+            deserializer.deserialize().unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        deserializer.set_option(SerializeOption::new().to_identity(&identity));
+        deserializer.deserialize().unwrap();
+        assert!(restored.join(ENTRY).is_file());
+
+        fs::remove_file(result).unwrap();
+        fs::remove_dir_all(restored).unwrap();
+    }
+
+    #[test]
+    fn deserialize_tampered_test() {
+        let original = PathBuf::from("tests/original_images/dir1");
+        let result = PathBuf::from("deserialize_tampered_test.bin");
+        let mut serializer = Serializer::new(original.clone(), result.clone()).unwrap();
         serializer.set_option(SerializeOption::new().to_encrypt("test_password"));
         serializer.serialize().unwrap();
+        let binary = fs::read(&result).unwrap();
 
-        let serialized_file = PathBuf::from("deserialize_with_decrypt_test.bin");
-        let restored = PathBuf::from("deserialize_with_decrypt_test_dir");
-        let mut deserializer = Deserializer::new(serialized_file, restored.clone()).unwrap();
+        let restored = PathBuf::from("deserialize_tampered_test_dir");
+        let mut deserializer = Deserializer::new(result.clone(), restored.clone()).unwrap();
         deserializer.set_option(SerializeOption::new().to_encrypt("test_password"));
+        let index = deserializer.read_index().unwrap();
+        // Nothing is left in the restore path, even if the file is found tampered after restoring some entries.
+        let assert_tampered = |deserializer: &mut Deserializer, tampered: &[u8]| {
+            fs::write(&result, tampered).unwrap();
+            let error = deserializer.deserialize().unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+            assert!(error.to_string().contains("tampered"));
+            assert!(!restored.exists());
+        };
+
+        // Swap the first two entries.
+        let (first, second, third) = (
+            index[0].offset() as usize,
+            index[1].offset() as usize,
+            index[2].offset() as usize,
+        );
+        let mut swapped = binary[..first].to_vec();
+        swapped.extend_from_slice(&binary[second..third]);
+        swapped.extend_from_slice(&binary[first..second]);
+        swapped.extend_from_slice(&binary[third..]);
+        assert_tampered(&mut deserializer, &swapped);
+
+        // Change the hidden files flag of the header.
+        let mut flagged = binary.clone();
+        flagged[FILE_LABEL.len() + 4] ^= 0x10;
+        assert_tampered(&mut deserializer, &flagged);
+
+        // Change the data of the last entry, which is found after the other entries are restored.
+        let mut changed = binary.clone();
+        changed[index[index.len() - 1].offset() as usize + 200] ^= 0x01;
+        assert_tampered(&mut deserializer, &changed);
+
+        fs::write(&result, &binary).unwrap();
         deserializer.deserialize().unwrap();
-        assert!(&result.is_file());
-        assert!(&restored.is_dir());
-        if result.is_file() {
-            fs::remove_file(result).unwrap();
+        fs::remove_dir_all(&restored).unwrap();
+
+        // Change the size of the compressed data in front of it.
+        let option = SerializeOption::new()
+            .to_encrypt("test_password")
+            .to_compress(true);
+        let mut serializer = Serializer::new(original.clone(), result.clone()).unwrap();
+        serializer.set_option(option.clone());
+        serializer.serialize().unwrap();
+        let binary = fs::read(&result).unwrap();
+        let mut deserializer = Deserializer::new(result.clone(), restored.clone()).unwrap();
+        deserializer.set_option(option);
+        let index = deserializer.read_index().unwrap();
+        let entry = index
+            .iter()
+            .find(|e| e.compressed_size().is_some())
+            .unwrap();
+        let size_offset = entry.offset() as usize + entry.metadata().serialize().len();
+        let mut resized = binary.clone();
+        resized[size_offset] ^= 0x01;
+        assert_tampered(&mut deserializer, &resized);
+
+        // Swap the key slots of two passwords, which both still open the file.
+        let option = SerializeOption::new()
+            .to_encrypt("test_password")
+            .to_encrypt("other_password");
+        let mut serializer = Serializer::new(original, result.clone()).unwrap();
+        serializer.set_option(option.clone());
+        serializer.serialize().unwrap();
+        let binary = fs::read(&result).unwrap();
+        let mut deserializer = Deserializer::new(result.clone(), restored.clone()).unwrap();
+        deserializer.set_option(option);
+        // The header is the label, the version, the flag, the cipher id and the file count of 3 in 2 bytes.
+        let slot_count_offset = FILE_LABEL.len() + 4 + 1 + 1 + 2;
+        assert_eq!(binary[slot_count_offset], 2);
+        let entries_offset = deserializer.read_index().unwrap()[0].offset() as usize;
+        let slot_length = (entries_offset - slot_count_offset - 1) / 2;
+        let first_slot = slot_count_offset + 1;
+        let mut swapped = binary[..first_slot].to_vec();
+        swapped.extend_from_slice(&binary[first_slot + slot_length..entries_offset]);
+        swapped.extend_from_slice(&binary[first_slot..first_slot + slot_length]);
+        swapped.extend_from_slice(&binary[entries_offset..]);
+        assert_tampered(&mut deserializer, &swapped);
+
+        fs::write(&result, &binary).unwrap();
+        deserializer.deserialize().unwrap();
+
+        fs::remove_file(result).unwrap();
+        fs::remove_dir_all(restored).unwrap();
+    }
+
+    #[test]
+    fn deserialize_signed_test() {
+        const ENTRY: &str = "dir1/폭발.jpg";
+        let original = PathBuf::from("tests/original_images/dir1");
+        let result = PathBuf::from("deserialize_signed_test.bin");
+        let restored = PathBuf::from("deserialize_signed_test_dir");
+        let signing_key = SigningKey::generate();
+        let option = SerializeOption::new().to_encrypt("test_password");
+        let verifying_option = option.clone().to_verify(&signing_key.verifying_key());
+        let other_option = option
+            .clone()
+            .to_verify(&SigningKey::generate().verifying_key());
+
+        for placement in [SignaturePlacement::Embedded, SignaturePlacement::Detached] {
+            let mut serializer = Serializer::new(&original, &result).unwrap();
+            serializer.set_option(
+                option
+                    .clone()
+                    .to_sign(&signing_key)
+                    .to_signature_placement(placement),
+            );
+            serializer.serialize().unwrap();
+            let detached = detached_signature_path(&result);
+            assert_eq!(
+                detached.is_file(),
+                placement == SignaturePlacement::Detached
+            );
+
+            // A wrong key is refused before restoring anything.
+            let mut deserializer = Deserializer::new(result.clone(), restored.clone()).unwrap();
+            deserializer.set_option(other_option.clone());
+            let error = deserializer.deserialize().unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+            assert!(!restored.exists());
+
+            deserializer.set_option(verifying_option.clone());
+            assert_eq!(deserializer.read_index().unwrap().len(), 3);
+            let mut data = Vec::new();
+            deserializer
+                .extract_entry_to_writer(ENTRY, &mut data)
+                .unwrap();
+            assert_eq!(data, fs::read(original.join("폭발.jpg")).unwrap());
+            deserializer.deserialize().unwrap();
+            fs::remove_dir_all(&restored).unwrap();
+
+            // Tampering with the file breaks the signature.
+            let mut binary = fs::read(&result).unwrap();
+            binary[FILE_LABEL.len() + 4] ^= 0x10;
+            fs::write(&result, &binary).unwrap();
+            let error = deserializer.deserialize().unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+            assert!(!restored.exists());
+
+            if detached.is_file() {
+                fs::remove_file(detached).unwrap();
+            }
         }
-        if restored.is_dir() {
+
+        // An unsigned file is refused.
+        let mut serializer = Serializer::new(&original, &result).unwrap();
+        serializer.set_option(option);
+        serializer.serialize().unwrap();
+        let mut deserializer = Deserializer::new(result.clone(), restored.clone()).unwrap();
+        deserializer.set_option(verifying_option);
+        let error = deserializer.deserialize().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        assert!(!restored.exists());
+
+        fs::remove_file(result).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn deserialize_with_metadata_test() {
+        use filetime::FileTime;
+        use std::os::unix::fs::PermissionsExt;
+
+        let original = PathBuf::from("deserialize_with_metadata_test_original");
+        let script = original.join("run.sh");
+        fs::create_dir_all(&original).unwrap();
+        fs::write(&script, "#!/bin/sh\necho lusl\n").unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o754)).unwrap();
+        let modified = FileTime::from_unix_time(1_500_000_000, 123_456_789);
+        filetime::set_file_times(&script, modified, modified).unwrap();
+
+        let result = PathBuf::from("deserialize_with_metadata_test.bin");
+        let option = SerializeOption::new()
+            .to_preserve_metadata(true)
+            .to_restore_ownership(Ownership::Skip);
+        let mut serializer = Serializer::new(original.clone(), result.clone()).unwrap();
+        serializer.set_option(option.clone());
+        serializer.serialize().unwrap();
+
+        let restored = PathBuf::from("deserialize_with_metadata_test_dir");
+        let mut deserializer = Deserializer::new(result.clone(), restored.clone()).unwrap();
+        deserializer.set_option(option);
+        let entries = deserializer.list().unwrap();
+        assert_eq!(
+            entries[0].extended().unwrap().mode().unwrap() & 0o7777,
+            0o754
+        );
+        deserializer.deserialize().unwrap();
+
+        let restored_script = restored.join("deserialize_with_metadata_test_original/run.sh");
+        let metadata = fs::metadata(&restored_script).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o7777, 0o754);
+        assert_eq!(FileTime::from_last_modification_time(&metadata), modified);
+
+        fs::remove_file(result).unwrap();
+        fs::remove_dir_all(original).unwrap();
+        fs::remove_dir_all(restored).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn deserialize_symlink_test() {
+        use std::os::unix::fs::symlink;
+
+        let original = PathBuf::from("deserialize_symlink_test_original");
+        fs::create_dir_all(&original).unwrap();
+        fs::write(original.join("data.txt"), "lusl").unwrap();
+        symlink("data.txt", original.join("link.txt")).unwrap();
+        symlink("no_such_file", original.join("dangling")).unwrap();
+
+        for (i, follow) in [false, true].into_iter().enumerate() {
+            let result = PathBuf::from(format!("deserialize_symlink_test_{}.bin", i));
+            let option = SerializeOption::new()
+                .to_encrypt("test_password")
+                .to_follow_symlinks(follow);
+            let mut serializer = Serializer::new(original.clone(), result.clone()).unwrap();
+            serializer.set_option(option.clone());
+            serializer.serialize().unwrap();
+
+            let restored = PathBuf::from(format!("deserialize_symlink_test_dir_{}", i));
+            let mut deserializer = Deserializer::new(result.clone(), restored.clone()).unwrap();
+            deserializer.set_option(option);
+            let entries = deserializer.list().unwrap();
+            let link = entries
+                .iter()
+                .find(|e| e.path().ends_with("link.txt"))
+                .unwrap();
+            assert_eq!(link.is_symlink(), !follow);
+            assert_eq!(link.is_file(), follow);
+            deserializer.deserialize().unwrap();
+
+            let restored_link = restored.join("deserialize_symlink_test_original/link.txt");
+            assert_eq!(fs::read(&restored_link).unwrap(), b"lusl");
+            match follow {
+                false => {
+                    assert_eq!(link.link_target().unwrap(), Path::new("data.txt"));
+                    assert_eq!(
+                        fs::read_link(&restored_link).unwrap(),
+                        PathBuf::from("data.txt")
+                    );
+                    let dangling = restored.join("deserialize_symlink_test_original/dangling");
+                    assert!(dangling.is_symlink());
+
+                    let destination = restored.join("extracted_link");
+                    deserializer
+                        .extract_entry("deserialize_symlink_test_original/link.txt", &destination)
+                        .unwrap();
+                    assert!(destination.is_symlink());
+                    assert!(deserializer
+                        .extract_entry_to_writer(
+                            "deserialize_symlink_test_original/link.txt",
+                            io::sink()
+                        )
+                        .is_err());
+                }
+                true => assert!(!restored_link.is_symlink()),
+            }
+            fs::remove_file(result).unwrap();
             fs::remove_dir_all(restored).unwrap();
         }
+        fs::remove_dir_all(original).unwrap();
     }
 
+    #[cfg(unix)]
     #[test]
-    fn deserialize_with_compress_test() {
-        let original = PathBuf::from("tests");
-        let result = PathBuf::from("deserialize_compress_test.bin");
-        let mut serializer = Serializer::new(original, result.clone()).unwrap();
-        serializer.set_option(SerializeOption::new().to_compress(true));
+    fn deserialize_through_symlink_test() {
+        use std::os::unix::fs::symlink;
+
+        let original = PathBuf::from("deserialize_through_symlink_test_original");
+        let outside = PathBuf::from("deserialize_through_symlink_test_outside");
+        fs::create_dir_all(original.join("dirx")).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        fs::write(original.join("dirx/evil"), "lusl").unwrap();
+        symlink(fs::canonicalize(&outside).unwrap(), original.join("link")).unwrap();
+
+        let result = PathBuf::from("deserialize_through_symlink_test.bin");
+        let mut serializer = Serializer::new(original.clone(), result.clone()).unwrap();
         serializer.serialize().unwrap();
 
-        let serialized_file = PathBuf::from("deserialize_compress_test.bin");
-        let restored = PathBuf::from("deserialize_compress_test_dir");
-        let mut deserializer = Deserializer::new(serialized_file, restored.clone()).unwrap();
-        deserializer.set_option(SerializeOption::new().to_compress(true));
-        deserializer.deserialize().unwrap();
-        assert!(&result.is_file());
-        assert!(&restored.is_dir());
-        if result.is_file() {
+        // Move the file under the symbolic link, which is restored before it.
+        let mut binary = fs::read(&result).unwrap();
+        let from = b"_original/dirx/evil";
+        let to = b"_original/link/evil";
+        for i in 0..binary.len() - from.len() {
+            if &binary[i..i + from.len()] == from {
+                binary[i..i + to.len()].copy_from_slice(to);
+            }
+        }
+        fs::write(&result, binary).unwrap();
+
+        let restored = PathBuf::from("deserialize_through_symlink_test_dir");
+        let mut deserializer = Deserializer::new(result.clone(), restored.clone()).unwrap();
+        let error = deserializer.deserialize().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(!outside.join("evil").exists());
+
+        fs::remove_file(result).unwrap();
+        fs::remove_dir_all(restored).unwrap();
+        fs::remove_dir_all(original).unwrap();
+        fs::remove_dir_all(outside).unwrap();
+    }
+
+    #[test]
+    fn deserialize_empty_dir_test() {
+        let original = PathBuf::from("deserialize_empty_dir_test_original");
+        for dir in ["logs", "data", "empty/inner"] {
+            fs::create_dir_all(original.join(dir)).unwrap();
+        }
+        fs::write(original.join("data/a.txt"), "lusl").unwrap();
+        let modified = filetime::FileTime::from_unix_time(1_500_000_000, 0);
+        filetime::set_file_times(original.join("data"), modified, modified).unwrap();
+
+        for (i, (directory_entries, expected)) in [
+            (DirectoryEntries::Empty, vec!["empty/inner", "logs"]),
+            (
+                DirectoryEntries::All,
+                vec!["data", "empty", "empty/inner", "logs"],
+            ),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let result = PathBuf::from(format!("deserialize_empty_dir_test_{}.bin", i));
+            let option = SerializeOption::new()
+                .to_compress(true)
+                .to_preserve_metadata(true)
+                .to_restore_ownership(Ownership::Skip)
+                .to_directory_entries(directory_entries);
+            let mut serializer = Serializer::new(original.clone(), result.clone()).unwrap();
+            serializer.set_option(option.clone());
+            serializer.serialize().unwrap();
+
+            let restored = PathBuf::from(format!("deserialize_empty_dir_test_dir_{}", i));
+            let mut deserializer = Deserializer::new(result.clone(), restored.clone()).unwrap();
+            deserializer.set_option(option);
+            let entries = deserializer.list().unwrap();
+            let mut dirs: Vec<&Path> = entries
+                .iter()
+                .filter(|e| e.is_dir())
+                .map(|e| e.path().strip_prefix(&original).unwrap())
+                .collect();
+            dirs.sort();
+            assert_eq!(dirs, expected.iter().map(Path::new).collect::<Vec<_>>());
+            assert!(entries
+                .iter()
+                .all(|e| !e.is_dir() || e.compressed_size().is_none()));
+            deserializer.deserialize().unwrap();
+
+            let restored_root = restored.join(&original);
+            assert!(restored_root.join("logs").is_dir());
+            assert!(restored_root.join("empty/inner").is_dir());
+            assert_eq!(fs::read(restored_root.join("data/a.txt")).unwrap(), b"lusl");
+            let data_modified = filetime::FileTime::from_last_modification_time(
+                &fs::metadata(restored_root.join("data")).unwrap(),
+            );
+            assert_eq!(
+                data_modified == modified,
+                directory_entries == DirectoryEntries::All
+            );
             fs::remove_file(result).unwrap();
+            fs::remove_dir_all(restored).unwrap();
         }
-        if restored.is_dir() {
+        fs::remove_dir_all(original).unwrap();
+    }
+
+    #[test]
+    fn deserialize_filtered_dir_test() {
+        let original = PathBuf::from("deserialize_filtered_dir_test_original");
+        for dir in ["logs", "uploads", "cache", "src"] {
+            fs::create_dir_all(original.join(dir)).unwrap();
+        }
+        for file in [
+            ".gitignore",
+            "logs/.gitkeep",
+            "uploads/a.tmp",
+            "cache/debug.log",
+            "src/main.rs",
+        ] {
+            fs::write(original.join(file), "*.log\n").unwrap();
+        }
+
+        for (i, (option, expected)) in [
+            (
+                SerializeOption::new()
+                    .to_exclude("*.tmp")
+                    .to_respect_ignore_files(true),
+                vec!["cache", "logs", "uploads"],
+            ),
+            // Directories are filtered by the include patterns as well as files.
+            (
+                SerializeOption::new()
+                    .to_include("*.rs")
+                    .to_include("logs/"),
+                vec!["logs"],
+            ),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let result = PathBuf::from(format!("deserialize_filtered_dir_test_{}.bin", i));
+            let mut serializer = Serializer::new(original.clone(), result.clone()).unwrap();
+            serializer.set_option(option);
+            serializer.serialize().unwrap();
+
+            let restored = PathBuf::from(format!("deserialize_filtered_dir_test_dir_{}", i));
+            let mut deserializer = Deserializer::new(result.clone(), restored.clone()).unwrap();
+            let entries = deserializer.list().unwrap();
+            let mut dirs: Vec<&Path> = entries
+                .iter()
+                .filter(|e| e.is_dir())
+                .map(|e| e.path().strip_prefix(&original).unwrap())
+                .collect();
+            dirs.sort();
+            assert_eq!(dirs, expected.iter().map(Path::new).collect::<Vec<_>>());
+            deserializer.deserialize().unwrap();
+
+            let restored_root = restored.join(&original);
+            for dir in expected {
+                assert!(restored_root.join(dir).is_dir());
+                assert_eq!(fs::read_dir(restored_root.join(dir)).unwrap().count(), 0);
+            }
+            assert!(restored_root.join("src/main.rs").is_file());
+            fs::remove_file(result).unwrap();
             fs::remove_dir_all(restored).unwrap();
         }
+        fs::remove_dir_all(original).unwrap();
     }
 
+    #[cfg(unix)]
     #[test]
-    fn deserialize_with_decrypt_compress_test() {
-        let original = PathBuf::from("tests");
-        let result = PathBuf::from("deserialize_decrypt_compress_test.bin");
+    fn deserialize_hardlink_test() {
+        use std::os::unix::fs::MetadataExt;
+
+        let original = PathBuf::from("deserialize_hardlink_test_original");
+        fs::create_dir_all(original.join("sub")).unwrap();
+        fs::write(original.join("a.txt"), "lusl").unwrap();
+        fs::hard_link(original.join("a.txt"), original.join("b.txt")).unwrap();
+        fs::hard_link(original.join("a.txt"), original.join("sub/c.txt")).unwrap();
+
+        let result = PathBuf::from("deserialize_hardlink_test.bin");
         let option = SerializeOption::new()
-            .to_compress(true)
-            .to_encrypt("test_password");
-        let mut serializer = Serializer::new(original, result.clone()).unwrap();
+            .to_encrypt("test_password")
+            .to_compress(true);
+        let mut serializer = Serializer::new(original.clone(), result.clone()).unwrap();
         serializer.set_option(option.clone());
         serializer.serialize().unwrap();
 
-        let serialized_file = PathBuf::from("deserialize_decrypt_compress_test.bin");
-        let restored = PathBuf::from("deserialize_decrypt_compress_test_dir");
-        let mut deserializer = Deserializer::new(serialized_file, restored.clone()).unwrap();
-        deserializer.set_option(option.clone());
+        let restored = PathBuf::from("deserialize_hardlink_test_dir");
+        let mut deserializer = Deserializer::new(result.clone(), restored.clone()).unwrap();
+        deserializer.set_option(option);
+        let entries = deserializer.list().unwrap();
+        let files: Vec<&EntryInfo> = entries.iter().filter(|e| e.is_file()).collect();
+        let links: Vec<&EntryInfo> = entries.iter().filter(|e| e.is_hardlink()).collect();
+        assert_eq!((files.len(), links.len()), (1, 2));
+        for link in links {
+            assert_eq!(link.link_target().unwrap(), files[0].path());
+            assert_eq!(link.size(), 4);
+            assert!(link.compressed_size().is_none());
+        }
         deserializer.deserialize().unwrap();
-        assert!(&result.is_file());
-        assert!(&restored.is_dir());
-        if result.is_file() {
-            fs::remove_file(result).unwrap();
+
+        let restored_root = restored.join(&original);
+        let inode = |name: &str| fs::metadata(restored_root.join(name)).unwrap().ino();
+        assert_eq!(inode("a.txt"), inode("b.txt"));
+        assert_eq!(inode("a.txt"), inode("sub/c.txt"));
+        assert_eq!(fs::read(restored_root.join("sub/c.txt")).unwrap(), b"lusl");
+
+        let mut data = Vec::new();
+        for name in ["a.txt", "b.txt", "sub/c.txt"] {
+            data.clear();
+            deserializer
+                .extract_entry_to_writer(original.join(name), &mut data)
+                .unwrap();
+            assert_eq!(data, b"lusl");
         }
-        if restored.is_dir() {
+
+        fs::remove_file(result).unwrap();
+        fs::remove_dir_all(original).unwrap();
+        fs::remove_dir_all(restored).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn deserialize_malicious_hardlink_test() {
+        let original = PathBuf::from("deserialize_malicious_hardlink_test_original");
+        let outside = PathBuf::from("deserialize_malicious_hardlink_test_outside.txt");
+        fs::create_dir_all(&original).unwrap();
+        fs::write(original.join("a.txt"), "lusl").unwrap();
+        fs::hard_link(original.join("a.txt"), original.join("b.txt")).unwrap();
+        fs::write(&outside, "outside").unwrap();
+
+        let result = PathBuf::from("deserialize_malicious_hardlink_test.bin");
+        let mut serializer = Serializer::new(original.clone(), result.clone()).unwrap();
+        serializer.serialize().unwrap();
+        let binary = fs::read(&result).unwrap();
+        let mut deserializer = Deserializer::new(result.clone(), PathBuf::new()).unwrap();
+        let entries = deserializer.list().unwrap();
+        let link = entries.iter().find(|e| e.is_hardlink()).unwrap();
+        let target = link.link_target().unwrap().to_str().unwrap().as_bytes();
+        let target_offset = link.offset() as usize
+            + binary[link.offset() as usize..]
+                .windows(target.len())
+                .position(|w| w == target)
+                .unwrap();
+
+        // Link to a file outside of the restore path, by a relative path, an absolute path,
+        // or a path in the restore path which is not restored from the file.
+        let escaping = format!("../{}", outside.to_str().unwrap());
+        let absolute = format!("/{}", "x".repeat(target.len() - 1));
+        let not_restored = original.join("c.txt").to_str().unwrap().to_string();
+        for malicious in [escaping, absolute, not_restored] {
+            let mut malicious = malicious.into_bytes();
+            malicious.resize(target.len(), b'x');
+            let mut tampered = binary.clone();
+            tampered[target_offset..target_offset + target.len()].copy_from_slice(&malicious);
+            fs::write(&result, tampered).unwrap();
+
+            let restored = PathBuf::from("deserialize_malicious_hardlink_test_dir");
+            let mut deserializer = Deserializer::new(result.clone(), restored.clone()).unwrap();
+            let error = deserializer.deserialize().unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+            assert!(error.to_string().contains("malicious"));
             fs::remove_dir_all(restored).unwrap();
         }
+
+        assert_eq!(fs::read(&outside).unwrap(), b"outside");
+        fs::remove_file(result).unwrap();
+        fs::remove_file(outside).unwrap();
+        fs::remove_dir_all(original).unwrap();
     }
 
     #[test]
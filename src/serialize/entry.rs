@@ -0,0 +1,123 @@
+use std::path::PathBuf;
+
+use crate::encrypt::Cipher;
+
+use super::{
+    extended::ExtendedMetaData,
+    meta::{Compression, MetaData},
+};
+
+/// Description of an entry in the serialized file.
+///
+/// Returned by [`Deserializer::list`](crate::Deserializer::list) and
+/// [`Deserializer::read_index`](crate::Deserializer::read_index) without restoring anything.
+/// It is also what the entry index at the end of the file records.
+#[derive(Debug, PartialEq)]
+pub struct EntryInfo {
+    offset: u64,
+    metadata: MetaData,
+    compressed_size: Option<u64>,
+}
+
+impl EntryInfo {
+    pub(crate) fn new(offset: u64, metadata: MetaData, compressed_size: Option<u64>) -> Self {
+        EntryInfo {
+            offset,
+            metadata,
+            compressed_size,
+        }
+    }
+
+    /// Returns the offset of the entry from the start of the serialized file.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Returns the path of the entry, relative to the parent of the serialized root.
+    pub fn path(&self) -> &PathBuf {
+        self.metadata.path()
+    }
+
+    /// Returns the original size of the entry.
+    pub fn size(&self) -> u64 {
+        self.metadata.size()
+    }
+
+    /// Returns true if the entry is a regular file.
+    pub fn is_file(&self) -> bool {
+        self.metadata.is_file()
+    }
+
+    /// Returns true if the entry is a directory.
+    pub fn is_dir(&self) -> bool {
+        self.metadata.is_dir()
+    }
+
+    /// Returns true if the entry is a symbolic link.
+    pub fn is_symlink(&self) -> bool {
+        self.metadata.is_symlink()
+    }
+
+    /// Returns true if the entry is a hard link to a file serialized before.
+    pub fn is_hardlink(&self) -> bool {
+        self.metadata.is_hardlink()
+    }
+
+    /// Returns the target path if the entry is a symbolic link,
+    /// or the path of the linked entry if it is a hard link.
+    pub fn link_target(&self) -> Option<&PathBuf> {
+        self.metadata.link_target()
+    }
+
+    /// Returns the [MD5](md5) checksum of the entry.
+    pub fn checksum(&self) -> Option<&[u8]> {
+        self.metadata.checksum().as_deref()
+    }
+
+    /// Returns the permissions, ownership and timestamps of the entry,
+    /// if the file was serialized with preserving them.
+    pub fn extended(&self) -> Option<&ExtendedMetaData> {
+        self.metadata.extended()
+    }
+
+    /// Returns the size of the compressed data if the entry is compressed by itself.
+    ///
+    /// Entries of a compressed file can be stored without compressing, like JPEG images,
+    /// or compressed with other entries in a solid block, whose size is not of one entry.
+    pub fn compressed_size(&self) -> Option<u64> {
+        self.compressed_size
+    }
+
+    /// Returns true if the entry is compressed with other entries in a solid block.
+    pub fn is_solid(&self) -> bool {
+        matches!(self.metadata.compression(), Some(Compression::Solid { .. }))
+    }
+    /// Returns the metadata of the entry.
+    pub(crate) fn metadata(&self) -> &MetaData {
+        &self.metadata
+    }
+
+    /// Set the size of the compressed data of the entry.
+    pub(crate) fn set_compressed_size(&mut self, compressed_size: Option<u64>) {
+        self.compressed_size = compressed_size;
+    }
+
+    /// Returns the entry moved by the given distance in the serialized file.
+    pub(crate) fn moved_by(self, distance: i64) -> Self {
+        EntryInfo {
+            offset: self.offset.wrapping_add_signed(distance),
+            ..self
+        }
+    }
+
+    /// Serialize the entry as it is recorded in the index.
+    /// If the key is given, the metadata is encrypted with it and the cipher.
+    pub(crate) fn serialize(&self, metadata_key: Option<&[u8]>, cipher: Cipher) -> Vec<u8> {
+        let mut binary = self.offset.to_le_bytes().to_vec();
+        match metadata_key {
+            Some(key) => binary.append(&mut self.metadata.serialize_sealed(key, cipher)),
+            None => binary.append(&mut self.metadata.serialize()),
+        }
+        binary
+    }
+}
@@ -0,0 +1,402 @@
+//! Extended metadata module.
+//!
+//! Extended metadata are optional attributes of an entry, like permissions, ownership and timestamps.
+//! If the extended metadata flag of the header is set, they are saved right after the checksum of every metadata.
+//! They are stored in the following format:
+//! - Block length: 2 bytes (little endian), not including itself
+//! - Field flag: 1 byte, which fields are present
+//! - Mode: 4 bytes (little endian)
+//! - Owner: uid 4 bytes and gid 4 bytes (little endian)
+//! - Owner names: user name and group name, each of them is 1 byte length and UTF-8 bytes
+//! - Times: access time and modification time
+//! - Change time: status change time
+//!
+//! Every time is 8 bytes seconds since the Unix epoch and 4 bytes nanoseconds (little endian).
+//!
+
+use std::{
+    fs, io,
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use filetime::FileTime;
+
+use crate::binary::binary_to_u64;
+
+const MODE_FIELD: u8 = 0x80;
+const OWNER_FIELD: u8 = 0x40;
+const OWNER_NAME_FIELD: u8 = 0x20;
+const TIME_FIELD: u8 = 0x10;
+const CHANGE_TIME_FIELD: u8 = 0x08;
+
+const TIME_LENGTH: usize = 12;
+
+/// Permissions, ownership and timestamps of an entry.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ExtendedMetaData {
+    mode: Option<u32>,
+    owner: Option<(u32, u32)>,
+    user_name: Option<String>,
+    group_name: Option<String>,
+    times: Option<(FileTime, FileTime)>,
+    changed: Option<FileTime>,
+}
+
+impl ExtendedMetaData {
+    /// Read the extended metadata of the file or directory.
+    ///
+    /// The mode, ownership and status change time are only available on Unix.
+    pub fn read<T: AsRef<Path>>(path: T) -> io::Result<Self> {
+        Ok(Self::from_metadata(&fs::metadata(path)?))
+    }
+
+    /// Read the extended metadata of the symbolic link itself, without following it.
+    pub fn read_symlink<T: AsRef<Path>>(path: T) -> io::Result<Self> {
+        Ok(Self::from_metadata(&fs::symlink_metadata(path)?))
+    }
+
+    fn from_metadata(metadata: &fs::Metadata) -> Self {
+        let mut extended = ExtendedMetaData {
+            times: Some((
+                FileTime::from_last_access_time(metadata),
+                FileTime::from_last_modification_time(metadata),
+            )),
+            ..Default::default()
+        };
+        #[cfg(unix)]
+        {
+            use nix::unistd::{Gid, Group, Uid, User};
+            use std::os::unix::fs::MetadataExt;
+
+            extended.mode = Some(metadata.mode());
+            extended.owner = Some((metadata.uid(), metadata.gid()));
+            extended.user_name = match User::from_uid(Uid::from_raw(metadata.uid())) {
+                Ok(Some(u)) => Some(u.name),
+                _ => None,
+            };
+            extended.group_name = match Group::from_gid(Gid::from_raw(metadata.gid())) {
+                Ok(Some(g)) => Some(g.name),
+                _ => None,
+            };
+            extended.changed = Some(FileTime::from_unix_time(
+                metadata.ctime(),
+                metadata.ctime_nsec() as u32,
+            ));
+        }
+        extended
+    }
+
+    /// Returns the permission bits and the file type bits.
+    pub fn mode(&self) -> Option<u32> {
+        self.mode
+    }
+
+    /// Returns the user id of the owner.
+    pub fn uid(&self) -> Option<u32> {
+        self.owner.map(|(uid, _)| uid)
+    }
+
+    /// Returns the group id of the owner.
+    pub fn gid(&self) -> Option<u32> {
+        self.owner.map(|(_, gid)| gid)
+    }
+
+    /// Returns the user name of the owner.
+    pub fn user_name(&self) -> Option<&str> {
+        self.user_name.as_deref()
+    }
+
+    /// Returns the group name of the owner.
+    pub fn group_name(&self) -> Option<&str> {
+        self.group_name.as_deref()
+    }
+
+    /// Returns the last access time, or `None` if it is not recorded or out of the range of [`SystemTime`].
+    pub fn accessed(&self) -> Option<SystemTime> {
+        self.times
+            .and_then(|(accessed, _)| to_system_time(accessed))
+    }
+
+    /// Returns the last modification time, or `None` if it is not recorded or out of the range of [`SystemTime`].
+    pub fn modified(&self) -> Option<SystemTime> {
+        self.times
+            .and_then(|(_, modified)| to_system_time(modified))
+    }
+
+    /// Returns the last status change time, or `None` if it is not recorded or out of the range of [`SystemTime`].
+    pub fn changed(&self) -> Option<SystemTime> {
+        self.changed.and_then(to_system_time)
+    }
+
+    /// Restore the extended metadata to the restored file or directory.
+    ///
+    /// The owner is found by the user and group names first, and by the ids if there is no such name.
+    /// The status change time can't be restored, because it is always set by the system.
+    pub fn restore<T: AsRef<Path>>(&self, path: T, restore_owner: bool) -> io::Result<()> {
+        self.restore_with(path, restore_owner, false)
+    }
+
+    /// Restore the extended metadata to the restored symbolic link itself, without following it.
+    ///
+    /// The mode of a symbolic link is not restored, because it can't be changed on most systems.
+    pub fn restore_symlink<T: AsRef<Path>>(&self, path: T, restore_owner: bool) -> io::Result<()> {
+        self.restore_with(path, restore_owner, true)
+    }
+
+    #[cfg_attr(not(unix), allow(unused_variables))]
+    fn restore_with<T: AsRef<Path>>(
+        &self,
+        path: T,
+        restore_owner: bool,
+        is_symlink: bool,
+    ) -> io::Result<()> {
+        #[cfg(unix)]
+        {
+            use nix::unistd::{Group, User};
+            use std::os::unix::fs::{chown, lchown, PermissionsExt};
+
+            // Change the owner first, because it may clear the set-user-ID bit.
+            if let (true, Some((uid, gid))) = (restore_owner, self.owner) {
+                let uid = match self.user_name.as_deref().map(User::from_name) {
+                    Some(Ok(Some(u))) => u.uid.as_raw(),
+                    _ => uid,
+                };
+                let gid = match self.group_name.as_deref().map(Group::from_name) {
+                    Some(Ok(Some(g))) => g.gid.as_raw(),
+                    _ => gid,
+                };
+                match is_symlink {
+                    true => lchown(&path, Some(uid), Some(gid))?,
+                    false => chown(&path, Some(uid), Some(gid))?,
+                }
+            }
+            if let (false, Some(mode)) = (is_symlink, self.mode) {
+                fs::set_permissions(&path, fs::Permissions::from_mode(mode & 0o7777))?;
+            }
+        }
+        if let Some((accessed, modified)) = self.times {
+            match is_symlink {
+                true => filetime::set_symlink_file_times(&path, accessed, modified)?,
+                false => filetime::set_file_times(&path, accessed, modified)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Converts the extended metadata into a binary vector, including the block length.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut field_flag = 0u8;
+        let mut fields: Vec<u8> = Vec::new();
+        if let Some(mode) = self.mode {
+            field_flag += MODE_FIELD;
+            fields.extend_from_slice(&mode.to_le_bytes());
+        }
+        if let Some((uid, gid)) = self.owner {
+            field_flag += OWNER_FIELD;
+            fields.extend_from_slice(&uid.to_le_bytes());
+            fields.extend_from_slice(&gid.to_le_bytes());
+        }
+        if self.user_name.is_some() || self.group_name.is_some() {
+            field_flag += OWNER_NAME_FIELD;
+            for name in [&self.user_name, &self.group_name] {
+                // An empty or too long name is saved as an unknown name.
+                match name {
+                    Some(n) if n.len() <= u8::MAX as usize => {
+                        fields.push(n.len() as u8);
+                        fields.extend_from_slice(n.as_bytes());
+                    }
+                    _ => fields.push(0),
+                }
+            }
+        }
+        if let Some((accessed, modified)) = self.times {
+            field_flag += TIME_FIELD;
+            fields.append(&mut time_to_binary(accessed));
+            fields.append(&mut time_to_binary(modified));
+        }
+        if let Some(changed) = self.changed {
+            field_flag += CHANGE_TIME_FIELD;
+            fields.append(&mut time_to_binary(changed));
+        }
+
+        let mut binary = ((fields.len() + 1) as u16).to_le_bytes().to_vec();
+        binary.push(field_flag);
+        binary.append(&mut fields);
+        binary
+    }
+
+    /// Read the extended metadata from binary vector, which doesn't include the block length.
+    pub fn from_binary(binary: &[u8]) -> io::Result<Self> {
+        let mut reader = FieldReader { binary, index: 0 };
+        let field_flag = reader.take(1)?[0];
+        let mut extended = ExtendedMetaData::default();
+        if field_flag & MODE_FIELD != 0 {
+            extended.mode = Some(binary_to_u64(reader.take(4)?) as u32);
+        }
+        if field_flag & OWNER_FIELD != 0 {
+            let uid = binary_to_u64(reader.take(4)?) as u32;
+            let gid = binary_to_u64(reader.take(4)?) as u32;
+            extended.owner = Some((uid, gid));
+        }
+        if field_flag & OWNER_NAME_FIELD != 0 {
+            extended.user_name = reader.take_name()?;
+            extended.group_name = reader.take_name()?;
+        }
+        if field_flag & TIME_FIELD != 0 {
+            let accessed = binary_to_time(reader.take(TIME_LENGTH)?);
+            let modified = binary_to_time(reader.take(TIME_LENGTH)?);
+            extended.times = Some((accessed, modified));
+        }
+        if field_flag & CHANGE_TIME_FIELD != 0 {
+            extended.changed = Some(binary_to_time(reader.take(TIME_LENGTH)?));
+        }
+        Ok(extended)
+    }
+}
+
+/// Returns true if the current process is running as root.
+pub fn is_root() -> bool {
+    #[cfg(unix)]
+    {
+        nix::unistd::geteuid().is_root()
+    }
+    #[cfg(not(unix))]
+    {
+        false
+    }
+}
+
+struct FieldReader<'a> {
+    binary: &'a [u8],
+    index: usize,
+}
+
+impl<'a> FieldReader<'a> {
+    fn take(&mut self, length: usize) -> io::Result<&'a [u8]> {
+        if self.index + length > self.binary.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Wrong extended metadata!",
+            ));
+        }
+        self.index += length;
+        Ok(&self.binary[self.index - length..self.index])
+    }
+
+    fn take_name(&mut self) -> io::Result<Option<String>> {
+        let length = self.take(1)?[0] as usize;
+        let name = self.take(length)?;
+        Ok(match length {
+            0 => None,
+            _ => Some(String::from_utf8_lossy(name).to_string()),
+        })
+    }
+}
+
+fn time_to_binary(time: FileTime) -> Vec<u8> {
+    let mut binary = time.unix_seconds().to_le_bytes().to_vec();
+    binary.extend_from_slice(&time.nanoseconds().to_le_bytes());
+    binary
+}
+
+fn binary_to_time(binary: &[u8]) -> FileTime {
+    FileTime::from_unix_time(
+        binary_to_u64(&binary[..8]) as i64,
+        binary_to_u64(&binary[8..12]) as u32,
+    )
+}
+
+/// Converts the time into [`SystemTime`], or returns `None` if it is out of the range.
+///
+/// The time is read from the serialized file, so it may be anything.
+fn to_system_time(time: FileTime) -> Option<SystemTime> {
+    let nanoseconds = Duration::from_nanos(time.nanoseconds() as u64);
+    let seconds = match time.unix_seconds() {
+        s if s >= 0 => UNIX_EPOCH.checked_add(Duration::from_secs(s as u64)),
+        s => UNIX_EPOCH.checked_sub(Duration::from_secs(s.unsigned_abs())),
+    };
+    seconds?.checked_add(nanoseconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORIGINAL_FILE: &str = "tests/original_images/dir1/board-g43968feec_1920.jpg";
+
+    #[test]
+    fn extended_serialize_test() {
+        let extended = ExtendedMetaData {
+            mode: Some(0o100755),
+            owner: Some((1000, 100)),
+            user_name: Some(String::from("user")),
+            group_name: None,
+            times: Some((
+                FileTime::from_unix_time(1_600_000_000, 123_456_789),
+                FileTime::from_unix_time(-86_400, 1),
+            )),
+            changed: None,
+        };
+        let binary = extended.serialize();
+        assert_eq!(binary_to_u64(&binary[..2]) as usize, binary.len() - 2);
+        assert_eq!(
+            ExtendedMetaData::from_binary(&binary[2..]).unwrap(),
+            extended
+        );
+        assert!(ExtendedMetaData::from_binary(&binary[2..binary.len() - 1]).is_err());
+        assert_eq!(
+            extended.modified().unwrap(),
+            UNIX_EPOCH - Duration::from_secs(86_400) + Duration::from_nanos(1)
+        );
+    }
+
+    #[test]
+    fn extended_read_test() {
+        let extended = ExtendedMetaData::read(ORIGINAL_FILE).unwrap();
+        let metadata = fs::metadata(ORIGINAL_FILE).unwrap();
+        assert_eq!(extended.modified().unwrap(), metadata.modified().unwrap());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            assert_eq!(extended.mode(), Some(metadata.mode()));
+            assert_eq!(extended.uid(), Some(metadata.uid()));
+            assert!(extended.changed().is_some());
+        }
+        let binary = extended.serialize();
+        assert_eq!(
+            ExtendedMetaData::from_binary(&binary[2..]).unwrap(),
+            extended
+        );
+    }
+
+    #[test]
+    fn out_of_range_time_test() {
+        let extended = ExtendedMetaData {
+            mode: None,
+            owner: None,
+            user_name: None,
+            group_name: None,
+            times: Some((
+                FileTime::from_unix_time(i64::MIN, 0),
+                FileTime::from_unix_time(i64::MAX, 999_999_999),
+            )),
+            changed: Some(FileTime::from_unix_time(1_600_000_000, 0)),
+        };
+        // Whether these fit depends on the platform, but they must never panic.
+        assert_eq!(
+            extended.accessed(),
+            UNIX_EPOCH.checked_sub(Duration::from_secs(i64::MIN.unsigned_abs()))
+        );
+        assert_eq!(
+            extended.modified(),
+            UNIX_EPOCH
+                .checked_add(Duration::from_secs(i64::MAX as u64))
+                .and_then(|time| time.checked_add(Duration::from_nanos(999_999_999)))
+        );
+        assert_eq!(
+            extended.changed(),
+            Some(UNIX_EPOCH + Duration::from_secs(1_600_000_000))
+        );
+    }
+}
@@ -0,0 +1,306 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
+use ignore::{
+    gitignore::{Gitignore, GitignoreBuilder},
+    Match,
+};
+use regex::RegexSet;
+
+use super::option::SerializeOption;
+
+/// Prefix of a pattern which is a regular expression, not a glob.
+pub const REGEX_PREFIX: &str = "re:";
+
+/// Filter of paths to serialize, made from the include and exclude patterns of [`SerializeOption`].
+///
+/// Patterns are matched against the path relative to the parent of the serialized root,
+/// which is the same as the path saved in the metadata.
+/// A glob without `/` is also matched against the file name, so `*.log` matches log files in any directory.
+/// A glob ending with `/`, like `target/`, matches directories only.
+/// A pattern starting with `re:` is a regular expression, which is searched in the relative path.
+pub struct PathFilter {
+    parent: PathBuf,
+    include: Option<Patterns>,
+    exclude: Patterns,
+}
+
+impl PathFilter {
+    /// Make a new filter for the given root directory.
+    pub fn new<T: AsRef<Path>>(root: T, option: &SerializeOption) -> io::Result<Self> {
+        let include = match option.include_patterns().is_empty() {
+            true => None,
+            false => Some(Patterns::new(option.include_patterns())?),
+        };
+        Ok(PathFilter {
+            parent: match root.as_ref().parent() {
+                Some(p) => p.to_path_buf(),
+                None => PathBuf::new(),
+            },
+            include,
+            exclude: Patterns::new(option.exclude_patterns())?,
+        })
+    }
+
+    /// Returns true if the path matches one of the exclude patterns.
+    ///
+    /// An excluded directory must not be traversed.
+    pub fn is_excluded<T: AsRef<Path>>(&self, path: T, is_dir: bool) -> bool {
+        self.exclude
+            .is_match(self.relative_path(&path), file_name(&path), is_dir)
+    }
+
+    /// Returns true if there is no include pattern or the path matches one of them.
+    pub fn is_included<T: AsRef<Path>>(&self, path: T, is_dir: bool) -> bool {
+        match &self.include {
+            Some(include) => include.is_match(self.relative_path(&path), file_name(&path), is_dir),
+            None => true,
+        }
+    }
+
+    fn relative_path<'a>(&self, path: &'a impl AsRef<Path>) -> &'a Path {
+        match path.as_ref().strip_prefix(&self.parent) {
+            Ok(p) => p,
+            Err(_) => path.as_ref(),
+        }
+    }
+}
+
+/// Names of ignore files read in every directory, in increasing order of precedence.
+pub const IGNORE_FILE_NAMES: [&str; 3] = [".gitignore", ".ignore", ".luslignore"];
+
+/// Ignore rules of a directory, read from the ignore files of it and its ancestors under the serialized root.
+///
+/// The rules follow the gitignore semantics, including negation, directory only rules and anchored patterns.
+/// The rules of the nearest directory take precedence.
+/// If the rules are disabled, nothing is read and nothing is ignored.
+#[derive(Clone)]
+pub struct IgnoreRules {
+    enabled: bool,
+    matchers: Vec<Gitignore>,
+}
+
+impl IgnoreRules {
+    pub fn new(enabled: bool) -> Self {
+        IgnoreRules {
+            enabled,
+            matchers: Vec::new(),
+        }
+    }
+
+    /// Returns the rules for the children of the given directory, adding the ignore files in it.
+    pub fn with_dir<T: AsRef<Path>>(&self, dir: T) -> io::Result<Self> {
+        let mut rules = self.clone();
+        if !self.enabled {
+            return Ok(rules);
+        }
+        let mut builder = GitignoreBuilder::new(&dir);
+        let mut has_ignore_file = false;
+        for name in IGNORE_FILE_NAMES {
+            let ignore_file = dir.as_ref().join(name);
+            if ignore_file.is_file() {
+                if let Some(e) = builder.add(&ignore_file) {
+                    return Err(ignore_error(e));
+                }
+                has_ignore_file = true;
+            }
+        }
+        if has_ignore_file {
+            rules.matchers.push(builder.build().map_err(ignore_error)?);
+        }
+        Ok(rules)
+    }
+
+    /// Returns true if the path is ignored by the rules.
+    ///
+    /// The `.git` directory is always ignored if the rules are enabled.
+    pub fn is_ignored<T: AsRef<Path>>(&self, path: T, is_dir: bool) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        if is_dir && file_name(&path) == Path::new(".git") {
+            return true;
+        }
+        for matcher in self.matchers.iter().rev() {
+            match matcher.matched(&path, is_dir) {
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+                Match::None => (),
+            }
+        }
+        false
+    }
+}
+
+fn ignore_error(e: ignore::Error) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Invalid ignore file: {}", e),
+    )
+}
+
+fn file_name<T: AsRef<Path>>(path: &T) -> &Path {
+    match path.as_ref().file_name() {
+        Some(n) => Path::new(n),
+        None => path.as_ref(),
+    }
+}
+
+/// Include or exclude patterns, compiled into the sets of globs and regular expressions.
+struct Patterns {
+    globs: GlobSet,
+    dir_globs: GlobSet,
+    regexes: RegexSet,
+}
+
+impl Patterns {
+    fn new(patterns: &[String]) -> io::Result<Self> {
+        let mut globs = GlobSetBuilder::new();
+        let mut dir_globs = GlobSetBuilder::new();
+        let mut regexes = Vec::new();
+        for pattern in patterns {
+            if let Some(regex) = pattern.strip_prefix(REGEX_PREFIX) {
+                regexes.push(regex);
+                continue;
+            }
+            // A pattern ending with `/` is matched against directories only.
+            let (builder, pattern) = match pattern.strip_suffix('/') {
+                Some(p) => (&mut dir_globs, p.trim_end_matches('/')),
+                None => (&mut globs, pattern.as_str()),
+            };
+            builder.add(match pattern.contains('/') {
+                true => make_glob(GlobBuilder::new(pattern).literal_separator(true))?,
+                // A pattern without `/` is matched against the file name only.
+                false => make_glob(&mut GlobBuilder::new(pattern))?,
+            });
+        }
+        let regexes = match RegexSet::new(regexes) {
+            Ok(s) => s,
+            Err(e) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Invalid pattern: {}", e),
+                ))
+            }
+        };
+        Ok(Patterns {
+            globs: build_glob_set(globs)?,
+            dir_globs: build_glob_set(dir_globs)?,
+            regexes,
+        })
+    }
+
+    fn is_match(&self, relative_path: &Path, file_name: &Path, is_dir: bool) -> bool {
+        let is_glob_match =
+            |globs: &GlobSet| globs.is_match(relative_path) || globs.is_match(file_name);
+        is_glob_match(&self.globs)
+            || (is_dir && is_glob_match(&self.dir_globs))
+            || self.regexes.is_match(&relative_path.to_string_lossy())
+    }
+}
+
+fn build_glob_set(builder: GlobSetBuilder) -> io::Result<GlobSet> {
+    match builder.build() {
+        Ok(s) => Ok(s),
+        Err(e) => Err(io::Error::new(io::ErrorKind::InvalidInput, e.to_string())),
+    }
+}
+
+fn make_glob(builder: &mut GlobBuilder) -> io::Result<Glob> {
+    match builder.build() {
+        Ok(g) => Ok(g),
+        Err(e) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid pattern: {}", e),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use super::{IgnoreRules, PathFilter};
+    use crate::serialize::option::SerializeOption;
+
+    #[test]
+    fn exclude_test() {
+        let option = SerializeOption::new()
+            .to_exclude("target/")
+            .to_exclude("*.log")
+            .to_exclude("root/docs/**/*.md");
+        let filter = PathFilter::new("root", &option).unwrap();
+        assert!(filter.is_excluded(PathBuf::from("root/target"), true));
+        assert!(filter.is_excluded(PathBuf::from("root/sub/target"), true));
+        assert!(filter.is_excluded(PathBuf::from("root/sub/debug.log"), false));
+        assert!(filter.is_excluded(PathBuf::from("root/docs/a/b/README.md"), false));
+        assert!(!filter.is_excluded(PathBuf::from("root/README.md"), false));
+        assert!(!filter.is_excluded(PathBuf::from("root/src/main.rs"), false));
+        assert!(filter.is_included(PathBuf::from("root/src/main.rs"), false));
+        // A pattern ending with `/` matches directories only.
+        assert!(!filter.is_excluded(PathBuf::from("root/sub/target"), false));
+    }
+
+    #[test]
+    fn regex_test() {
+        let option = SerializeOption::new()
+            .to_include(r"re:^root/src/.*\.rs$")
+            .to_exclude(r"re:_test\.rs$");
+        let filter = PathFilter::new("root", &option).unwrap();
+        assert!(filter.is_included(PathBuf::from("root/src/main.rs"), false));
+        assert!(filter.is_included(PathBuf::from("root/src/a/lib.rs"), false));
+        assert!(!filter.is_included(PathBuf::from("root/main.rs"), false));
+        assert!(filter.is_excluded(PathBuf::from("root/src/filter_test.rs"), false));
+        assert!(!filter.is_excluded(PathBuf::from("root/src/main.rs"), false));
+
+        let option = SerializeOption::new().to_exclude("re:(");
+        assert!(PathFilter::new("root", &option).is_err());
+    }
+
+    #[test]
+    fn include_test() {
+        let option = SerializeOption::new()
+            .to_include("*.rs")
+            .to_include("root/Cargo.toml");
+        let filter = PathFilter::new("root", &option).unwrap();
+        assert!(filter.is_included(PathBuf::from("root/src/main.rs"), false));
+        assert!(filter.is_included(PathBuf::from("root/Cargo.toml"), false));
+        assert!(!filter.is_included(PathBuf::from("root/sub/Cargo.toml"), false));
+        assert!(!filter.is_included(PathBuf::from("root/README.md"), false));
+    }
+
+    #[test]
+    fn ignore_rules_test() {
+        let root = PathBuf::from("ignore_rules_test");
+        fs::create_dir_all(root.join("sub/build")).unwrap();
+        fs::write(root.join(".gitignore"), "*.log\n!keep.log\n/build/\ntmp\n").unwrap();
+        fs::write(root.join("sub/.luslignore"), "!tmp\n").unwrap();
+
+        let rules = IgnoreRules::new(true).with_dir(&root).unwrap();
+        assert!(rules.is_ignored(root.join("debug.log"), false));
+        assert!(!rules.is_ignored(root.join("keep.log"), false));
+        assert!(rules.is_ignored(root.join("build"), true));
+        assert!(!rules.is_ignored(root.join("build"), false));
+        assert!(rules.is_ignored(root.join("tmp"), false));
+        assert!(rules.is_ignored(root.join(".git"), true));
+        assert!(!rules.is_ignored(root.join("sub"), true));
+
+        let sub_rules = rules.with_dir(root.join("sub")).unwrap();
+        assert!(sub_rules.is_ignored(root.join("sub/debug.log"), false));
+        assert!(!sub_rules.is_ignored(root.join("sub/build"), true));
+        assert!(!sub_rules.is_ignored(root.join("sub/tmp"), false));
+
+        let disabled = IgnoreRules::new(false).with_dir(&root).unwrap();
+        assert!(!disabled.is_ignored(root.join("debug.log"), false));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn invalid_pattern_test() {
+        let option = SerializeOption::new().to_exclude("a[");
+        assert!(PathFilter::new("root", &option).is_err());
+    }
+}
@@ -2,15 +2,47 @@ use std::io;
 
 use crate::{
     binary::{binary_to_u64, is_flag_true},
+    compress::Codec,
+    encrypt::Cipher,
     version::Version,
 };
 
-use super::version::{get_major_version, get_minor_version, get_patch_version};
+use super::{
+    option::HiddenFiles,
+    version::{get_major_version, get_minor_version, get_patch_version},
+};
 
 pub const FILE_LABEL: &str = "LUSL Serialized File";
 pub const VERSION_START_POINTER: u8 = 0x1;
 const ENCRYPTED_FLAG: u8 = 0x80;
 const COMPRESSED_FLAG: u8 = 0x40;
+const INDEXED_FLAG: u8 = 0x20;
+const HIDDEN_INCLUDED_FLAG: u8 = 0x10;
+const HIDDEN_DIRS_SKIPPED_FLAG: u8 = 0x08;
+const EXTENDED_METADATA_FLAG: u8 = 0x04;
+const METADATA_ENCRYPTED_FLAG: u8 = 0x02;
+const KEY_SLOTS_FLAG: u8 = 0x01;
+
+/// The first version that can write an entry index at the end of the file.
+pub const INDEX_MIN_VERSION: Version = Version::new(2, 2, 0);
+/// The first version that records how hidden files were handled in the flag.
+pub const HIDDEN_FILES_MIN_VERSION: Version = Version::new(2, 2, 0);
+/// The first version that can write extended metadata after every metadata.
+pub const EXTENDED_METADATA_MIN_VERSION: Version = Version::new(2, 3, 0);
+/// The first version that can encrypt the metadata of the entries.
+pub const METADATA_ENCRYPTION_MIN_VERSION: Version = Version::new(2, 7, 0);
+/// The first version that can wrap the data key in key slots.
+pub const KEY_SLOTS_MIN_VERSION: Version = Version::new(2, 8, 0);
+/// The first version that authenticates the header and the order of the entries in encrypted files.
+pub const AUTHENTICATION_MIN_VERSION: Version = Version::new(2, 10, 0);
+/// The first version that can embed a signature at the end of the file.
+pub const SIGNATURE_MIN_VERSION: Version = Version::new(2, 11, 0);
+/// The first version that records the cipher of encrypted files after the flag.
+pub const CIPHER_MIN_VERSION: Version = Version::new(2, 13, 0);
+/// The first version that records the compression codec of compressed files after the cipher.
+pub const CODEC_MIN_VERSION: Version = Version::new(2, 14, 0);
+/// The first version that records whether every entry of compressed files is compressed.
+pub const ENTRY_COMPRESSION_MIN_VERSION: Version = Version::new(2, 16, 0);
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 
@@ -18,6 +50,13 @@ pub struct Header {
     version: Version,
     is_encrypted: bool,
     is_compressed: bool,
+    is_indexed: bool,
+    hidden_files: HiddenFiles,
+    has_extended_metadata: bool,
+    is_metadata_encrypted: bool,
+    has_key_slots: bool,
+    cipher: Cipher,
+    codec: Codec,
     file_count: u64,
 }
 
@@ -32,6 +71,13 @@ impl Header {
             ),
             is_encrypted: false,
             is_compressed: false,
+            is_indexed: false,
+            hidden_files: HiddenFiles::default(),
+            has_extended_metadata: false,
+            is_metadata_encrypted: false,
+            has_key_slots: false,
+            cipher: Cipher::default(),
+            codec: Codec::default(),
             file_count: 0,
         }
     }
@@ -46,6 +92,13 @@ impl Header {
             ),
             is_encrypted,
             is_compressed,
+            is_indexed: false,
+            hidden_files: HiddenFiles::default(),
+            has_extended_metadata: false,
+            is_metadata_encrypted: false,
+            has_key_slots: false,
+            cipher: Cipher::default(),
+            codec: Codec::default(),
             file_count,
         }
     }
@@ -65,17 +118,125 @@ impl Header {
         self.is_compressed
     }
 
+    /// Returns true if the file has an entry index at the end.
+    pub fn is_indexed(&self) -> bool {
+        self.is_indexed
+    }
+
+    /// Set whether the file has an entry index at the end.
+    pub fn set_indexed(&mut self, is_indexed: bool) {
+        self.is_indexed = is_indexed;
+    }
+
+    /// Returns how hidden files and directories were handled when serializing.
+    pub fn hidden_files(&self) -> HiddenFiles {
+        self.hidden_files
+    }
+
+    /// Set how hidden files and directories were handled when serializing.
+    pub fn set_hidden_files(&mut self, hidden_files: HiddenFiles) {
+        self.hidden_files = hidden_files;
+    }
+
+    /// Returns true if every metadata is followed by extended metadata.
+    pub fn has_extended_metadata(&self) -> bool {
+        self.has_extended_metadata
+    }
+
+    /// Set whether every metadata is followed by extended metadata.
+    pub fn set_extended_metadata(&mut self, has_extended_metadata: bool) {
+        self.has_extended_metadata = has_extended_metadata;
+    }
+
+    /// Returns true if the metadata of the entries is encrypted as well as their data.
+    pub fn is_metadata_encrypted(&self) -> bool {
+        self.is_metadata_encrypted
+    }
+
+    /// Set whether the metadata of the entries is encrypted as well as their data.
+    pub fn set_metadata_encrypted(&mut self, is_metadata_encrypted: bool) {
+        self.is_metadata_encrypted = is_metadata_encrypted;
+    }
+
+    /// Returns true if the data key is wrapped in key slots after the header.
+    ///
+    /// Otherwise the key of an encrypted file is derived from the password and the salt after the header.
+    pub fn has_key_slots(&self) -> bool {
+        self.has_key_slots
+    }
+
+    /// Set whether the data key is wrapped in key slots after the header.
+    pub fn set_key_slots(&mut self, has_key_slots: bool) {
+        self.has_key_slots = has_key_slots;
+    }
+
+    /// Returns the cipher which encrypts the file.
+    ///
+    /// Files older than 2.13.0 don't record it, and are always encrypted with XChaCha20-Poly1305.
+    pub fn cipher(&self) -> Cipher {
+        self.cipher
+    }
+
+    /// Set the cipher which encrypts the file.
+    pub fn set_cipher(&mut self, cipher: Cipher) {
+        self.cipher = cipher;
+    }
+
+    /// Returns true if the cipher is recorded after the flag, which is for encrypted files since 2.13.0.
+    pub fn has_cipher_id(&self) -> bool {
+        self.is_encrypted && self.version >= CIPHER_MIN_VERSION
+    }
+
+    /// Returns the codec which compresses the file.
+    ///
+    /// Its level is not recorded, so the default level of the codec is returned for a deserialized header.
+    /// Files older than 2.14.0 don't record it, and are always compressed with zlib.
+    pub fn codec(&self) -> Codec {
+        self.codec
+    }
+
+    /// Set the codec which compresses the file.
+    pub fn set_codec(&mut self, codec: Codec) {
+        self.codec = codec;
+    }
+
+    /// Returns true if the codec is recorded after the cipher, which is for compressed files since 2.14.0.
+    pub fn has_codec_id(&self) -> bool {
+        self.is_compressed && self.version >= CODEC_MIN_VERSION
+    }
+
+    /// Returns true if the metadata of every entry with data records whether the entry is compressed,
+    /// which is for compressed files since 2.16.0.
+    ///
+    /// Otherwise every entry of a compressed file is compressed.
+    pub fn has_entry_compression(&self) -> bool {
+        self.is_compressed && self.version >= ENTRY_COMPRESSION_MIN_VERSION
+    }
+
     /// Returns the number of files in the archive.
+    ///
+    /// If the metadata is encrypted, the count is hidden and 0 until it is set by [`Header::set_file_count`].
     pub fn file_count(&self) -> u64 {
         self.file_count
     }
 
+    /// Set the number of files in the archive.
+    pub fn set_file_count(&mut self, file_count: u64) {
+        self.file_count = file_count;
+    }
+
     /// Converts the header into a binary vector.
     pub fn to_binary_vec(&self) -> Vec<u8> {
         let mut binary = Vec::new();
         binary.append(&mut self.label_to_binary());
         binary.append(&mut self.version_to_binary());
         binary.append(&mut self.flag_to_binary());
+        if self.has_cipher_id() {
+            binary.push(self.cipher.id());
+        }
+        if self.has_codec_id() {
+            binary.push(self.codec.id());
+        }
         binary.append(&mut self.file_count_to_binary());
         binary
     }
@@ -103,16 +264,40 @@ impl Header {
     /// The flag is a byte that contains the following information:
     /// - Bit 0: Encrypted
     /// - Bit 1: Compressed
-    /// - Bit 2-7: Reserved
+    /// - Bit 2: Indexed
+    /// - Bit 3: Hidden files and directories are included
+    /// - Bit 4: Hidden directories are skipped as well as hidden files
+    /// - Bit 5: Extended metadata
+    /// - Bit 6: Metadata is encrypted
+    /// - Bit 7: Key slots
+    ///
+    /// If neither bit 3 nor bit 4 is set, only hidden files were skipped.
     fn flag_to_binary(&self) -> Vec<u8> {
         let mut binary = Vec::with_capacity(1);
         let mut flag: u8 = 0x0;
-        if let true = self.is_encrypted {
+        if self.is_encrypted {
             flag += ENCRYPTED_FLAG;
         }
-        if let true = self.is_compressed {
+        if self.is_compressed {
             flag += COMPRESSED_FLAG;
         }
+        if self.is_indexed {
+            flag += INDEXED_FLAG;
+        }
+        match self.hidden_files {
+            HiddenFiles::Include => flag += HIDDEN_INCLUDED_FLAG,
+            HiddenFiles::SkipFiles => (),
+            HiddenFiles::SkipAll => flag += HIDDEN_DIRS_SKIPPED_FLAG,
+        }
+        if self.has_extended_metadata {
+            flag += EXTENDED_METADATA_FLAG;
+        }
+        if self.is_metadata_encrypted {
+            flag += METADATA_ENCRYPTED_FLAG;
+        }
+        if self.has_key_slots {
+            flag += KEY_SLOTS_FLAG;
+        }
         binary.push(flag);
         binary
     }
@@ -130,7 +315,7 @@ impl Header {
         }
         let file_count_bytes = self.file_count.to_le_bytes().len() - index;
         count_binary.push(file_count_bytes as u8);
-        for i in &self.file_count.to_le_bytes()[..file_count_bytes as usize] {
+        for i in &self.file_count.to_le_bytes()[..file_count_bytes] {
             count_binary.push(*i);
         }
         count_binary
@@ -142,7 +327,7 @@ impl Header {
         for i in binary {
             label.push(*i as char);
         }
-        if let false = label == FILE_LABEL {
+        if label != FILE_LABEL {
             Err(io::Error::new(
                 io::ErrorKind::InvalidData,
                 "Wrong file label!",
@@ -169,6 +354,29 @@ impl Header {
     pub fn deserialize_flag(&mut self, binary: &[u8]) {
         self.is_encrypted = is_flag_true(binary[0], ENCRYPTED_FLAG);
         self.is_compressed = is_flag_true(binary[0], COMPRESSED_FLAG);
+        self.is_indexed = is_flag_true(binary[0], INDEXED_FLAG);
+        self.hidden_files = if is_flag_true(binary[0], HIDDEN_INCLUDED_FLAG) {
+            HiddenFiles::Include
+        } else if is_flag_true(binary[0], HIDDEN_DIRS_SKIPPED_FLAG) {
+            HiddenFiles::SkipAll
+        } else {
+            HiddenFiles::SkipFiles
+        };
+        self.has_extended_metadata = is_flag_true(binary[0], EXTENDED_METADATA_FLAG);
+        self.is_metadata_encrypted = is_flag_true(binary[0], METADATA_ENCRYPTED_FLAG);
+        self.has_key_slots = is_flag_true(binary[0], KEY_SLOTS_FLAG);
+    }
+
+    /// Deserialize the cipher identifier and set the header's cipher.
+    pub fn deserialize_cipher(&mut self, binary: &[u8]) -> io::Result<()> {
+        self.cipher = Cipher::from_id(binary[0])?;
+        Ok(())
+    }
+
+    /// Deserialize the codec identifier and set the header's codec.
+    pub fn deserialize_codec(&mut self, binary: &[u8]) -> io::Result<()> {
+        self.codec = Codec::from_id(binary[0])?;
+        Ok(())
     }
 
     /// Deserialize the file count and set the header's file count.
@@ -183,10 +391,16 @@ mod tests {
 
     #[test]
     fn header_test() {
-        let header = Header::with(true, false, 83);
+        let mut header = Header::with(true, false, 83);
+        header.set_indexed(true);
+        header.set_hidden_files(HiddenFiles::SkipAll);
+        header.set_extended_metadata(true);
+        header.set_metadata_encrypted(true);
+        header.set_key_slots(true);
+        header.set_cipher(Cipher::Aes256GcmSiv);
         let header_binary = header.to_binary_vec();
         let mut new_header = Header::new();
-        let mut counter = FILE_LABEL.as_bytes().len();
+        let mut counter = FILE_LABEL.len();
         new_header
             .deserialize_label(&header_binary[..counter])
             .unwrap();
@@ -196,16 +410,47 @@ mod tests {
         counter += 4;
         new_header.deserialize_flag(&header_binary[counter..counter + 1]);
         counter += 1;
+        assert!(new_header.has_cipher_id());
+        new_header
+            .deserialize_cipher(&header_binary[counter..counter + 1])
+            .unwrap();
+        counter += 1;
         let file_count_byte_size = header_binary[counter];
         counter += 1;
         new_header.deserialize_file_count(
             &header_binary[counter..counter + file_count_byte_size as usize],
         );
-        assert_eq!(new_header.is_encrypted, true);
-        assert_eq!(new_header.is_compressed, false);
+        assert!(new_header.is_encrypted);
+        assert!(!new_header.is_compressed);
+        assert!(new_header.is_indexed);
+        assert_eq!(new_header.hidden_files, HiddenFiles::SkipAll);
+        assert!(new_header.has_extended_metadata);
+        assert!(new_header.is_metadata_encrypted);
+        assert!(new_header.has_key_slots);
+        assert_eq!(new_header.cipher, Cipher::Aes256GcmSiv);
         assert_eq!(new_header.file_count, 83);
     }
 
+    #[test]
+    fn codec_header_test() {
+        let mut header = Header::with(false, true, 1);
+        header.set_codec(Codec::zstd(-1));
+        let header_binary = header.to_binary_vec();
+        // The codec follows the flag, with no cipher because the file is not encrypted.
+        let counter = FILE_LABEL.len() + 5;
+        assert!(header.has_codec_id());
+        let mut new_header = Header::new();
+        new_header.deserialize_flag(&header_binary[counter - 1..counter]);
+        new_header
+            .deserialize_codec(&header_binary[counter..counter + 1])
+            .unwrap();
+        assert_eq!(
+            new_header.codec(),
+            Codec::zstd(zstd::DEFAULT_COMPRESSION_LEVEL)
+        );
+        assert_eq!(&header_binary[counter + 1..], &[1, 1]);
+    }
+
     #[test]
     fn version_compare_test() {
         let version1 = Version::new(1, 0, 0);
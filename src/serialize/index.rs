@@ -0,0 +1,110 @@
+//! Entry index module.
+//!
+//! The index is written after the last entry of the serialized file.
+//! It records the metadata and the byte offset of every entry, as [`EntryInfo`](super::entry::EntryInfo),
+//! so a reader can locate an entry without walking the whole file.
+//!
+//! The index is stored in the following format:
+//! - Entry offset: 8 bytes (little endian), repeated with the metadata of each entry
+//! - Metadata: variable, same format as the metadata in front of each entry
+//!
+//! If the metadata is encrypted, it is stored sealed with its length in the same way as in front of each entry,
+//! and the entry count of the trailer is 0.
+//!
+//! The index is followed by a fixed size trailer which points at it:
+//! - Index offset: 8 bytes (little endian)
+//! - Entry count: 8 bytes (little endian)
+//! - Index label: 8 bytes
+//!
+
+use std::io;
+
+use crate::binary::binary_to_u64;
+
+pub const INDEX_LABEL: &[u8; 8] = b"LUSL IDX";
+pub const OFFSET_LENGTH: usize = 8;
+pub const TRAILER_LENGTH: usize = 24;
+
+/// The fixed size trailer at the end of an indexed file.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Trailer {
+    index_offset: u64,
+    entry_count: u64,
+}
+
+impl Trailer {
+    pub fn new(index_offset: u64, entry_count: u64) -> Self {
+        Trailer {
+            index_offset,
+            entry_count,
+        }
+    }
+
+    /// Returns the offset of the index from the start of the serialized file.
+    pub fn index_offset(&self) -> u64 {
+        self.index_offset
+    }
+
+    /// Returns the number of entries in the index.
+    pub fn entry_count(&self) -> u64 {
+        self.entry_count
+    }
+
+    /// Converts the trailer into a binary vector.
+    pub fn to_binary_vec(&self) -> Vec<u8> {
+        let mut binary = Vec::with_capacity(TRAILER_LENGTH);
+        binary.extend_from_slice(&self.index_offset.to_le_bytes());
+        binary.extend_from_slice(&self.entry_count.to_le_bytes());
+        binary.extend_from_slice(INDEX_LABEL);
+        binary
+    }
+
+    /// Read the trailer from binary vector.
+    pub fn from_binary(binary: &[u8]) -> io::Result<Self> {
+        if binary.len() != TRAILER_LENGTH || &binary[16..] != INDEX_LABEL {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Wrong index trailer! The index of the file may be broken.",
+            ));
+        }
+        Ok(Trailer {
+            index_offset: binary_to_u64(&binary[..8]),
+            entry_count: binary_to_u64(&binary[8..16]),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::{
+        encrypt::Cipher,
+        serialize::{entry::EntryInfo, meta::MetaData},
+    };
+
+    const ORIGINAL_FILE: &str = "tests/original_images/dir1/board-g43968feec_1920.jpg";
+
+    #[test]
+    fn trailer_test() {
+        let trailer = Trailer::new(1234567, 10);
+        let binary = trailer.to_binary_vec();
+        assert_eq!(binary.len(), TRAILER_LENGTH);
+        assert_eq!(Trailer::from_binary(&binary).unwrap(), trailer);
+
+        let mut broken = binary.clone();
+        broken[TRAILER_LENGTH - 1] = 0;
+        assert!(Trailer::from_binary(&broken).is_err());
+    }
+
+    #[test]
+    fn index_entry_serialize_test() {
+        let metadata = MetaData::from(&PathBuf::from(ORIGINAL_FILE));
+        let metadata_binary = metadata.serialize();
+        let entry = EntryInfo::new(4321, metadata, None);
+        let binary = entry.serialize(None, Cipher::default());
+        assert_eq!(binary_to_u64(&binary[..OFFSET_LENGTH]), 4321);
+        assert_eq!(&binary[OFFSET_LENGTH..], metadata_binary);
+    }
+}
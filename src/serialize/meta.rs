@@ -1,11 +1,36 @@
-use std::fs::File;
+use std::fs::{self, File};
+use std::io::{self, Read};
 use std::path::{Path, PathBuf};
 
-use crate::binary::{binary_to_u64, get_checksum, is_flag_true};
+use crate::binary::{binary_to_u64, get_bytes_checksum, get_checksum, is_flag_true};
+use crate::encrypt::Cipher;
+
+use super::{extended::ExtendedMetaData, header::Header};
 
 const FILE_FLAG: u8 = 0x80;
 const DIR_FLAG: u8 = 0x40;
 const SYMLINK_FLAG: u8 = 0x20;
+const HARDLINK_FLAG: u8 = 0x10;
+const STORED_ENTRY: u8 = 0x00;
+const COMPRESSED_ENTRY: u8 = 0x01;
+const SOLID_ENTRY: u8 = 0x02;
+const SOLID_BLOCK_END_ENTRY: u8 = 0x03;
+/// The longest target of a link in bytes, which is the same as the longest path.
+pub const MAX_LINK_TARGET_LENGTH: usize = u16::MAX as usize;
+
+/// How the data of an entry of a compressed file is written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    /// The data is written without compressing, right after the metadata.
+    Stored,
+    /// The data is compressed by itself, right after the metadata.
+    Compressed,
+    /// The data is compressed with the data of other entries in a solid block,
+    /// which follows the metadata of the last entry in it.
+    ///
+    /// The offset is where the data starts in the decompressed block.
+    Solid { offset: u64, is_last: bool },
+}
 
 #[derive(Debug)]
 pub struct MetaData {
@@ -14,7 +39,11 @@ pub struct MetaData {
     is_file: bool,
     is_dir: bool,
     is_symlink: bool,
+    is_hardlink: bool,
     checksum: Option<Vec<u8>>,
+    link_target: Option<PathBuf>,
+    extended: Option<ExtendedMetaData>,
+    compression: Option<Compression>,
 }
 
 impl MetaData {
@@ -25,7 +54,72 @@ impl MetaData {
             is_file: false,
             is_dir: false,
             is_symlink: false,
+            is_hardlink: false,
             checksum: None,
+            link_target: None,
+            extended: None,
+            compression: None,
+        }
+    }
+
+    /// Make the metadata of the directory, which has no data.
+    pub fn from_dir<T: AsRef<Path>>(dir_path: T) -> MetaData {
+        MetaData {
+            path: dir_path.as_ref().to_path_buf(),
+            is_dir: true,
+            ..MetaData::new()
+        }
+    }
+
+    /// Make the metadata of the symbolic link itself, without following it.
+    ///
+    /// The size and the checksum of a symbolic link are those of its target path.
+    ///
+    /// # Errors
+    /// The link cannot be read, or its target is longer than [`MAX_LINK_TARGET_LENGTH`].
+    pub fn from_symlink<T: AsRef<Path>>(link_path: T) -> io::Result<MetaData> {
+        let link_target = fs::read_link(&link_path)?;
+        let target_binary = link_target.to_string_lossy().as_bytes().to_vec();
+        if target_binary.len() > MAX_LINK_TARGET_LENGTH {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "The target of the symbolic link is longer than {} bytes: {}",
+                    MAX_LINK_TARGET_LENGTH,
+                    link_path.as_ref().display()
+                ),
+            ));
+        }
+        Ok(MetaData {
+            path: link_path.as_ref().to_path_buf(),
+            size: target_binary.len() as u64,
+            is_file: false,
+            is_dir: false,
+            is_symlink: true,
+            is_hardlink: false,
+            checksum: Some(get_bytes_checksum(&target_binary)),
+            link_target: Some(link_target),
+            extended: None,
+            compression: None,
+        })
+    }
+
+    /// Make the metadata of the hard link to the file which was serialized before.
+    ///
+    /// The size and the checksum of a hard link are those of the linked file.
+    pub fn from_hardlink<T: AsRef<Path>>(
+        link_path: T,
+        target: PathBuf,
+        size: u64,
+        checksum: Option<Vec<u8>>,
+    ) -> MetaData {
+        MetaData {
+            path: link_path.as_ref().to_path_buf(),
+            size,
+            is_hardlink: true,
+            checksum,
+            link_target: Some(target),
+            ..MetaData::new()
         }
     }
 
@@ -37,20 +131,67 @@ impl MetaData {
         self.size
     }
 
+    pub fn is_file(&self) -> bool {
+        self.is_file
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.is_dir
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.is_symlink
+    }
+
+    pub fn is_hardlink(&self) -> bool {
+        self.is_hardlink
+    }
+
     pub fn checksum(&self) -> &Option<Vec<u8>> {
         &self.checksum
     }
 
+    pub fn link_target(&self) -> Option<&PathBuf> {
+        self.link_target.as_ref()
+    }
+
+    /// Returns true if the data of the entry follows the metadata.
+    pub fn has_data(&self) -> bool {
+        !(self.is_symlink || self.is_dir || self.is_hardlink)
+    }
+
+    pub fn extended(&self) -> Option<&ExtendedMetaData> {
+        self.extended.as_ref()
+    }
+
+    /// Returns how the data of the entry is written, if it is recorded in the metadata.
+    ///
+    /// It is recorded for entries with data of compressed files since 2.16.0.
+    pub fn compression(&self) -> Option<Compression> {
+        self.compression
+    }
+
+    /// Record how the data of the entry is written, to serialize it after the checksum.
+    pub fn set_compression(&mut self, compression: Compression) {
+        self.compression = Some(compression);
+    }
+
+    /// Read the extended metadata of the original file, to serialize them after the checksum.
+    pub fn read_extended<T: AsRef<Path>>(&mut self, file_path: T) -> io::Result<()> {
+        self.extended = Some(match self.is_symlink {
+            true => ExtendedMetaData::read_symlink(file_path)?,
+            false => ExtendedMetaData::read(file_path)?,
+        });
+        Ok(())
+    }
+
     pub fn strip_prefix<T: AsRef<Path>>(&mut self, root: T) {
         self.path = self.path.strip_prefix(root).unwrap().to_path_buf()
     }
 
     fn serialize_path(&self) -> Vec<u8> {
         let mut binary: Vec<u8> = Vec::new();
-        let mut name = self.path.to_str().unwrap().to_string();
-        while name.len() > u16::MAX.into() {
-            name.pop();
-        }
+        let name = limit_path_length(self.path.to_str().unwrap().to_string());
         let length: u16 = name.len().try_into().unwrap();
         let length = length.to_be_bytes();
         binary.push(length[0]);
@@ -67,15 +208,18 @@ impl MetaData {
         let mut binary: Vec<u8> = Vec::new();
 
         let mut flag_and_size: u8 = 0x0;
-        if let true = self.is_file {
+        if self.is_file {
             flag_and_size += FILE_FLAG;
         }
-        if let true = self.is_dir {
+        if self.is_dir {
             flag_and_size += DIR_FLAG;
         }
-        if let true = self.is_symlink {
+        if self.is_symlink {
             flag_and_size += SYMLINK_FLAG;
         }
+        if self.is_hardlink {
+            flag_and_size += HARDLINK_FLAG;
+        }
 
         let mut index = 0;
         for byte in self.size.to_be_bytes() {
@@ -103,11 +247,7 @@ impl MetaData {
                     binary.push(*i);
                 }
             }
-            None => {
-                for _ in 0..16 {
-                    binary.push(0);
-                }
-            }
+            None => binary.extend_from_slice(&[0u8; 16]),
         }
         binary
     }
@@ -117,9 +257,97 @@ impl MetaData {
         binary.append(&mut self.serialize_path());
         binary.append(&mut self.serialize_type_size());
         binary.append(&mut self.serialize_checksum());
+        match self.compression {
+            Some(Compression::Stored) => binary.push(STORED_ENTRY),
+            Some(Compression::Compressed) => binary.push(COMPRESSED_ENTRY),
+            Some(Compression::Solid { offset, is_last }) => {
+                binary.push(match is_last {
+                    true => SOLID_BLOCK_END_ENTRY,
+                    false => SOLID_ENTRY,
+                });
+                binary.extend_from_slice(&offset.to_le_bytes());
+            }
+            None => (),
+        }
+        if let Some(link_target) = &self.link_target {
+            let mut target = link_target.to_string_lossy().to_string();
+            // The length of a symbolic link target is the size of the entry.
+            if self.is_hardlink {
+                // The target is the path of another entry, so it is cut as the path of the entry is.
+                target = limit_path_length(target);
+                binary.extend_from_slice(&(target.len() as u16).to_be_bytes());
+            }
+            binary.extend_from_slice(target.as_bytes());
+        }
+        if let Some(extended) = &self.extended {
+            binary.append(&mut extended.serialize());
+        }
+        binary
+    }
+
+    /// Encrypt the serialized metadata with the cipher, with its length in 4 bytes (little endian) in front.
+    pub fn serialize_sealed(&self, key: &[u8], cipher: Cipher) -> Vec<u8> {
+        let mut sealed = cipher.seal(key, &self.serialize());
+        let mut binary = (sealed.len() as u32).to_le_bytes().to_vec();
+        binary.append(&mut sealed);
         binary
     }
 
+    /// Read the metadata from the reader, which is serialized by [`MetaData::serialize`].
+    ///
+    /// The header tells which parts are recorded in the metadata.
+    pub fn read_from<R: Read>(reader: &mut R, header: &Header) -> io::Result<MetaData> {
+        let mut metadata = MetaData::new();
+
+        // Restore file path
+        let path_size = read_bytes(reader, 2)?;
+        let path_size = path_size[0] as usize * 0x100 + path_size[1] as usize;
+        metadata.deserialize_path(&read_bytes(reader, path_size)?);
+
+        // Restore file type
+        let flag_and_byte_count = read_bytes(reader, 1)?[0];
+        metadata.deserialize_type(flag_and_byte_count);
+
+        // Restore file size
+        let size_count = (flag_and_byte_count & 0xF) as usize;
+        metadata.deserialize_size(&read_bytes(reader, size_count)?);
+
+        // Restore checksum
+        metadata.deserialize_checksum(&read_bytes(reader, 16)?);
+
+        // Restore how the data is written
+        if header.has_entry_compression() && metadata.has_data() {
+            metadata.deserialize_compression(reader)?;
+        }
+
+        // Restore link target
+        if metadata.is_symlink {
+            // The size is not authenticated, so it must be checked before allocating the target.
+            if metadata.size > MAX_LINK_TARGET_LENGTH as u64 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "The target of a symbolic link is longer than {} bytes.",
+                        MAX_LINK_TARGET_LENGTH
+                    ),
+                ));
+            }
+            metadata.deserialize_link_target(&read_bytes(reader, metadata.size as usize)?);
+        } else if metadata.is_hardlink {
+            let target_size = read_bytes(reader, 2)?;
+            let target_size = target_size[0] as usize * 0x100 + target_size[1] as usize;
+            metadata.deserialize_link_target(&read_bytes(reader, target_size)?);
+        }
+
+        // Restore extended metadata
+        if header.has_extended_metadata() {
+            let extended_size = binary_to_u64(&read_bytes(reader, 2)?) as usize;
+            metadata.deserialize_extended(&read_bytes(reader, extended_size)?)?;
+        }
+
+        Ok(metadata)
+    }
+
     pub fn deserialize_path(&mut self, name_binary: &[u8]) {
         self.path = match String::from_utf8(name_binary.to_vec()) {
             Ok(n) => PathBuf::from(n),
@@ -130,6 +358,7 @@ impl MetaData {
         self.is_file = is_flag_true(type_flag, FILE_FLAG);
         self.is_dir = is_flag_true(type_flag, DIR_FLAG);
         self.is_symlink = is_flag_true(type_flag, SYMLINK_FLAG);
+        self.is_hardlink = is_flag_true(type_flag, HARDLINK_FLAG);
     }
 
     pub fn deserialize_size(&mut self, size_binary: &[u8]) {
@@ -139,35 +368,81 @@ impl MetaData {
     pub fn deserialize_checksum(&mut self, checksum_binary: &[u8]) {
         self.checksum = Some(checksum_binary.to_vec());
     }
+
+    pub fn deserialize_compression<R: Read>(&mut self, reader: &mut R) -> io::Result<()> {
+        let compression = read_bytes(reader, 1)?[0];
+        self.compression = Some(match compression {
+            STORED_ENTRY => Compression::Stored,
+            COMPRESSED_ENTRY => Compression::Compressed,
+            SOLID_ENTRY | SOLID_BLOCK_END_ENTRY => Compression::Solid {
+                offset: binary_to_u64(&read_bytes(reader, 8)?),
+                is_last: compression == SOLID_BLOCK_END_ENTRY,
+            },
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Invalid compression flag of the entry.",
+                ))
+            }
+        });
+        Ok(())
+    }
+
+    pub fn deserialize_link_target(&mut self, target_binary: &[u8]) {
+        self.link_target = Some(PathBuf::from(
+            String::from_utf8_lossy(target_binary).to_string(),
+        ));
+    }
+
+    pub fn deserialize_extended(&mut self, extended_binary: &[u8]) -> io::Result<()> {
+        self.extended = Some(ExtendedMetaData::from_binary(extended_binary)?);
+        Ok(())
+    }
+}
+
+/// Cut the path to the longest length which its 2 bytes length can tell.
+fn limit_path_length(mut path: String) -> String {
+    while path.len() > u16::MAX.into() {
+        path.pop();
+    }
+    path
+}
+
+fn read_bytes<R: Read>(reader: &mut R, length: usize) -> io::Result<Vec<u8>> {
+    let mut binary = vec![0; length];
+    reader.read_exact(&mut binary)?;
+    Ok(binary)
 }
 
 impl<T: AsRef<Path>> From<&T> for MetaData {
     fn from(file_path: &T) -> Self {
-        match File::open(&file_path) {
-            Ok(file) => {
-                return MetaData {
-                    path: file_path.as_ref().to_path_buf(),
-                    size: match file.metadata() {
-                        Ok(m) => m.len(),
-                        Err(_) => 0,
-                    },
-                    is_file: match file.metadata() {
-                        Ok(m) => m.is_file(),
-                        Err(_) => false,
-                    },
-                    is_dir: match file.metadata() {
-                        Ok(m) => m.is_dir(),
-                        Err(_) => false,
-                    },
-                    is_symlink: match file.metadata() {
-                        Ok(m) => m.is_symlink(),
-                        Err(_) => false,
-                    },
-                    checksum: { Some(get_checksum(file)) },
-                }
-            }
-            Err(_) => return MetaData::new(),
-        };
+        match File::open(file_path) {
+            Ok(file) => MetaData {
+                path: file_path.as_ref().to_path_buf(),
+                size: match file.metadata() {
+                    Ok(m) => m.len(),
+                    Err(_) => 0,
+                },
+                is_file: match file.metadata() {
+                    Ok(m) => m.is_file(),
+                    Err(_) => false,
+                },
+                is_dir: match file.metadata() {
+                    Ok(m) => m.is_dir(),
+                    Err(_) => false,
+                },
+                is_symlink: match file.metadata() {
+                    Ok(m) => m.is_symlink(),
+                    Err(_) => false,
+                },
+                is_hardlink: false,
+                checksum: { Some(get_checksum(file)) },
+                link_target: None,
+                extended: None,
+                compression: None,
+            },
+            Err(_) => MetaData::new(),
+        }
     }
 }
 
@@ -178,17 +453,21 @@ impl PartialEq for MetaData {
             && self.is_file == other.is_file
             && self.is_dir == other.is_dir
             && self.is_symlink == other.is_symlink
+            && self.is_hardlink == other.is_hardlink
             && self.checksum == other.checksum
+            && self.link_target == other.link_target
+            && self.extended == other.extended
+            && self.compression == other.compression
     }
 }
 
 #[cfg(test)]
 mod tests {
 
-    use std::{collections::VecDeque, path::PathBuf};
     use hex::decode;
+    use std::{collections::VecDeque, path::PathBuf};
 
-    use crate::serialize::get_file_list;
+    use crate::serialize::{get_file_list, header::Header, option::SerializeOption};
 
     use super::MetaData;
 
@@ -198,7 +477,7 @@ mod tests {
     fn metadata_compare_test() {
         let original = PathBuf::from("tests");
 
-        let original_file_vec = get_file_list(&original).unwrap();
+        let original_file_vec = get_file_list(&original, &SerializeOption::default()).unwrap();
         let mut original_metadata_vec = Vec::new();
         for f in original_file_vec {
             let meta = MetaData::from(&f);
@@ -213,7 +492,11 @@ mod tests {
                 is_file: m.is_file,
                 is_dir: m.is_dir,
                 is_symlink: m.is_symlink,
+                is_hardlink: m.is_hardlink,
                 checksum: Some(m.checksum.clone().unwrap()),
+                link_target: None,
+                extended: None,
+                compression: None,
             })
             .collect();
         let mut result_metadata_vec = Vec::from([
@@ -223,7 +506,11 @@ mod tests {
                 is_file: true,
                 is_dir: false,
                 is_symlink: false,
+                is_hardlink: false,
                 checksum: Some(decode("4e42993bfd2756df48b646d68433db1e").unwrap()),
+                link_target: None,
+                extended: None,
+                compression: None,
             },
             MetaData {
                 path: PathBuf::from("capsules-g869437822_1920.jpg"),
@@ -231,7 +518,11 @@ mod tests {
                 is_file: true,
                 is_dir: false,
                 is_symlink: false,
+                is_hardlink: false,
                 checksum: Some(decode("60e191a914756ff7ae259e33f40f20da").unwrap()),
+                link_target: None,
+                extended: None,
+                compression: None,
             },
             MetaData {
                 path: PathBuf::from("board-g43968feec_1920.jpg"),
@@ -239,7 +530,11 @@ mod tests {
                 is_file: true,
                 is_dir: false,
                 is_symlink: false,
+                is_hardlink: false,
                 checksum: Some(decode("37ca14866812327e1776d8cbb250501c").unwrap()),
+                link_target: None,
+                extended: None,
+                compression: None,
             },
             MetaData {
                 path: PathBuf::from("laboratory-g8f9267f5f_1920.jpg"),
@@ -247,7 +542,11 @@ mod tests {
                 is_file: true,
                 is_dir: false,
                 is_symlink: false,
+                is_hardlink: false,
                 checksum: Some(decode("0c37be929cdc29b5ac0914104cda75aa").unwrap()),
+                link_target: None,
+                extended: None,
+                compression: None,
             },
             MetaData {
                 path: PathBuf::from("폭발.jpg"),
@@ -255,7 +554,11 @@ mod tests {
                 is_file: true,
                 is_dir: false,
                 is_symlink: false,
+                is_hardlink: false,
                 checksum: Some(decode("4753aff9b06a34832ad1de0a69d5dcd3").unwrap()),
+                link_target: None,
+                extended: None,
+                compression: None,
             },
             MetaData {
                 path: PathBuf::from("digitization-1755812_1920.jpg"),
@@ -263,7 +566,11 @@ mod tests {
                 is_file: true,
                 is_dir: false,
                 is_symlink: false,
+                is_hardlink: false,
                 checksum: Some(decode("4b6cab47e9193a4aebe4c8c6b7c88c1b").unwrap()),
+                link_target: None,
+                extended: None,
+                compression: None,
             },
             MetaData {
                 path: PathBuf::from("syringe-ge5e95bfe6_1920.jpg"),
@@ -271,7 +578,11 @@ mod tests {
                 is_file: true,
                 is_dir: false,
                 is_symlink: false,
+                is_hardlink: false,
                 checksum: Some(decode("a7385d8a719c3036a857e21225c5bd6b").unwrap()),
+                link_target: None,
+                extended: None,
+                compression: None,
             },
             MetaData {
                 path: PathBuf::from("books-g6617d4d97_1920.jpg"),
@@ -279,7 +590,11 @@ mod tests {
                 is_file: true,
                 is_dir: false,
                 is_symlink: false,
+                is_hardlink: false,
                 checksum: Some(decode("65aee1442129f56a0a6157c6b55f80c9").unwrap()),
+                link_target: None,
+                extended: None,
+                compression: None,
             },
             MetaData {
                 path: PathBuf::from("test-pattern-152459.png"),
@@ -287,7 +602,11 @@ mod tests {
                 is_file: true,
                 is_dir: false,
                 is_symlink: false,
+                is_hardlink: false,
                 checksum: Some(decode("a09d4eab0326ba5403369035531f9308").unwrap()),
+                link_target: None,
+                extended: None,
+                compression: None,
             },
             MetaData {
                 path: PathBuf::from("tv-g87676cdfb_1280.png"),
@@ -295,7 +614,11 @@ mod tests {
                 is_file: true,
                 is_dir: false,
                 is_symlink: false,
+                is_hardlink: false,
                 checksum: Some(decode("91517821bc6851b0d9abec5d5adea961").unwrap()),
+                link_target: None,
+                extended: None,
+                compression: None,
             },
         ]);
         original_metadata_vec.sort_by_key(|m| m.path.clone());
@@ -350,7 +673,7 @@ mod tests {
         let type_size_index = (type_size & 0xF) as usize;
 
         let expected_checksum: [u8; 16] = [
-            55, 202, 20, 134, 104, 18, 50, 126, 23, 118, 216, 203, 178, 80, 80, 28
+            55, 202, 20, 134, 104, 18, 50, 126, 23, 118, 216, 203, 178, 80, 80, 28,
         ];
 
         assert_eq!(
@@ -387,4 +710,42 @@ mod tests {
 
         assert_eq!(meta1, meta2);
     }
+
+    #[test]
+    fn huge_symlink_target_test() {
+        let symlink = MetaData {
+            path: PathBuf::from("link"),
+            size: u64::MAX,
+            is_symlink: true,
+            checksum: Some(vec![0; 16]),
+            link_target: Some(PathBuf::from("target")),
+            ..MetaData::new()
+        };
+        let error =
+            MetaData::read_from(&mut symlink.serialize().as_slice(), &Header::new()).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn long_hardlink_target_test() {
+        let target = PathBuf::from("a".repeat(u16::MAX as usize + 10));
+        let file = MetaData {
+            path: target.clone(),
+            ..MetaData::new()
+        };
+        let hardlink = MetaData::from_hardlink("link", target, 0, Some(vec![0; 16]));
+        let binary = hardlink.serialize();
+        let mut reader = binary.as_slice();
+        let restored = MetaData::read_from(&mut reader, &Header::new()).unwrap();
+        // Everything written is read, so the entries after it are read right.
+        assert!(reader.is_empty());
+        // The target is cut as the path of the file it is linked to.
+        let mut binary = file.serialize();
+        binary.drain(..2);
+        binary.truncate(u16::MAX as usize);
+        assert_eq!(
+            restored.link_target().unwrap(),
+            &PathBuf::from(String::from_utf8(binary).unwrap())
+        );
+    }
 }
@@ -1,9 +1,25 @@
 use std::{
-    io,
+    collections::HashSet,
+    fs, io,
     path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use crate::encrypt::{
+    keyslot::{read_keyfile, KeySlot},
+    Cipher,
+};
+
+use self::{
+    filter::{IgnoreRules, PathFilter},
+    option::{DirectoryEntries, HiddenFiles, SerializeOption},
 };
 pub mod deserializer;
+pub mod entry;
+pub mod extended;
+pub mod filter;
 pub mod header;
+pub mod index;
 pub mod meta;
 pub mod option;
 pub mod serializer;
@@ -12,36 +28,165 @@ pub mod version;
 pub const BUFFER_LENGTH: usize = 8192;
 
 /// Find all files in the root directory in a recursive way.
-/// The hidden files started with `.` will be not included in result.
-fn get_file_list<O: AsRef<Path>>(root: O) -> io::Result<Vec<PathBuf>> {
-    let mut image_list: Vec<PathBuf> = Vec::new();
-    let mut file_list: Vec<PathBuf> = root
+/// Empty directories, or all directories if the option is set so, are listed in front of their contents.
+/// A directory is empty if nothing under it is listed, so a directory whose children are all filtered out is listed as well.
+/// The hidden files and directories started with `.` are handled by the hidden files mode of the option.
+/// The files and directories are filtered by the include and exclude patterns of the option,
+/// and excluded directories are not traversed.
+/// If the option is set to respect ignore files, ignored files and directories are also filtered out.
+/// Symbolic links are listed as they are without being followed, unless the option is set to follow them.
+/// A followed link to a directory which is already visited, like `loop -> .`, is skipped to avoid endless recursion.
+fn get_file_list<O: AsRef<Path>>(root: O, option: &SerializeOption) -> io::Result<Vec<PathBuf>> {
+    let filter = PathFilter::new(&root, option)?;
+    let root_rules =
+        Rc::new(IgnoreRules::new(option.is_respecting_ignore_files()).with_dir(&root)?);
+    let mut visited_dirs: HashSet<(u64, u64)> = get_dir_id(&root)?.into_iter().collect();
+    // Every file and directory which passed the filters, with whether it is a directory.
+    let mut found_list: Vec<(PathBuf, bool)> = Vec::new();
+    let mut file_list: Vec<(PathBuf, Rc<IgnoreRules>)> = root
         .as_ref()
         .read_dir()?
-        .map(|entry| entry.unwrap().path())
+        .map(|entry| (entry.unwrap().path(), root_rules.clone()))
         .collect();
     let mut i = 0;
     loop {
         if i >= file_list.len() {
             break;
         }
-        if file_list[i].is_dir() {
-            for component in file_list[i].read_dir()? {
-                file_list.push(component.unwrap().path());
-            }
-        } else if file_list[i]
-            .file_name()
-            .unwrap()
-            .to_str()
-            .unwrap()
-            .chars()
-            .collect::<Vec<_>>()[0]
-            != '.'
+        let (path, rules) = file_list[i].clone();
+        let is_symlink = is_preserved_symlink(&path, option);
+        let is_dir = !is_symlink && path.is_dir();
+        let is_skipped_hidden = is_hidden(&path)
+            && match option.hidden_files_mode() {
+                HiddenFiles::Include => false,
+                HiddenFiles::SkipFiles => !is_dir,
+                HiddenFiles::SkipAll => true,
+            };
+        if is_skipped_hidden || filter.is_excluded(&path, is_dir) || rules.is_ignored(&path, is_dir)
         {
-            image_list.push(file_list[i].to_path_buf());
+            i += 1;
+            continue;
+        }
+        if is_dir {
+            if let Some(id) = get_dir_id(&path)? {
+                if !visited_dirs.insert(id) {
+                    i += 1;
+                    continue;
+                }
+            }
+            let child_rules = Rc::new(rules.with_dir(&path)?);
+            for component in path.read_dir()? {
+                file_list.push((component.unwrap().path(), child_rules.clone()));
+            }
+            found_list.push((path, true));
+        } else if filter.is_included(&path, false) {
+            found_list.push((path, false));
         }
         i += 1;
     }
 
+    // The contents of a directory are found after it, so whether it is empty is known in reverse order.
+    let mut non_empty_dirs: HashSet<PathBuf> = HashSet::new();
+    let mut image_list: Vec<PathBuf> = Vec::new();
+    for (path, is_dir) in found_list.into_iter().rev() {
+        let is_listed = match is_dir {
+            true => {
+                (!non_empty_dirs.contains(&path)
+                    || option.directory_entries_mode() == DirectoryEntries::All)
+                    && filter.is_included(&path, true)
+            }
+            false => true,
+        };
+        if is_listed || non_empty_dirs.contains(&path) {
+            if let Some(parent) = path.parent() {
+                non_empty_dirs.insert(parent.to_path_buf());
+            }
+        }
+        if is_listed {
+            image_list.push(path);
+        }
+    }
+    image_list.reverse();
+
     Ok(image_list)
 }
+
+/// Returns true if the name of the file or directory starts with `.`.
+fn is_hidden<T: AsRef<Path>>(path: T) -> bool {
+    match path.as_ref().file_name() {
+        Some(name) => name.to_string_lossy().starts_with('.'),
+        None => false,
+    }
+}
+
+/// Returns the device and inode numbers of the directory, following symbolic links.
+#[cfg_attr(not(unix), allow(unused_variables))]
+fn get_dir_id<T: AsRef<Path>>(path: T) -> io::Result<Option<(u64, u64)>> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        let metadata = fs::metadata(path)?;
+        Ok(Some((metadata.dev(), metadata.ino())))
+    }
+    #[cfg(not(unix))]
+    {
+        Ok(None)
+    }
+}
+
+/// Returns true if the path is a symbolic link which is serialized as a link, not followed.
+/// A broken link is always serialized as a link, because there is nothing to follow.
+fn is_preserved_symlink<T: AsRef<Path>>(path: T, option: &SerializeOption) -> bool {
+    let path = path.as_ref();
+    path.is_symlink() && (!option.is_following_symlinks() || !path.exists())
+}
+
+/// Make the key slots which wrap the data key with the cipher for every credential and recipient of the option,
+/// with the number of them in front.
+fn make_key_slots(option: &SerializeOption, key: &[u8], cipher: Cipher) -> io::Result<Vec<u8>> {
+    let mut slots = Vec::new();
+    for password in option.passwords() {
+        slots.push(KeySlot::for_password(
+            password,
+            &option.argon2_params(),
+            key,
+            cipher,
+        )?);
+    }
+    for raw_key in option.keys() {
+        slots.push(KeySlot::for_key(raw_key, key, cipher));
+    }
+    for keyfile in option.keyfiles() {
+        slots.push(KeySlot::for_keyfile(&read_keyfile(keyfile)?, key, cipher));
+    }
+    for (password, keyfile) in option.password_keyfiles() {
+        slots.push(KeySlot::for_password_and_keyfile(
+            password,
+            &read_keyfile(keyfile)?,
+            &option.argon2_params(),
+            key,
+            cipher,
+        )?);
+    }
+    for recipient in option.recipients() {
+        slots.push(KeySlot::for_recipient(recipient, key, cipher));
+    }
+    if slots.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "There is no credential or recipient to encrypt with!",
+        ));
+    }
+    if slots.len() > u8::MAX as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Too many key slots! At most {} are allowed.", u8::MAX),
+        ));
+    }
+    let mut binary = vec![slots.len() as u8];
+    for slot in slots {
+        binary.append(&mut slot.to_binary_vec());
+    }
+    Ok(binary)
+}
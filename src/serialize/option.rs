@@ -1,3 +1,82 @@
+use std::path::{Path, PathBuf};
+
+use zeroize::Zeroizing;
+
+use crate::{
+    compress::Codec,
+    encrypt::{
+        keyslot::{CredentialKind, Identity, Recipient, KEY_LENGTH},
+        Argon2Params, Cipher,
+    },
+    sign::{SigningKey, VerifyingKey},
+};
+
+/// How to handle hidden files and directories, whose names start with `.`.
+///
+/// The mode used to serialize is recorded in the serialized file.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HiddenFiles {
+    /// Serialize hidden files and directories.
+    Include,
+    /// Skip hidden files, but traverse hidden directories.
+    #[default]
+    SkipFiles,
+    /// Skip hidden files and directories.
+    SkipAll,
+}
+
+/// Which directories to serialize as directory entries, which have no data.
+///
+/// Directories containing serialized files are restored anyway, so only empty directories are needed to be stored by default.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DirectoryEntries {
+    /// Serialize only the directories which have nothing in them.
+    #[default]
+    Empty,
+    /// Serialize every directory, to restore the metadata of all of them.
+    All,
+}
+
+/// Whether to restore the owner of files when deserializing with preserved metadata.
+///
+/// Changing the owner usually requires root privileges.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Ownership {
+    /// Always restore the owner, and occur error if it is not permitted.
+    Restore,
+    /// Restore the owner only if the process is running as root.
+    #[default]
+    RestoreIfRoot,
+    /// Never restore the owner.
+    Skip,
+}
+
+/// Which entries to compress when the file is compressed.
+///
+/// The decision is recorded in the metadata of every entry, so the deserializer follows it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EntryCompression {
+    /// Compress every entry.
+    #[default]
+    All,
+    /// Store the entries which hardly get smaller without compressing them,
+    /// like JPEG or PNG images and other already compressed formats.
+    ///
+    /// An entry is stored if its extension is of an already compressed format,
+    /// or compressing its first block saves less than 5 percent of it.
+    SkipIncompressible,
+}
+
+/// Where to write the signature of a signed file.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SignaturePlacement {
+    /// Append the signature to the end of the serialized file.
+    #[default]
+    Embedded,
+    /// Write the signature to a detached file, whose path is the path of the serialized file with `.sig` appended.
+    Detached,
+}
+
 /// Option struct for serializing and deserializing
 ///
 /// Pass this object to the serializer or deserializer.
@@ -14,23 +93,35 @@
 /// .to_compress(true);
 /// assert_eq!(option.is_encrypted(), true);
 /// assert_eq!(option.is_compressed(), true);
-/// assert_eq!(option.password(), Some(String::from("test_password")));
+/// assert_eq!(option.password(), Some("test_password"));
 /// ```
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct SerializeOption {
     encrypt: bool,
     compress: bool,
-    password: Option<String>,
-}
-
-impl Default for SerializeOption {
-    fn default() -> Self {
-        Self {
-            encrypt: false,
-            compress: false,
-            password: None,
-        }
-    }
+    codec: Codec,
+    entry_compression: EntryCompression,
+    solid_block_size: Option<u64>,
+    passwords: Vec<Zeroizing<String>>,
+    include: Vec<String>,
+    exclude: Vec<String>,
+    respect_ignore_files: bool,
+    hidden_files: HiddenFiles,
+    preserve_metadata: bool,
+    ownership: Ownership,
+    follow_symlinks: bool,
+    directory_entries: DirectoryEntries,
+    encrypt_metadata: bool,
+    recipients: Vec<Recipient>,
+    identities: Vec<Identity>,
+    argon2: Argon2Params,
+    cipher: Cipher,
+    signing_key: Option<SigningKey>,
+    signature_placement: SignaturePlacement,
+    verifying_key: Option<VerifyingKey>,
+    keys: Vec<Zeroizing<[u8; KEY_LENGTH]>>,
+    keyfiles: Vec<PathBuf>,
+    password_keyfiles: Vec<(Zeroizing<String>, PathBuf)>,
 }
 
 impl SerializeOption {
@@ -40,22 +131,293 @@ impl SerializeOption {
     }
 
     /// Set the encryption option. Use with builder pattern.
+    ///
+    /// Calling it again adds another password, and the file can be decrypted by any of them.
+    /// When deserializing, every password is tried.
     pub fn to_encrypt(mut self, password: &str) -> Self {
         self.encrypt = true;
-        self.password = Some(String::from(password));
+        self.passwords.push(Zeroizing::new(String::from(password)));
+        self
+    }
+
+    /// Add a raw key of 32 bytes to encrypt or decrypt with. Use with builder pattern.
+    ///
+    /// The key is used as it is, without being derived by Argon2, so it must be random.
+    pub fn to_encrypt_with_key(mut self, key: &[u8; KEY_LENGTH]) -> Self {
+        self.encrypt = true;
+        self.keys.push(Zeroizing::new(*key));
+        self
+    }
+
+    /// Add a keyfile to encrypt or decrypt with. Use with builder pattern.
+    ///
+    /// The keyfile is read when serializing or deserializing, and its contents are the credential.
+    pub fn to_encrypt_with_keyfile<P: AsRef<Path>>(mut self, keyfile: P) -> Self {
+        self.encrypt = true;
+        self.keyfiles.push(keyfile.as_ref().to_path_buf());
+        self
+    }
+
+    /// Add a password combined with a keyfile to encrypt or decrypt with. Use with builder pattern.
+    ///
+    /// Both of them are needed to decrypt the file.
+    pub fn to_encrypt_with_password_and_keyfile<P: AsRef<Path>>(
+        mut self,
+        password: &str,
+        keyfile: P,
+    ) -> Self {
+        self.encrypt = true;
+        self.password_keyfiles.push((
+            Zeroizing::new(String::from(password)),
+            keyfile.as_ref().to_path_buf(),
+        ));
+        self
+    }
+
+    /// Add a recipient to encrypt for. Use with builder pattern.
+    ///
+    /// The file can be decrypted by the [`Identity`] of any recipient, as well as by the passwords if they are set.
+    pub fn to_recipient(mut self, recipient: &Recipient) -> Self {
+        self.encrypt = true;
+        self.recipients.push(recipient.clone());
+        self
+    }
+
+    /// Add an identity to decrypt files encrypted for its recipient. Use with builder pattern.
+    pub fn to_identity(mut self, identity: &Identity) -> Self {
+        self.encrypt = true;
+        self.identities.push(identity.clone());
+        self
+    }
+
+    /// Set the Argon2 parameters to derive keys from the passwords. Use with builder pattern.
+    ///
+    /// The parameters are recorded in the serialized file, so they are not needed to deserialize it.
+    /// The default is [`Argon2Params::default`].
+    pub fn to_argon2(mut self, params: Argon2Params) -> Self {
+        self.argon2 = params;
+        self
+    }
+
+    /// Set the cipher to encrypt with. Use with builder pattern.
+    ///
+    /// The cipher is recorded in the serialized file, so it is not needed to deserialize it.
+    /// The default is [`Cipher::XChaCha20Poly1305`].
+    pub fn to_cipher(mut self, cipher: Cipher) -> Self {
+        self.cipher = cipher;
+        self
+    }
+
+    /// Set the key to sign the serialized file. Use with builder pattern.
+    ///
+    /// The signature is embedded at the end of the file by default, see [`SerializeOption::to_signature_placement`].
+    pub fn to_sign(mut self, signing_key: &SigningKey) -> Self {
+        self.signing_key = Some(signing_key.clone());
+        self
+    }
+
+    /// Set where to write the signature. Use with builder pattern.
+    ///
+    /// The default is [`SignaturePlacement::Embedded`].
+    pub fn to_signature_placement(mut self, signature_placement: SignaturePlacement) -> Self {
+        self.signature_placement = signature_placement;
+        self
+    }
+
+    /// Set the key to verify the signature of the serialized file. Use with builder pattern.
+    ///
+    /// If it is set, the deserializer refuses unsigned or badly signed files before restoring anything.
+    pub fn to_verify(mut self, verifying_key: &VerifyingKey) -> Self {
+        self.verifying_key = Some(verifying_key.clone());
         self
     }
 
     /// Set the compression option. Use with builder pattern.
+    ///
+    /// The data is compressed with the codec of [`SerializeOption::to_compression`], which is zlib with level 9 by default.
+    /// Which entries are compressed is set by [`SerializeOption::to_entry_compression`].
     pub fn to_compress(mut self, compress: bool) -> Self {
         self.compress = compress;
         self
     }
 
-    /// Set the encryption option.
+    /// Set the compression option with the codec and its level. Use with builder pattern.
+    ///
+    /// The codec is recorded in the serialized file, so only [`SerializeOption::to_compress`] is needed to deserialize it.
+    pub fn to_compression(mut self, codec: Codec) -> Self {
+        self.compress = true;
+        self.codec = codec;
+        self
+    }
+
+    /// Set which entries to compress when the file is compressed. Use with builder pattern.
+    ///
+    /// The default is [`EntryCompression::All`].
+    pub fn to_entry_compression(mut self, entry_compression: EntryCompression) -> Self {
+        self.entry_compression = entry_compression;
+        self
+    }
+
+    /// Set the compression option to compress entries together in solid blocks. Use with builder pattern.
+    ///
+    /// The data of consecutive entries to compress are compressed as one stream, until it reaches the block size.
+    /// It compresses many small files much better, and extracting one entry decompresses only its block.
+    /// With `u64::MAX`, all entries are compressed in one block.
+    pub fn to_solid(mut self, block_size: u64) -> Self {
+        self.compress = true;
+        self.solid_block_size = Some(block_size);
+        self
+    }
+
+    /// Add a glob or regular expression pattern of files to serialize. Use with builder pattern.
+    ///
+    /// If there is any include pattern, only the files matching one of them are serialized.
+    /// Patterns are matched against the path relative to the parent of the serialized root, like `root/src/*.rs`.
+    /// A pattern without `/`, like `*.rs`, is matched against the file name in any directory.
+    /// A pattern ending with `/`, like `target/`, is matched against directories only.
+    /// A pattern starting with `re:`, like `re:^root/src/.*\.rs$`, is a regular expression searched in the relative path.
+    pub fn to_include(mut self, pattern: &str) -> Self {
+        self.include.push(String::from(pattern));
+        self
+    }
+
+    /// Add a glob or regular expression pattern of files and directories not to serialize. Use with builder pattern.
+    ///
+    /// Excluded directories are not traversed at all.
+    /// Patterns are matched in the same way as [`SerializeOption::to_include`], like `target/`, `*.log` or `node_modules`.
+    pub fn to_exclude(mut self, pattern: &str) -> Self {
+        self.exclude.push(String::from(pattern));
+        self
+    }
+
+    /// Set whether to respect ignore files. Use with builder pattern.
+    ///
+    /// If it is true, `.gitignore`, `.ignore` and `.luslignore` files in every directory under the serialized root
+    /// are read with the gitignore semantics, and ignored files and directories are not serialized.
+    /// The `.git` directory is also not serialized.
+    pub fn to_respect_ignore_files(mut self, respect: bool) -> Self {
+        self.respect_ignore_files = respect;
+        self
+    }
+
+    /// Set how to handle hidden files and directories. Use with builder pattern.
+    ///
+    /// The default is [`HiddenFiles::SkipFiles`].
+    pub fn to_hidden_files(mut self, hidden_files: HiddenFiles) -> Self {
+        self.hidden_files = hidden_files;
+        self
+    }
+
+    /// Set whether to preserve permissions, ownership and timestamps of entries. Use with builder pattern.
+    ///
+    /// If it is true, the extended metadata of every entry are serialized
+    /// and restored by the deserializer.
+    pub fn to_preserve_metadata(mut self, preserve: bool) -> Self {
+        self.preserve_metadata = preserve;
+        self
+    }
+
+    /// Set whether to restore the owner of files when deserializing. Use with builder pattern.
+    ///
+    /// The default is [`Ownership::RestoreIfRoot`].
+    pub fn to_restore_ownership(mut self, ownership: Ownership) -> Self {
+        self.ownership = ownership;
+        self
+    }
+
+    /// Set whether to follow symbolic links. Use with builder pattern.
+    ///
+    /// If it is false, which is the default, symbolic links are serialized as links carrying their targets,
+    /// and the deserializer recreates them as symbolic links.
+    /// If it is true, the files and directories the links point at are serialized in place of the links.
+    pub fn to_follow_symlinks(mut self, follow: bool) -> Self {
+        self.follow_symlinks = follow;
+        self
+    }
+
+    /// Set which directories to serialize as directory entries. Use with builder pattern.
+    ///
+    /// The default is [`DirectoryEntries::Empty`].
+    pub fn to_directory_entries(mut self, directory_entries: DirectoryEntries) -> Self {
+        self.directory_entries = directory_entries;
+        self
+    }
+
+    /// Set whether to encrypt the metadata of entries as well as their data. Use with builder pattern.
+    ///
+    /// If it is true, paths, sizes, checksums and the number of entries cannot be read without the password.
+    /// It takes effect only with encryption set by [`SerializeOption::to_encrypt`].
+    pub fn to_encrypt_metadata(mut self, encrypt_metadata: bool) -> Self {
+        self.encrypt_metadata = encrypt_metadata;
+        self
+    }
+
+    /// Set the encryption option, or add another password if it is already set.
     pub fn encrypt(&mut self, password: &str) {
         self.encrypt = true;
-        self.password = Some(String::from(password));
+        self.passwords.push(Zeroizing::new(String::from(password)));
+    }
+
+    /// Add a raw key of 32 bytes to encrypt or decrypt with.
+    pub fn encrypt_with_key(&mut self, key: &[u8; KEY_LENGTH]) {
+        self.encrypt = true;
+        self.keys.push(Zeroizing::new(*key));
+    }
+
+    /// Add a keyfile to encrypt or decrypt with.
+    pub fn encrypt_with_keyfile<P: AsRef<Path>>(&mut self, keyfile: P) {
+        self.encrypt = true;
+        self.keyfiles.push(keyfile.as_ref().to_path_buf());
+    }
+
+    /// Add a password combined with a keyfile to encrypt or decrypt with.
+    pub fn encrypt_with_password_and_keyfile<P: AsRef<Path>>(
+        &mut self,
+        password: &str,
+        keyfile: P,
+    ) {
+        self.encrypt = true;
+        self.password_keyfiles.push((
+            Zeroizing::new(String::from(password)),
+            keyfile.as_ref().to_path_buf(),
+        ));
+    }
+
+    /// Add a recipient to encrypt for.
+    pub fn recipient(&mut self, recipient: &Recipient) {
+        self.encrypt = true;
+        self.recipients.push(recipient.clone());
+    }
+
+    /// Add an identity to decrypt files encrypted for its recipient.
+    pub fn identity(&mut self, identity: &Identity) {
+        self.encrypt = true;
+        self.identities.push(identity.clone());
+    }
+
+    /// Set the Argon2 parameters to derive keys from the passwords.
+    pub fn argon2(&mut self, params: Argon2Params) {
+        self.argon2 = params;
+    }
+
+    /// Set the cipher to encrypt with.
+    pub fn cipher(&mut self, cipher: Cipher) {
+        self.cipher = cipher;
+    }
+
+    /// Set the key to sign the serialized file.
+    pub fn sign(&mut self, signing_key: &SigningKey) {
+        self.signing_key = Some(signing_key.clone());
+    }
+
+    /// Set where to write the signature.
+    pub fn signature_placement(&mut self, signature_placement: SignaturePlacement) {
+        self.signature_placement = signature_placement;
+    }
+
+    /// Set the key to verify the signature of the serialized file.
+    pub fn verify(&mut self, verifying_key: &VerifyingKey) {
+        self.verifying_key = Some(verifying_key.clone());
     }
 
     /// Set the compression option.
@@ -63,6 +425,68 @@ impl SerializeOption {
         self.compress = compress;
     }
 
+    /// Set the compression option with the codec and its level.
+    pub fn compression(&mut self, codec: Codec) {
+        self.compress = true;
+        self.codec = codec;
+    }
+
+    /// Set which entries to compress when the file is compressed.
+    pub fn entry_compression(&mut self, entry_compression: EntryCompression) {
+        self.entry_compression = entry_compression;
+    }
+
+    /// Set the compression option to compress entries together in solid blocks of the size.
+    pub fn solid(&mut self, block_size: u64) {
+        self.compress = true;
+        self.solid_block_size = Some(block_size);
+    }
+
+    /// Add a glob or regular expression pattern of files to serialize.
+    pub fn include(&mut self, pattern: &str) {
+        self.include.push(String::from(pattern));
+    }
+
+    /// Add a glob or regular expression pattern of files and directories not to serialize.
+    pub fn exclude(&mut self, pattern: &str) {
+        self.exclude.push(String::from(pattern));
+    }
+
+    /// Set whether to respect ignore files.
+    pub fn respect_ignore_files(&mut self, respect: bool) {
+        self.respect_ignore_files = respect;
+    }
+
+    /// Set how to handle hidden files and directories.
+    pub fn hidden_files(&mut self, hidden_files: HiddenFiles) {
+        self.hidden_files = hidden_files;
+    }
+
+    /// Set whether to preserve permissions, ownership and timestamps of entries.
+    pub fn preserve_metadata(&mut self, preserve: bool) {
+        self.preserve_metadata = preserve;
+    }
+
+    /// Set whether to restore the owner of files when deserializing.
+    pub fn restore_ownership(&mut self, ownership: Ownership) {
+        self.ownership = ownership;
+    }
+
+    /// Set whether to follow symbolic links.
+    pub fn follow_symlinks(&mut self, follow: bool) {
+        self.follow_symlinks = follow;
+    }
+
+    /// Set which directories to serialize as directory entries.
+    pub fn directory_entries(&mut self, directory_entries: DirectoryEntries) {
+        self.directory_entries = directory_entries;
+    }
+
+    /// Set whether to encrypt the metadata of entries as well as their data.
+    pub fn encrypt_metadata(&mut self, encrypt_metadata: bool) {
+        self.encrypt_metadata = encrypt_metadata;
+    }
+
     /// Returns true if the option is set to encrypt.
     pub fn is_encrypted(&self) -> bool {
         self.encrypt
@@ -73,8 +497,154 @@ impl SerializeOption {
         self.compress
     }
 
-    /// Returns the password if the option is set to encrypt.
-    pub fn password(&self) -> Option<String> {
-        return self.password.clone();
+    /// Returns the codec to compress with.
+    pub fn compression_codec(&self) -> Codec {
+        self.codec
+    }
+
+    /// Returns which entries to compress when the file is compressed.
+    pub fn entry_compression_mode(&self) -> EntryCompression {
+        self.entry_compression
+    }
+
+    /// Returns the size of solid blocks if the option is set to compress in solid blocks.
+    pub fn solid_block_size(&self) -> Option<u64> {
+        self.solid_block_size
+    }
+
+    /// Returns the first password if the option is set to encrypt.
+    ///
+    /// The password is borrowed, so it is not copied out of the option, which zeroizes it when dropped.
+    pub fn password(&self) -> Option<&str> {
+        self.passwords.first().map(|p| p.as_str())
+    }
+
+    /// Returns all passwords to encrypt or decrypt with.
+    pub fn passwords(&self) -> impl Iterator<Item = &str> {
+        self.passwords.iter().map(|p| p.as_str())
+    }
+
+    /// Returns all raw keys to encrypt or decrypt with.
+    pub fn keys(&self) -> impl Iterator<Item = &[u8; KEY_LENGTH]> {
+        self.keys.iter().map(|k| &**k)
+    }
+
+    /// Returns all keyfiles to encrypt or decrypt with.
+    pub fn keyfiles(&self) -> &[PathBuf] {
+        &self.keyfiles
+    }
+
+    /// Returns all passwords combined with keyfiles to encrypt or decrypt with.
+    pub fn password_keyfiles(&self) -> impl Iterator<Item = (&str, &Path)> {
+        self.password_keyfiles
+            .iter()
+            .map(|(p, k)| (p.as_str(), k.as_path()))
+    }
+
+    /// Returns true if the option has a credential or a recipient to encrypt with.
+    pub(crate) fn has_encryption_credentials(&self) -> bool {
+        !(self.passwords.is_empty()
+            && self.keys.is_empty()
+            && self.keyfiles.is_empty()
+            && self.password_keyfiles.is_empty()
+            && self.recipients.is_empty())
+    }
+
+    /// Returns the kinds of credentials to decrypt with.
+    pub(crate) fn credential_kinds(&self) -> Vec<CredentialKind> {
+        [
+            (CredentialKind::Password, self.passwords.is_empty()),
+            (CredentialKind::Key, self.keys.is_empty()),
+            (CredentialKind::Keyfile, self.keyfiles.is_empty()),
+            (
+                CredentialKind::PasswordAndKeyfile,
+                self.password_keyfiles.is_empty(),
+            ),
+            (CredentialKind::Identity, self.identities.is_empty()),
+        ]
+        .into_iter()
+        .filter(|(_, is_empty)| !is_empty)
+        .map(|(kind, _)| kind)
+        .collect()
+    }
+
+    /// Returns the Argon2 parameters to derive keys from the passwords.
+    pub fn argon2_params(&self) -> Argon2Params {
+        self.argon2
+    }
+
+    /// Returns the cipher to encrypt with.
+    pub fn cipher_suite(&self) -> Cipher {
+        self.cipher
+    }
+
+    /// Returns the recipients to encrypt for.
+    pub fn recipients(&self) -> &[Recipient] {
+        &self.recipients
+    }
+
+    /// Returns the identities to decrypt with.
+    pub fn identities(&self) -> &[Identity] {
+        &self.identities
+    }
+
+    /// Returns true if the option is set to respect ignore files.
+    pub fn is_respecting_ignore_files(&self) -> bool {
+        self.respect_ignore_files
+    }
+
+    /// Returns how to handle hidden files and directories.
+    pub fn hidden_files_mode(&self) -> HiddenFiles {
+        self.hidden_files
+    }
+
+    /// Returns the glob patterns of files to serialize.
+    pub fn include_patterns(&self) -> &[String] {
+        &self.include
+    }
+
+    /// Returns the glob patterns of files and directories not to serialize.
+    pub fn exclude_patterns(&self) -> &[String] {
+        &self.exclude
+    }
+
+    /// Returns true if the option is set to preserve permissions, ownership and timestamps.
+    pub fn is_preserving_metadata(&self) -> bool {
+        self.preserve_metadata
+    }
+
+    /// Returns whether to restore the owner of files when deserializing.
+    pub fn ownership_mode(&self) -> Ownership {
+        self.ownership
+    }
+
+    /// Returns true if the option is set to follow symbolic links.
+    pub fn is_following_symlinks(&self) -> bool {
+        self.follow_symlinks
+    }
+
+    /// Returns which directories to serialize as directory entries.
+    pub fn directory_entries_mode(&self) -> DirectoryEntries {
+        self.directory_entries
+    }
+
+    /// Returns true if the option is set to encrypt the metadata of entries.
+    pub fn is_encrypting_metadata(&self) -> bool {
+        self.encrypt_metadata
+    }
+
+    /// Returns the key to sign the serialized file, if it is set.
+    pub fn signing_key(&self) -> Option<&SigningKey> {
+        self.signing_key.as_ref()
+    }
+
+    /// Returns where to write the signature.
+    pub fn signature_placement_mode(&self) -> SignaturePlacement {
+        self.signature_placement
+    }
+
+    /// Returns the key to verify the signature of the serialized file, if it is set.
+    pub fn verifying_key(&self) -> Option<&VerifyingKey> {
+        self.verifying_key.as_ref()
     }
 }
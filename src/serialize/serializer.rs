@@ -1,15 +1,25 @@
 use crate::{
-    compress::{self, TEMP_COMPRESSED_FILE_PATH},
-    encrypt::{make_encryptor, make_new_key_from_password, make_nonce},
+    binary::CountingWriter,
+    compress::{is_compressible, make_encoder},
+    encrypt::{keyslot::make_data_key, EncryptWriter, Transcript},
+    sign::{detached_signature_path, make_signature},
 };
 
 use super::{
-    get_file_list, header::Header, meta::MetaData, option::SerializeOption, BUFFER_LENGTH,
+    entry::EntryInfo,
+    get_file_list,
+    header::Header,
+    index::Trailer,
+    is_preserved_symlink, make_key_slots,
+    meta::{Compression, MetaData},
+    option::{EntryCompression, SerializeOption, SignaturePlacement},
+    BUFFER_LENGTH,
 };
 
 use std::{
-    fs::{self, File, OpenOptions},
-    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
     path::{Path, PathBuf},
     sync::mpsc::Sender,
 };
@@ -34,15 +44,24 @@ use std::{
 /// serializer.serialize().unwrap();
 /// assert!(result.is_file());
 /// ```
-
 pub struct Serializer {
+    root: PathBuf,
     parent: PathBuf,
     original_file_list: Vec<PathBuf>,
+    hardlinks: HashMap<(u64, u64), LinkedFile>,
     result: BufWriter<File>,
+    result_path: PathBuf,
     option: SerializeOption,
     sender: Option<Sender<String>>,
 }
 
+/// The first serialized file among hard links to the same file, which later ones are linked to.
+struct LinkedFile {
+    path: PathBuf,
+    size: u64,
+    checksum: Option<Vec<u8>>,
+}
+
 impl Serializer {
     /// Set original root directory and result path and create Serializer.
     /// May create result file.
@@ -59,23 +78,19 @@ impl Serializer {
                 }
             }
         }
-        File::create(&result_path)?;
-        let original_file_list: Vec<PathBuf>;
-        // if original root is file, add it to file list only.
-        if original_root.as_ref().is_file() {
-            original_file_list = vec![original_root.as_ref().to_path_buf()];
-        } else {
-            original_file_list = get_file_list(&original_root)?;
+        if !original_root.as_ref().exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "Original root doesn't exist!",
+            ));
         }
         Ok(Serializer {
+            root: original_root.as_ref().to_path_buf(),
             parent: original_root.as_ref().parent().unwrap().to_path_buf(),
-            original_file_list: original_file_list,
-            result: BufWriter::new(
-                OpenOptions::new()
-                    .append(true)
-                    .write(true)
-                    .open(result_path)?,
-            ),
+            original_file_list: Vec::new(),
+            hardlinks: HashMap::new(),
+            result: BufWriter::new(File::create(&result_path)?),
+            result_path,
             option: SerializeOption::default(),
             sender: None,
         })
@@ -96,24 +111,140 @@ impl Serializer {
 
     /// Serialize root directory and copy it to result file.
     ///
-    /// If `option.compress` is true, compress result file.
+    /// If `option.compress` is true, compress result file with the codec of the option.
+    /// If the solid block size of the option is set, the entries are compressed together in solid blocks.
     ///
     /// If `option.encrypt` is true, encrypt result file.
+    /// The data key is random and wrapped in a key slot for every password and every recipient of the option.
+    ///
+    /// Files in the root directory are found at this time, filtered by the include and exclude patterns of the option.
+    ///
+    /// If `option.preserve_metadata` is true, permissions, ownership and timestamps of every entry are serialized.
+    ///
+    /// After all entries, an index of the entries and a trailer pointing at it are written.
+    ///
+    /// If `option.encrypt_metadata` is also true, the metadata of every entry and the number of entries are encrypted.
+    ///
+    /// If the signing key of the option is set, the whole file is signed at last,
+    /// and the signature is embedded at the end of the file or written to a detached file.
     pub fn serialize(&mut self) -> io::Result<()> {
-        let header = Header::with(
+        if self.option.is_encrypting_metadata() && !self.option.is_encrypted() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Metadata can be encrypted only with encryption!",
+            ));
+        }
+        if self.option.is_encrypted() && !self.option.has_encryption_credentials() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "There is no credential or recipient to encrypt with!",
+            ));
+        }
+        if self.option.is_compressed() {
+            self.option.compression_codec().verify_level()?;
+        }
+        self.hardlinks.clear();
+        // if original root is file, add it to file list only.
+        self.original_file_list =
+            if self.root.is_file() || is_preserved_symlink(&self.root, &self.option) {
+                vec![self.root.clone()]
+            } else {
+                get_file_list(&self.root, &self.option)?
+            };
+        // If the metadata is encrypted, the file count is hidden from the header.
+        let file_count = match self.option.is_encrypting_metadata() {
+            true => 0,
+            false => self.original_file_list.len() as u64,
+        };
+        let mut header = Header::with(
             self.option.is_encrypted(),
             self.option.is_compressed(),
-            self.original_file_list.len() as u64,
+            file_count,
         );
-        self.result.write(&header.to_binary_vec())?;
+        header.set_indexed(true);
+        header.set_hidden_files(self.option.hidden_files_mode());
+        header.set_extended_metadata(self.option.is_preserving_metadata());
+        header.set_metadata_encrypted(self.option.is_encrypting_metadata());
+        header.set_key_slots(self.option.is_encrypted());
+        header.set_cipher(self.option.cipher_suite());
+        header.set_codec(self.option.compression_codec());
+        let header_binary = header.to_binary_vec();
+        self.result.write_all(&header_binary)?;
         match self.option.is_encrypted() {
-            true => self.serialize_with_encrypt(&self.option.password().unwrap())?,
+            true => self.serialize_with_encrypt(&header_binary)?,
             false => self.serialize_raw()?,
+        };
+        if self.option.signing_key().is_some() {
+            self.write_signature()?;
         }
         self.send_progress("All serialization complete");
         Ok(())
     }
 
+    /// Make the metadata of the entry, whose path is relative to the parent of the root.
+    ///
+    /// If the file shares its device and inode with a file serialized before, it is made as a hard link to that file.
+    /// If the option is set to compress, whether to compress the entry is decided and recorded here.
+    fn read_metadata<T: AsRef<Path>>(&mut self, path: T) -> io::Result<MetaData> {
+        let path = path.as_ref();
+        let mut hardlink_id = None;
+        let mut metadata = if is_preserved_symlink(path, &self.option) {
+            MetaData::from_symlink(path)?
+        } else if path.is_dir() {
+            MetaData::from_dir(path)
+        } else {
+            hardlink_id = get_hardlink_id(path)?;
+            match hardlink_id.and_then(|id| self.hardlinks.get(&id)) {
+                Some(target) => MetaData::from_hardlink(
+                    path,
+                    target.path.clone(),
+                    target.size,
+                    target.checksum.clone(),
+                ),
+                None => MetaData::from(&path),
+            }
+        };
+        if self.option.is_preserving_metadata() {
+            metadata.read_extended(path)?;
+        }
+        if self.option.is_compressed() && metadata.has_data() {
+            let is_compressed = match self.option.entry_compression_mode() {
+                EntryCompression::All => true,
+                EntryCompression::SkipIncompressible => {
+                    is_compressible(path, self.option.compression_codec())?
+                }
+            };
+            metadata.set_compression(match is_compressed {
+                true => Compression::Compressed,
+                false => Compression::Stored,
+            });
+        }
+        metadata.strip_prefix(&self.parent);
+        if let (Some(id), true) = (hardlink_id, metadata.is_file()) {
+            let linked_file = LinkedFile {
+                path: metadata.path().clone(),
+                size: metadata.size(),
+                checksum: metadata.checksum().clone(),
+            };
+            self.hardlinks.insert(id, linked_file);
+        }
+        Ok(metadata)
+    }
+
+    /// Make the metadata of all entries in order.
+    ///
+    /// If the option is set to compress in solid blocks, the entries to compress are grouped into the blocks here.
+    fn read_entries(&mut self) -> io::Result<Vec<MetaData>> {
+        let mut entries = Vec::with_capacity(self.original_file_list.len());
+        for original_file in self.original_file_list.clone() {
+            entries.push(self.read_metadata(&original_file)?);
+        }
+        if let Some(block_size) = self.option.solid_block_size() {
+            make_solid_blocks(&mut entries, block_size);
+        }
+        Ok(entries)
+    }
+
     fn send_progress(&self, message: &str) {
         if let Some(ref tx) = self.sender {
             tx.send(message.to_string()).unwrap();
@@ -121,28 +252,48 @@ impl Serializer {
     }
 
     fn serialize_raw(&mut self) -> io::Result<()> {
-        for i in 0..self.original_file_list.len() {
+        let entries = self.read_entries()?;
+        let mut index = Vec::with_capacity(entries.len());
+        let mut solid_block = Vec::new();
+        for (i, metadata) in entries.into_iter().enumerate() {
             // Write metadata.
-            let mut metadata = MetaData::from(&self.original_file_list[i]);
-            metadata.strip_prefix(&self.parent);
-            self.result.write(&metadata.serialize())?;
+            let offset = self.result.stream_position()?;
+            let original_file = self.original_file_list[i].clone();
+            self.result.write_all(&metadata.serialize())?;
+            let has_data = metadata.has_data();
+            let size = metadata.size();
+            let compression = metadata.compression();
+            index.push(EntryInfo::new(offset, metadata, None));
+            if !has_data {
+                self.send_progress(&format!(
+                    "Serialization complete: {}",
+                    self.original_file_list[i].to_str().unwrap()
+                ));
+                continue;
+            }
 
             // Write binary data.
-            let original_file = self.original_file_list[i].clone();
-            match self.option.is_compressed() {
-                true => {
-                    let compressed_file =
-                        compress::compress(original_file, TEMP_COMPRESSED_FILE_PATH)?;
-                    self.result
-                        .write(&compressed_file.metadata()?.len().to_le_bytes().to_vec())?;
-                    self.write_raw_data(&compressed_file)?;
-                    fs::remove_file(compressed_file)?;
+            match compression {
+                Some(Compression::Compressed) => {
+                    self.write_compressed_data(&[(original_file, size)], None, &[])?;
                     self.send_progress(&format!(
                         "Serialization and compression complete: {}",
                         self.original_file_list[i].to_str().unwrap()
                     ))
                 }
-                false => {
+                Some(Compression::Solid { is_last, .. }) => {
+                    // The block is written after the metadata of its last entry.
+                    solid_block.push((original_file, size));
+                    if is_last {
+                        self.write_compressed_data(&solid_block, None, &[])?;
+                        solid_block.clear();
+                    }
+                    self.send_progress(&format!(
+                        "Serialization and compression complete: {}",
+                        self.original_file_list[i].to_str().unwrap()
+                    ))
+                }
+                _ => {
                     self.write_raw_data(&original_file)?;
                     self.send_progress(&format!(
                         "Serialization complete: {}",
@@ -151,40 +302,79 @@ impl Serializer {
                 }
             }
         }
-        if PathBuf::from(TEMP_COMPRESSED_FILE_PATH).is_dir() {
-            fs::remove_dir_all(TEMP_COMPRESSED_FILE_PATH)?;
-        }
         self.result.flush()?;
-        Ok(())
+        self.write_index(&index, None)
     }
 
-    fn serialize_with_encrypt(&mut self, password: &str) -> io::Result<()> {
-        let (key, salt) = make_new_key_from_password(password);
-        // Write salt.
-        self.result.write(&salt)?;
-        for i in 0..self.original_file_list.len() {
+    /// Encrypt every entry with a random data key, which is wrapped in the key slots.
+    ///
+    /// The data of every entry is authenticated with the header, its position and its metadata,
+    /// and the end marker after the last entry is authenticated with the metadata of all entries and the key slots.
+    fn serialize_with_encrypt(&mut self, header: &[u8]) -> io::Result<()> {
+        let key = make_data_key();
+        let cipher = self.option.cipher_suite();
+        let mut transcript = Transcript::new(header);
+        let key_slots = make_key_slots(&self.option, &key, cipher)?;
+        self.result.write_all(&key_slots)?;
+        let metadata_key = match self.option.is_encrypting_metadata() {
+            true => {
+                // Write the encrypted file count.
+                let file_count = self.original_file_list.len() as u64;
+                self.result
+                    .write_all(&cipher.seal(&key, &file_count.to_le_bytes()))?;
+                Some(&*key)
+            }
+            false => None,
+        };
+        let entries = self.read_entries()?;
+        let mut index = Vec::with_capacity(entries.len());
+        let mut solid_block = Vec::new();
+        for (i, metadata) in entries.into_iter().enumerate() {
             // Write metadata.
-            let mut metadata = MetaData::from(&self.original_file_list[i]);
-            metadata.strip_prefix(&self.parent);
-            self.result.write(&metadata.serialize())?;
+            let offset = self.result.stream_position()?;
+            let original_file = self.original_file_list[i].clone();
+            match metadata_key {
+                Some(key) => self
+                    .result
+                    .write_all(&metadata.serialize_sealed(key, cipher))?,
+                None => self.result.write_all(&metadata.serialize())?,
+            }
+            let aad = transcript.next_entry(&metadata.serialize());
+            let has_data = metadata.has_data();
+            let size = metadata.size();
+            let compression = metadata.compression();
+            index.push(EntryInfo::new(offset, metadata, None));
+            if !has_data {
+                self.send_progress(&format!(
+                    "Serialization complete: {}",
+                    self.original_file_list[i].to_str().unwrap()
+                ));
+                continue;
+            }
 
             // Write binary data.
-            let original_file = self.original_file_list[i].clone();
-            match self.option.is_compressed() {
-                true => {
-                    let compressed_file =
-                        compress::compress(original_file, TEMP_COMPRESSED_FILE_PATH)?;
-                    self.result
-                        .write(&compressed_file.metadata()?.len().to_le_bytes().to_vec())?;
-                    self.write_encrypt_data(&compressed_file, &key)?;
-                    fs::remove_file(compressed_file)?;
+            match compression {
+                Some(Compression::Compressed) => {
+                    self.write_compressed_data(&[(original_file, size)], Some(&key), &aad)?;
+                    self.send_progress(&format!(
+                        "Serialization and compression complete: {}",
+                        self.original_file_list[i].to_str().unwrap()
+                    ))
+                }
+                Some(Compression::Solid { is_last, .. }) => {
+                    // The block is written after the metadata of its last entry, and authenticated with it.
+                    solid_block.push((original_file, size));
+                    if is_last {
+                        self.write_compressed_data(&solid_block, Some(&key), &aad)?;
+                        solid_block.clear();
+                    }
                     self.send_progress(&format!(
                         "Serialization and compression complete: {}",
                         self.original_file_list[i].to_str().unwrap()
                     ))
                 }
-                false => {
-                    self.write_encrypt_data(&original_file, &key)?;
+                _ => {
+                    self.write_encrypt_data(&original_file, &key, &aad)?;
                     self.send_progress(&format!(
                         "Serialization complete: {}",
                         self.original_file_list[i].to_str().unwrap()
@@ -192,20 +382,58 @@ impl Serializer {
                 }
             }
         }
-        if PathBuf::from(TEMP_COMPRESSED_FILE_PATH).is_dir() {
-            fs::remove_dir_all(TEMP_COMPRESSED_FILE_PATH)?;
+        // Write the end marker.
+        let file_count = self.original_file_list.len() as u64;
+        self.result.write_all(&cipher.seal_with_aad(
+            &key,
+            &file_count.to_le_bytes(),
+            &transcript.end_aad(&key_slots),
+        ))?;
+        self.result.flush()?;
+        self.write_index(&index, metadata_key)
+    }
+
+    /// Write the index of all entries and the trailer which points at it.
+    ///
+    /// If the key of the metadata is given, the metadata is encrypted with the cipher of the option
+    /// and the entry count of the trailer is hidden.
+    fn write_index(&mut self, index: &[EntryInfo], metadata_key: Option<&[u8]>) -> io::Result<()> {
+        let index_offset = self.result.stream_position()?;
+        for entry in index {
+            self.result
+                .write_all(&entry.serialize(metadata_key, self.option.cipher_suite()))?;
         }
+        let entry_count = match metadata_key {
+            Some(_) => 0,
+            None => index.len() as u64,
+        };
+        self.result
+            .write_all(&Trailer::new(index_offset, entry_count).to_binary_vec())?;
         self.result.flush()?;
         Ok(())
     }
 
+    /// Sign the whole serialized file, and write the signature where the option is set to.
+    fn write_signature(&mut self) -> io::Result<()> {
+        let key = self.option.signing_key().unwrap();
+        let length = self.result.stream_position()?;
+        let signature = make_signature(File::open(&self.result_path)?, length, key)?;
+        match self.option.signature_placement_mode() {
+            SignaturePlacement::Embedded => self.result.write_all(&signature)?,
+            SignaturePlacement::Detached => {
+                fs::write(detached_signature_path(&self.result_path), signature)?
+            }
+        }
+        self.result.flush()
+    }
+
     fn write_raw_data<T: AsRef<Path>>(&mut self, original_file: T) -> io::Result<()> {
         let mut buffer_reader = BufReader::new(File::open(original_file)?);
         loop {
             let length = {
                 let buffer = buffer_reader.fill_buf()?;
 
-                self.result.write(buffer)?;
+                self.result.write_all(buffer)?;
                 buffer.len()
             };
             if length == 0 {
@@ -221,54 +449,148 @@ impl Serializer {
         &mut self,
         original_file: T,
         key: &[u8],
+        aad: &[u8],
     ) -> io::Result<()> {
         let mut buffer_reader = BufReader::with_capacity(BUFFER_LENGTH, File::open(original_file)?);
-        let nonce = make_nonce();
-        let mut encryptor = make_encryptor(key, &nonce);
-
         // Every time the encryption begins, create another random nonce.
-        self.result.write(&nonce)?;
+        let mut encryptor =
+            EncryptWriter::new(&mut self.result, self.option.cipher_suite(), key, aad)?;
+        io::copy(&mut buffer_reader, &mut encryptor)?;
+        encryptor.finish()?;
+        self.result.flush()?;
+        Ok(())
+    }
 
-        let mut buffer = [0u8; BUFFER_LENGTH];
-        loop {
-            let length = buffer_reader.read(&mut buffer)?;
-            if length == BUFFER_LENGTH {
-                let encrypted_data = match encryptor.encrypt_next(buffer.as_slice()) {
-                    Ok(c) => c,
-                    Err(_) => {
-                        return Err(io::Error::new(
-                            io::ErrorKind::InvalidData,
-                            "Cannot encrypt data!",
-                        ))
-                    }
-                };
-                self.result.write(&encrypted_data)?;
-            } else {
-                let encrypted_data = match encryptor.encrypt_last(&buffer[..length]) {
-                    Ok(c) => c,
-                    Err(_) => {
-                        return Err(io::Error::new(
-                            io::ErrorKind::InvalidData,
-                            "Cannot encrypt data!",
-                        ))
-                    }
-                };
-                self.result.write(&encrypted_data)?;
-                break;
+    /// Compress the files with their sizes into the result, with the length of the compressed data in 8 bytes (little endian) in front.
+    ///
+    /// The files are compressed as one stream, which is a solid block if there are several of them.
+    /// The compressed data is streamed into the result, and encrypted if the key is given.
+    /// Its length is written back in front of it when the compression is finished.
+    fn write_compressed_data(
+        &mut self,
+        original_files: &[(PathBuf, u64)],
+        key: Option<&[u8]>,
+        aad: &[u8],
+    ) -> io::Result<()> {
+        let codec = self.option.compression_codec();
+        let length_offset = self.result.stream_position()?;
+        self.result.write_all(&0u64.to_le_bytes())?;
+        let compressed_size = match key {
+            Some(key) => {
+                let encryptor =
+                    EncryptWriter::new(&mut self.result, self.option.cipher_suite(), key, aad)?;
+                let mut encoder = make_encoder(codec, CountingWriter::new(encryptor))?;
+                copy_files(original_files, &mut encoder)?;
+                let counter = encoder.finish()?;
+                let compressed_size = counter.count();
+                counter.into_inner().finish()?;
+                compressed_size
             }
+            None => {
+                let mut encoder = make_encoder(codec, CountingWriter::new(&mut self.result))?;
+                copy_files(original_files, &mut encoder)?;
+                encoder.finish()?.count()
+            }
+        };
+        let end_offset = self.result.stream_position()?;
+        self.result.seek(SeekFrom::Start(length_offset))?;
+        self.result.write_all(&compressed_size.to_le_bytes())?;
+        self.result.seek(SeekFrom::Start(end_offset))?;
+        self.result.flush()
+    }
+}
+
+/// Group the entries to compress into solid blocks, each of which holds up to `block_size` bytes of their data.
+///
+/// An entry larger than the block size fills a block by itself.
+fn make_solid_blocks(entries: &mut [MetaData], block_size: u64) {
+    let mut block_length: u64 = 0;
+    let mut last = None;
+    for i in 0..entries.len() {
+        if entries[i].compression() != Some(Compression::Compressed) {
+            continue;
         }
-        self.result.flush()?;
-        Ok(())
+        let size = entries[i].size();
+        if let Some(last) = last {
+            if block_length > 0 && block_length.saturating_add(size) > block_size {
+                end_solid_block(&mut entries[last]);
+                block_length = 0;
+            }
+        }
+        entries[i].set_compression(Compression::Solid {
+            offset: block_length,
+            is_last: false,
+        });
+        block_length += size;
+        last = Some(i);
+    }
+    if let Some(last) = last {
+        end_solid_block(&mut entries[last]);
+    }
+}
+
+/// Mark the entry as the last entry of its solid block.
+fn end_solid_block(metadata: &mut MetaData) {
+    if let Some(Compression::Solid { offset, .. }) = metadata.compression() {
+        metadata.set_compression(Compression::Solid {
+            offset,
+            is_last: true,
+        });
+    }
+}
+
+/// Copy the files in order into the writer, each of which must have the given size.
+///
+/// The entries of a solid block are split by their sizes, so a file changed while serializing is an error.
+fn copy_files<W: Write>(files: &[(PathBuf, u64)], writer: &mut W) -> io::Result<()> {
+    for (path, size) in files {
+        let reader = BufReader::with_capacity(BUFFER_LENGTH, File::open(path)?);
+        if io::copy(&mut reader.take(*size), writer)? != *size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "The file {} was changed while serializing.",
+                    path.to_str().unwrap()
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Returns the device and inode numbers of the file, if it has other hard links.
+#[cfg_attr(not(unix), allow(unused_variables))]
+fn get_hardlink_id<T: AsRef<Path>>(path: T) -> io::Result<Option<(u64, u64)>> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        let metadata = fs::metadata(path)?;
+        Ok(match metadata.nlink() > 1 {
+            true => Some((metadata.dev(), metadata.ino())),
+            false => None,
+        })
+    }
+    #[cfg(not(unix))]
+    {
+        Ok(None)
     }
 }
 
 #[cfg(test)]
 mod tests {
 
-    use crate::serialize::option::SerializeOption;
+    use crate::{
+        list_entries,
+        serialize::{
+            header::FILE_LABEL,
+            option::{HiddenFiles, SerializeOption},
+        },
+        Deserializer,
+    };
 
     use super::Serializer;
-    use std::{fs, path::PathBuf, thread};
+    use std::{fs, io, path::PathBuf, thread};
 
     #[test]
     fn serialize_file_test() {
@@ -339,6 +661,148 @@ mod tests {
         }
     }
 
+    #[test]
+    fn serialize_with_filter_test() {
+        let original = PathBuf::from("tests");
+        let result = PathBuf::from("serialize_with_filter_test.bin");
+        let mut serializer = Serializer::new(original, result.clone()).unwrap();
+        serializer.set_option(
+            SerializeOption::new()
+                .to_include("*.jpg")
+                .to_exclude("tests/original_images/dir2")
+                .to_exclude("폭발.jpg"),
+        );
+        serializer.serialize().unwrap();
+        let mut entries: Vec<PathBuf> = list_entries(&result)
+            .unwrap()
+            .iter()
+            .map(|e| e.path().clone())
+            .collect();
+        entries.sort();
+        assert_eq!(
+            entries,
+            [
+                "tests/original_images/dir1/board-g43968feec_1920.jpg",
+                "tests/original_images/dir1/laboratory-g8f9267f5f_1920.jpg",
+                "tests/original_images/dir4/dir5/digitization-1755812_1920.jpg",
+            ]
+            .map(PathBuf::from)
+        );
+        // The file count of the header is the number of filtered files.
+        let binary = fs::read(&result).unwrap();
+        assert_eq!(binary[FILE_LABEL.len() + 5..FILE_LABEL.len() + 7], [1, 3]);
+        fs::remove_file(result).unwrap();
+    }
+
+    #[test]
+    fn serialize_with_ignore_files_test() {
+        let original = PathBuf::from("serialize_with_ignore_files_test_dir");
+        for dir in ["build", "sub", ".git"] {
+            fs::create_dir_all(original.join(dir)).unwrap();
+        }
+        fs::write(original.join(".gitignore"), "*.log\nbuild/\n").unwrap();
+        fs::write(original.join("sub/.luslignore"), "secret.txt\n").unwrap();
+        for file in [
+            "a.txt",
+            "a.log",
+            "build/x.txt",
+            "sub/b.txt",
+            "sub/secret.txt",
+            ".git/config",
+        ] {
+            fs::write(original.join(file), file).unwrap();
+        }
+
+        let list_with = |option: SerializeOption| {
+            let result = PathBuf::from("serialize_with_ignore_files_test.bin");
+            let mut serializer = Serializer::new(original.clone(), result.clone()).unwrap();
+            serializer.set_option(option);
+            serializer.serialize().unwrap();
+            let mut entries: Vec<PathBuf> = list_entries(&result)
+                .unwrap()
+                .iter()
+                .map(|e| e.path().strip_prefix(&original).unwrap().to_path_buf())
+                .collect();
+            entries.sort();
+            fs::remove_file(result).unwrap();
+            entries
+        };
+        assert_eq!(
+            list_with(SerializeOption::new().to_respect_ignore_files(true)),
+            ["a.txt", "sub/b.txt"].map(PathBuf::from)
+        );
+        assert_eq!(list_with(SerializeOption::default()).len(), 6);
+        fs::remove_dir_all(original).unwrap();
+    }
+
+    #[test]
+    fn serialize_hidden_files_test() {
+        let original = PathBuf::from("serialize_hidden_files_test_dir");
+        for dir in [".github", "dir"] {
+            fs::create_dir_all(original.join(dir)).unwrap();
+        }
+        for file in [
+            ".env.example",
+            ".github/workflow.yml",
+            "a.txt",
+            "dir/.hidden",
+        ] {
+            fs::write(original.join(file), file).unwrap();
+        }
+
+        // A directory whose hidden files are skipped is serialized as an empty directory.
+        for (hidden_files, expected) in [
+            (HiddenFiles::Include, 4),
+            (HiddenFiles::SkipFiles, 3),
+            (HiddenFiles::SkipAll, 2),
+        ] {
+            let result = PathBuf::from("serialize_hidden_files_test.bin");
+            let mut serializer = Serializer::new(original.clone(), result.clone()).unwrap();
+            serializer.set_option(SerializeOption::new().to_hidden_files(hidden_files));
+            serializer.serialize().unwrap();
+            assert_eq!(list_entries(&result).unwrap().len(), expected);
+            let mut deserializer = Deserializer::new(result.clone(), PathBuf::new()).unwrap();
+            assert_eq!(deserializer.hidden_files().unwrap(), hidden_files);
+
+            // A file older than 2.2.0 must not have a hidden files flag.
+            if hidden_files != HiddenFiles::SkipFiles {
+                let mut binary = fs::read(&result).unwrap();
+                binary[FILE_LABEL.len() + 2] = 1;
+                binary[FILE_LABEL.len() + 4] &= !0x20;
+                fs::write(&result, binary).unwrap();
+                assert_eq!(
+                    deserializer.hidden_files().unwrap_err().kind(),
+                    io::ErrorKind::InvalidData
+                );
+            }
+            fs::remove_file(result).unwrap();
+        }
+        fs::remove_dir_all(original).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn serialize_symlink_loop_test() {
+        use std::os::unix::fs::symlink;
+
+        let original = PathBuf::from("serialize_symlink_loop_test_dir");
+        fs::create_dir_all(original.join("dir")).unwrap();
+        fs::write(original.join("dir/a.txt"), "lusl").unwrap();
+        symlink(".", original.join("dir/loop")).unwrap();
+        symlink("..", original.join("dir/parent")).unwrap();
+
+        let result = PathBuf::from("serialize_symlink_loop_test.bin");
+        let mut serializer = Serializer::new(original.clone(), result.clone()).unwrap();
+        serializer.set_option(SerializeOption::new().to_follow_symlinks(true));
+        serializer.serialize().unwrap();
+        let entries = list_entries(&result).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert!(entries[0].path().ends_with("dir/a.txt"));
+
+        fs::remove_file(result).unwrap();
+        fs::remove_dir_all(original).unwrap();
+    }
+
     #[test]
     fn serialize_sender_test() {
         let (tx, rx) = std::sync::mpsc::channel();
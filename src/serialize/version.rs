@@ -46,7 +46,7 @@ pub struct Version {
 
 impl Version {
     /// Create a new version.
-    pub fn new(major: u8, minor: u8, patch: u8) -> Self {
+    pub const fn new(major: u8, minor: u8, patch: u8) -> Self {
         Version {
             major,
             minor,
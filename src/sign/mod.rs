@@ -0,0 +1,265 @@
+//! Signature module.
+//!
+//! A serialized file is signed with an Ed25519 key, to prove who produced it.
+//! The signed message is the label `LUSL signature` followed by the SHA-256 hash of the file.
+//!
+//! The signature is stored in a signature block of 72 bytes,
+//! which is the signature of 64 bytes followed by the label `LUSL SIG`.
+//! The block is either appended to the end of the file, which it signs except itself,
+//! or written to a detached file next to it, with the `.sig` extension appended.
+//!
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use chacha20poly1305::aead::{rand_core::RngCore, OsRng};
+use ed25519_dalek::{Signature, Signer, Verifier};
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+use crate::{
+    read_version,
+    serialize::{header::SIGNATURE_MIN_VERSION, option::SignaturePlacement},
+};
+
+pub const SIGNING_KEY_LENGTH: usize = 32;
+pub const VERIFYING_KEY_LENGTH: usize = 32;
+pub const SIGNATURE_LENGTH: usize = 64;
+pub const SIGNATURE_BLOCK_LENGTH: usize = SIGNATURE_LENGTH + SIGNATURE_LABEL.len();
+const SIGNATURE_LABEL: &[u8] = b"LUSL SIG";
+const SIGNED_MESSAGE_LABEL: &[u8] = b"LUSL signature";
+
+/// The private key of an Ed25519 key pair, which signs serialized files.
+///
+/// # Examples
+/// ```
+/// use lusl::{SigningKey, VerifyingKey};
+/// let signing_key = SigningKey::generate();
+/// let verifying_key = signing_key.verifying_key();
+/// assert_eq!(VerifyingKey::from_bytes(verifying_key.to_bytes()).unwrap(), verifying_key);
+/// ```
+#[derive(Clone)]
+pub struct SigningKey(ed25519_dalek::SigningKey);
+
+impl SigningKey {
+    /// Generate a new random signing key.
+    pub fn generate() -> Self {
+        let mut bytes = Zeroizing::new([0u8; SIGNING_KEY_LENGTH]);
+        OsRng.fill_bytes(bytes.as_mut());
+        SigningKey(ed25519_dalek::SigningKey::from_bytes(&bytes))
+    }
+
+    /// Make a signing key from the bytes of an Ed25519 private key.
+    pub fn from_bytes(bytes: [u8; SIGNING_KEY_LENGTH]) -> Self {
+        SigningKey(ed25519_dalek::SigningKey::from_bytes(&bytes))
+    }
+
+    /// Returns the bytes of the private key.
+    pub fn to_bytes(&self) -> [u8; SIGNING_KEY_LENGTH] {
+        self.0.to_bytes()
+    }
+
+    /// Returns the public key to verify the files signed by this key.
+    pub fn verifying_key(&self) -> VerifyingKey {
+        VerifyingKey(self.0.verifying_key())
+    }
+}
+
+/// The public key of an Ed25519 key pair, which verifies signed files.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VerifyingKey(ed25519_dalek::VerifyingKey);
+
+impl VerifyingKey {
+    /// Make a verifying key from the bytes of an Ed25519 public key.
+    ///
+    /// # Errors
+    /// The bytes are not a valid Ed25519 public key.
+    pub fn from_bytes(bytes: [u8; VERIFYING_KEY_LENGTH]) -> io::Result<Self> {
+        match ed25519_dalek::VerifyingKey::from_bytes(&bytes) {
+            Ok(key) => Ok(VerifyingKey(key)),
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Invalid Ed25519 public key.",
+            )),
+        }
+    }
+
+    /// Returns the bytes of the public key.
+    pub fn to_bytes(&self) -> [u8; VERIFYING_KEY_LENGTH] {
+        self.0.to_bytes()
+    }
+}
+
+/// Returns the path of the detached signature of the file.
+pub fn detached_signature_path<T: AsRef<Path>>(filepath: T) -> PathBuf {
+    let mut path = filepath.as_ref().as_os_str().to_owned();
+    path.push(".sig");
+    PathBuf::from(path)
+}
+
+/// Make the signature block which signs the first `length` bytes of the reader.
+pub fn make_signature<R: Read>(reader: R, length: u64, key: &SigningKey) -> io::Result<Vec<u8>> {
+    let message = make_signed_message(reader, length)?;
+    let mut block = key.0.sign(&message).to_bytes().to_vec();
+    block.extend_from_slice(SIGNATURE_LABEL);
+    Ok(block)
+}
+
+/// Returns true if the file has an embedded signature or a detached signature next to it.
+pub fn is_signed<T: AsRef<Path>>(filepath: T) -> io::Result<bool> {
+    let filepath = filepath.as_ref();
+    if detached_signature_path(filepath).is_file() {
+        return Ok(true);
+    }
+    Ok(read_version(filepath)? >= SIGNATURE_MIN_VERSION
+        && read_embedded_signature(&mut File::open(filepath)?)?.is_some())
+}
+
+/// Sign the whole file, and write the signature where the placement is set to.
+pub fn sign_file<T: AsRef<Path>>(
+    filepath: T,
+    key: &SigningKey,
+    placement: SignaturePlacement,
+) -> io::Result<()> {
+    let filepath = filepath.as_ref();
+    let mut file = OpenOptions::new().read(true).append(true).open(filepath)?;
+    let length = file.seek(SeekFrom::End(0))?;
+    file.seek(SeekFrom::Start(0))?;
+    let signature = make_signature(&mut file, length, key)?;
+    match placement {
+        SignaturePlacement::Embedded => file.write_all(&signature),
+        SignaturePlacement::Detached => fs::write(detached_signature_path(filepath), signature),
+    }
+}
+
+/// Returns the length of the file without the signature block embedded at its end.
+///
+/// If there is no embedded signature, the length of the whole file is returned.
+pub fn signed_length<R: Read + Seek>(reader: &mut R) -> io::Result<u64> {
+    Ok(match read_embedded_signature(reader)? {
+        Some((length, _)) => length,
+        None => reader.seek(SeekFrom::End(0))?,
+    })
+}
+
+/// Verify the signature of the serialized file with the verifying key.
+///
+/// The signature embedded at the end of the file is verified if there is,
+/// otherwise the detached signature next to the file is verified.
+///
+/// # Errors
+/// - The file is not signed.
+/// - The signature is not valid for the file and the key.
+pub fn verify_file<T: AsRef<Path>>(filepath: T, key: &VerifyingKey) -> io::Result<()> {
+    let filepath = filepath.as_ref();
+    let mut file = File::open(filepath)?;
+    let embedded = match read_version(filepath)? >= SIGNATURE_MIN_VERSION {
+        true => read_embedded_signature(&mut file)?,
+        false => None,
+    };
+    let (length, block) = match embedded {
+        Some(embedded) => embedded,
+        None => {
+            let detached = detached_signature_path(filepath);
+            if !detached.is_file() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "The serialized file is not signed.",
+                ));
+            }
+            let block = fs::read(detached)?;
+            (file.seek(SeekFrom::End(0))?, block)
+        }
+    };
+    file.seek(SeekFrom::Start(0))?;
+    verify(file, length, &block, key)
+}
+
+/// Read the signature block at the end of the reader.
+///
+/// Returns the length of the signed data in front of it and the block, if there is.
+fn read_embedded_signature<R: Read + Seek>(reader: &mut R) -> io::Result<Option<(u64, Vec<u8>)>> {
+    let file_length = reader.seek(SeekFrom::End(0))?;
+    if file_length < SIGNATURE_BLOCK_LENGTH as u64 {
+        return Ok(None);
+    }
+    let length = file_length - SIGNATURE_BLOCK_LENGTH as u64;
+    reader.seek(SeekFrom::Start(length))?;
+    let mut block = vec![0u8; SIGNATURE_BLOCK_LENGTH];
+    reader.read_exact(&mut block)?;
+    match block.ends_with(SIGNATURE_LABEL) {
+        true => Ok(Some((length, block))),
+        false => Ok(None),
+    }
+}
+
+/// Verify the signature block signs the first `length` bytes of the reader.
+fn verify<R: Read>(reader: R, length: u64, block: &[u8], key: &VerifyingKey) -> io::Result<()> {
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "The signature of the serialized file is not valid.",
+        )
+    };
+    if block.len() != SIGNATURE_BLOCK_LENGTH || !block.ends_with(SIGNATURE_LABEL) {
+        return Err(invalid());
+    }
+    let signature = Signature::from_slice(&block[..SIGNATURE_LENGTH]).map_err(|_| invalid())?;
+    let message = make_signed_message(reader, length)?;
+    key.0.verify(&message, &signature).map_err(|_| invalid())
+}
+
+/// Make the message to sign, from the hash of the first `length` bytes of the reader.
+fn make_signed_message<R: Read>(reader: R, length: u64) -> io::Result<Vec<u8>> {
+    let mut hasher = Sha256::new();
+    let copied = io::copy(&mut reader.take(length), &mut hasher)?;
+    if copied < length {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "The serialized file ends unexpectedly.",
+        ));
+    }
+    let mut message = SIGNED_MESSAGE_LABEL.to_vec();
+    message.extend_from_slice(&hasher.finalize());
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn signature_test() {
+        let key = SigningKey::generate();
+        let mut signed = b"LUSL serialized data".to_vec();
+        let length = signed.len() as u64;
+        let block = make_signature(signed.as_slice(), length, &key).unwrap();
+        assert_eq!(block.len(), SIGNATURE_BLOCK_LENGTH);
+        verify(signed.as_slice(), length, &block, &key.verifying_key()).unwrap();
+
+        let other = SigningKey::generate().verifying_key();
+        let error = verify(signed.as_slice(), length, &block, &other).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        let error = verify(
+            &b"LUSL tampered data!!"[..],
+            length,
+            &block,
+            &key.verifying_key(),
+        );
+        assert_eq!(error.unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        let mut unsigned = Cursor::new(signed.clone());
+        assert_eq!(signed_length(&mut unsigned).unwrap(), length);
+        signed.extend_from_slice(&block);
+        let mut embedded = Cursor::new(signed);
+        assert_eq!(signed_length(&mut embedded).unwrap(), length);
+        assert_eq!(
+            read_embedded_signature(&mut embedded).unwrap().unwrap().1,
+            block
+        );
+    }
+}